@@ -0,0 +1,419 @@
+use std::{
+    fmt::{self, Display},
+    io::{self, BufRead, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use serde_json::{json, Value};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use config::Loader;
+use db::{
+    choices, images, images_associations,
+    result::{self, context},
+    results,
+    sessions::{self, State},
+    tokens, users, Pool,
+};
+use storage::{import, phash, Storage};
+
+use crate::matching;
+
+pub enum Command {
+    CreateAdmin {
+        email: String,
+        password: String,
+    },
+    ConfirmUser {
+        user: users::Id,
+    },
+    CreateSession {
+        name: String,
+        phase1: Date,
+        phase2: Date,
+        phase3: Date,
+    },
+    ListSessions,
+    AssociateImage {
+        session: sessions::Id,
+        image: images::Id,
+    },
+    RunMatching {
+        session: sessions::Id,
+        /// Runs even though users can still change their choice.
+        force: bool,
+    },
+    PurgeTokens,
+    ImportImages {
+        session: sessions::Id,
+        /// Directory or ZIP archive.
+        path: PathBuf,
+        storage: Storage,
+    },
+    /// Computes the perceptual hashes missing from images stored before they existed.
+    HashImages {
+        storage: Storage,
+    },
+}
+
+/// What a command prints, as text or as JSON with `--json`.
+pub struct Output {
+    pub text: String,
+    pub json: Value,
+}
+
+pub enum Error {
+    Db(result::Error),
+    /// Matching was requested before the last phase of the session.
+    TooEarly(sessions::Id, State),
+    /// The directory or archive to import could not be read.
+    Read(PathBuf, io::Error),
+    /// The directory or archive to import holds too many files, or too large ones.
+    TooLarge(PathBuf, import::Exceeded),
+    Import(import::Error),
+}
+
+/// RFC 3339 date given as an option.
+#[derive(Debug, Clone, Copy)]
+pub struct Date(OffsetDateTime);
+
+impl Command {
+    /// Reads the options of the command `name`, `None` when there is no such command.
+    pub fn parse(name: &str, loader: &mut Loader) -> Option<Self> {
+        let command = match name {
+            "create-admin" => Command::CreateAdmin {
+                email: loader.required::<String>("email").trim().to_lowercase(),
+                password: loader
+                    .optional("password")
+                    .unwrap_or_else(|| prompt("Password: ")),
+            },
+            "confirm-user" => Command::ConfirmUser {
+                user: users::Id(loader.required("user")),
+            },
+            "create-session" => Command::CreateSession {
+                name: loader.required("name"),
+                phase1: loader.required("phase1"),
+                phase2: loader.required("phase2"),
+                phase3: loader.required("phase3"),
+            },
+            "list-sessions" => Command::ListSessions,
+            "associate-image" => Command::AssociateImage {
+                session: sessions::Id(loader.required("session")),
+                image: images::Id(loader.required("image")),
+            },
+            "run-matching" => Command::RunMatching {
+                session: sessions::Id(loader.required("session")),
+                force: loader.get("force", || false),
+            },
+            "purge-tokens" => Command::PurgeTokens,
+            "import-images" => Command::ImportImages {
+                session: sessions::Id(loader.required("session")),
+                path: loader.required("path"),
+                storage: Storage::new(loader.get("storage_path", || PathBuf::from("./images"))),
+            },
+            "hash-images" => Command::HashImages {
+                storage: Storage::new(loader.get("storage_path", || PathBuf::from("./images"))),
+            },
+            _ => return None,
+        };
+
+        Some(command)
+    }
+
+    pub async fn run(self, db: &Pool) -> Result<Output, Error> {
+        match self {
+            Command::CreateAdmin { email, password } => {
+                let mut trans = db.begin().await.map_err(context("begin", "transaction"))?;
+                let id = users::create(&email, &password, &mut trans).await?;
+
+                users::confirm(id, &mut trans).await?;
+                users::set_admin(id, true, &mut trans).await?;
+                trans
+                    .commit()
+                    .await
+                    .map_err(context("commit", "transaction"))?;
+
+                Ok(Output {
+                    text: format!("Created admin {email} with id {}\n", id.0),
+                    json: json!({ "id": id.0, "email": email }),
+                })
+            }
+            Command::ConfirmUser { user } => {
+                users::confirm(user, db).await?;
+
+                Ok(Output {
+                    text: format!("Confirmed user {}\n", user.0),
+                    json: json!({ "id": user.0 }),
+                })
+            }
+            Command::CreateSession {
+                name,
+                phase1,
+                phase2,
+                phase3,
+            } => {
+                let id = sessions::create(&name, phase1.0, phase2.0, phase3.0, db).await?;
+
+                Ok(Output {
+                    text: format!("Created session {name} with id {}\n", id.0),
+                    json: json!({ "id": id.0, "name": name }),
+                })
+            }
+            Command::ListSessions => {
+                let now = OffsetDateTime::now_utc();
+                let sessions = sessions::list(db).await?;
+                let mut text = String::new();
+                let mut list = Vec::new();
+
+                for session in sessions {
+                    let state = session.state(now).as_str();
+                    let [phase1, phase2, phase3] = [session.phase1, session.phase2, session.phase3]
+                        .map(|date| Date(date).to_string());
+
+                    text += &format!("{}\t{state}\t{phase1}\t{phase2}\t{phase3}\n", session.id.0);
+                    list.push(json!({
+                        "id": session.id.0,
+                        "state": state,
+                        "phase1": phase1,
+                        "phase2": phase2,
+                        "phase3": phase3,
+                    }));
+                }
+
+                Ok(Output {
+                    text,
+                    json: Value::Array(list),
+                })
+            }
+            Command::AssociateImage { session, image } => {
+                let id = images_associations::create(image, session, db).await?;
+                let duplicates = images::near_duplicates(
+                    Some(session),
+                    Some(&[image]),
+                    images::NEAR_DUPLICATE_DISTANCE,
+                    db,
+                )
+                .await?;
+                let mut text = format!("Associated image {} with session {}\n", image.0, session.0);
+                let mut warnings = Vec::new();
+
+                for duplicate in duplicates {
+                    let (a, b) = duplicate.images;
+
+                    text += &format!(
+                        "Warning: images {} and {} look alike, their hashes differ by {} bits\n",
+                        a.0, b.0, duplicate.distance
+                    );
+                    warnings.push(json!({ "images": [a.0, b.0], "distance": duplicate.distance }));
+                }
+
+                Ok(Output {
+                    text,
+                    json: json!({
+                        "id": id.0,
+                        "session": session.0,
+                        "image": image.0,
+                        "warnings": warnings,
+                    }),
+                })
+            }
+            Command::RunMatching { session, force } => run_matching(session, force, db).await,
+            Command::PurgeTokens => {
+                let deleted = tokens::purge_expired(db).await?;
+
+                Ok(Output {
+                    text: format!("Deleted {deleted} expired tokens\n"),
+                    json: json!({ "deleted": deleted }),
+                })
+            }
+            Command::ImportImages {
+                session,
+                path,
+                storage,
+            } => import_images(session, path, storage, db).await,
+            Command::HashImages { storage } => hash_images(storage, db).await,
+        }
+    }
+}
+
+/// Replaces the results of the session with new matches, refusing to run before its last phase
+/// unless `force` is set.
+async fn run_matching(session: sessions::Id, force: bool, db: &Pool) -> Result<Output, Error> {
+    let mut trans = db.begin().await.map_err(context("begin", "transaction"))?;
+    let progress = sessions::progress(session, &mut trans)
+        .await?
+        .ok_or(result::Error::InvalidSession)?;
+    let state = progress.session.state(OffsetDateTime::now_utc());
+
+    if state != State::Phase3 && !force {
+        return Err(Error::TooEarly(session, state));
+    }
+
+    let choices = choices::by_session(session, &mut trans).await?;
+    let (matches, unmatched) = matching::matches(choices, &mut rand::thread_rng());
+
+    results::replace(session, &matches, &mut trans).await?;
+    trans
+        .commit()
+        .await
+        .map_err(context("commit", "transaction"))?;
+
+    let users = |m: &results::Match| {
+        [Some(m.users.0), Some(m.users.1), m.users.2]
+            .into_iter()
+            .flatten()
+            .map(|user| user.0)
+            .collect::<Vec<_>>()
+    };
+
+    Ok(Output {
+        text: format!(
+            "Matched {} groups in session {}, {} users left unmatched\n",
+            matches.len(),
+            session.0,
+            unmatched.len()
+        ),
+        json: json!({
+            "session": session.0,
+            "state": state.as_str(),
+            "results": matches
+                .iter()
+                .map(|m| json!({ "image": m.image.0, "users": users(m) }))
+                .collect::<Vec<_>>(),
+            "unmatched": unmatched.iter().map(|user| user.0).collect::<Vec<_>>(),
+        }),
+    })
+}
+
+/// Imports every image of the directory or archive at `path` into the session, or none of them.
+async fn import_images(
+    session: sessions::Id,
+    path: PathBuf,
+    storage: Storage,
+    db: &Pool,
+) -> Result<Output, Error> {
+    let mut reader = import::Reader::new(import::Limits::default());
+
+    if let Err(err) = reader.add_path(&path) {
+        return Err(Error::Read(path, err));
+    }
+
+    let entries = reader
+        .finish()
+        .map_err(|exceeded| Error::TooLarge(path, exceeded))?;
+    let report = import::import(session, None, entries, &storage, db)
+        .await
+        .map_err(|err| match err {
+            import::Error::Db(err) => Error::Db(err),
+            err => Error::Import(err),
+        })?;
+    let mut text = String::new();
+
+    for file in &report.files {
+        match (file.image, &file.error) {
+            (Some(image), _) => text += &format!("{}\timage {image}\n", file.name),
+            (None, error) => {
+                text += &format!("{}\t{}\n", file.name, error.as_deref().unwrap_or_default())
+            }
+        }
+    }
+    text += &format!(
+        "Imported {} images into session {}, rejected {} files\n",
+        report.imported, session.0, report.rejected
+    );
+
+    Ok(Output {
+        text,
+        json: serde_json::to_value(&report).unwrap_or_default(),
+    })
+}
+
+/// Hashes the images without a perceptual hash, skipping those that cannot be read or decoded.
+async fn hash_images(storage: Storage, db: &Pool) -> Result<Output, Error> {
+    let mut hashed = Vec::new();
+    let mut skipped = Vec::new();
+
+    for id in images::without_phash(db).await? {
+        let phash = match tokio::fs::read(storage.path(id)).await {
+            Ok(data) => phash::dhash(&data),
+            Err(_) => None,
+        };
+
+        match phash {
+            Some(phash) => {
+                images::set_phash(id, phash, db).await?;
+                hashed.push(id.0);
+            }
+            None => skipped.push(id.0),
+        }
+    }
+
+    Ok(Output {
+        text: format!(
+            "Hashed {} images, skipped {} that could not be read or decoded\n",
+            hashed.len(),
+            skipped.len()
+        ),
+        json: json!({ "hashed": hashed, "skipped": skipped }),
+    })
+}
+
+/// Reads a line from stdin, so that secrets do not have to be given as flags.
+fn prompt(message: &str) -> String {
+    eprint!("{message}");
+    let _ = std::io::stderr().flush();
+
+    let mut line = String::new();
+    let _ = std::io::stdin().lock().read_line(&mut line);
+
+    line.trim_end_matches(['\r', '\n']).to_string()
+}
+
+impl From<result::Error> for Error {
+    fn from(err: result::Error) -> Self {
+        Error::Db(err)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Db(err) => write!(f, "{err}"),
+            Error::TooEarly(session, state) => write!(
+                f,
+                "session {} is in {}, choices can still change, rerun with --force to match anyway",
+                session.0,
+                state.as_str()
+            ),
+            Error::Read(path, err) => write!(f, "failed to read {}: {err}", path.display()),
+            Error::TooLarge(path, exceeded) => {
+                write!(f, "{} holds {exceeded}", path.display())
+            }
+            Error::Import(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl Default for Date {
+    fn default() -> Self {
+        Self(OffsetDateTime::UNIX_EPOCH)
+    }
+}
+
+impl FromStr for Date {
+    type Err = time::error::Parse;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OffsetDateTime::parse(s, &Rfc3339).map(Date)
+    }
+}
+
+impl Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.format(&Rfc3339) {
+            Ok(date) => write!(f, "{date}"),
+            Err(_) => write!(f, "{:?}", self.0),
+        }
+    }
+}
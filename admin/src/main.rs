@@ -0,0 +1,96 @@
+//! Administrative commands run directly against the database.
+//!
+//! The database is configured like for the other binaries, with `DATABASE_URL`, the `DB_*`
+//! variables, `--db.*` flags or a `--config` file, while the options of the commands are only
+//! read from flags, except for `STORAGE_PATH` which is shared with `image-host`.
+
+mod commands;
+mod matching;
+
+use std::process::exit;
+
+use commands::Command;
+use config::{database, Loader};
+
+const USAGE: &str = "\
+Usage: admin <command> [options] [--json]
+
+Commands:
+  create-admin --email <email> [--password <password>]
+      Creates a confirmed admin, the password is read from stdin when not given
+  confirm-user --user <id>
+  create-session --name <name> --phase1 <date> --phase2 <date> --phase3 <date>
+      Dates are RFC 3339, such as 2024-05-01T12:00:00Z
+  list-sessions
+  associate-image --session <id> --image <id>
+  run-matching --session <id> [--force]
+      Replaces the results of a session, --force runs before its last phase
+  purge-tokens
+      Deletes expired tokens
+  import-images --session <id> --path <path> [--storage-path <path>]
+      Imports every image of a directory or ZIP archive into a session, or none of them,
+      and stores them in the directory served by image-host, ./images by default
+  hash-images [--storage-path <path>]
+      Computes the perceptual hashes of the images stored before they were introduced
+
+Options:
+  --json  Prints the output as JSON, for scripts
+";
+
+#[tokio::main]
+async fn main() {
+    let mut args = std::env::args().skip(1);
+    let name = args.next().unwrap_or_default();
+
+    if matches!(name.as_str(), "" | "help" | "--help") {
+        print!("{USAGE}");
+        exit(if name.is_empty() { 2 } else { 0 });
+    }
+
+    let mut loader = Loader::new(
+        args,
+        |var| {
+            let forwarded = matches!(var, "DATABASE_URL" | "CONFIG_FILE" | "STORAGE_PATH")
+                || var.starts_with("DB_");
+
+            forwarded.then(|| std::env::var(var).ok()).flatten()
+        },
+        |path| std::fs::read_to_string(path).map_err(|e| e.to_string()),
+        database::SECRETS.into_iter().chain(["password"]),
+    );
+    let json = loader.get("json", || false);
+    let command = Command::parse(&name, &mut loader);
+    let options = database::connect_options(&mut loader, "admin");
+
+    let command = match (command, loader.finish()) {
+        (None, _) => {
+            eprint!("Unknown command {name}\n\n{USAGE}");
+            exit(2);
+        }
+        (Some(_), Err(errors)) => {
+            eprint!("Invalid options:\n{errors}");
+            exit(2);
+        }
+        (Some(command), Ok(_)) => command,
+    };
+    let pool = match db::connect(&options).await {
+        Ok(pool) => pool,
+        Err(err) => {
+            eprintln!("Failed to connect to database: {err}");
+            exit(1);
+        }
+    };
+
+    let result = command.run(&pool).await;
+
+    pool.close().await;
+
+    match result {
+        Ok(output) if json => println!("{}", output.json),
+        Ok(output) => print!("{}", output.text),
+        Err(err) => {
+            eprintln!("Error: {err}");
+            exit(1);
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Groups the users of a session who chose the same image.
+
+use std::collections::BTreeMap;
+
+use rand::{seq::SliceRandom, Rng};
+
+use db::{images, results::Match, users};
+
+/// Pairs users at random among those who chose the same image, with a group of three when they
+/// are an odd number. Users alone on their image are returned apart.
+pub fn matches<R: Rng>(
+    choices: Vec<(users::Id, images::Id)>,
+    rng: &mut R,
+) -> (Vec<Match>, Vec<users::Id>) {
+    let mut by_image = BTreeMap::<i32, Vec<users::Id>>::new();
+    let mut matches = Vec::new();
+    let mut unmatched = Vec::new();
+
+    for (user, image) in choices {
+        by_image.entry(image.0).or_default().push(user);
+    }
+
+    for (image, mut users) in by_image {
+        if users.len() < 2 {
+            unmatched.extend(users);
+            continue;
+        }
+
+        users.shuffle(rng);
+
+        let mut rest = &users[..];
+
+        while !rest.is_empty() {
+            let (group, tail) = rest.split_at(if rest.len() == 3 { 3 } else { 2 });
+
+            matches.push(Match {
+                image: images::Id(image),
+                users: (group[0], group[1], group.get(2).copied()),
+            });
+            rest = tail;
+        }
+    }
+
+    (matches, unmatched)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use db::{images, users};
+
+    use super::matches;
+
+    fn choices(images: &[i32]) -> Vec<(users::Id, images::Id)> {
+        images
+            .iter()
+            .enumerate()
+            .map(|(user, image)| (users::Id(user as i32), images::Id(*image)))
+            .collect()
+    }
+
+    #[test]
+    fn groups() {
+        let (matches, unmatched) = matches(
+            choices(&[1, 1, 1, 1, 1, 2, 2, 3]),
+            &mut StdRng::seed_from_u64(0),
+        );
+        let sizes: Vec<_> = matches
+            .iter()
+            .map(|m| (m.image.0, if m.users.2.is_some() { 3 } else { 2 }))
+            .collect();
+
+        assert_eq!(sizes, [(1, 2), (1, 3), (2, 2)]);
+        assert_eq!(unmatched, [users::Id(7)]);
+    }
+
+    #[test]
+    fn everyone_once() {
+        let (matches, _) = matches(choices(&[4; 9]), &mut StdRng::seed_from_u64(1));
+        let mut users: Vec<_> = matches
+            .iter()
+            .flat_map(|m| [Some(m.users.0), Some(m.users.1), m.users.2])
+            .flatten()
+            .map(|user| user.0)
+            .collect();
+
+        users.sort_unstable();
+        assert_eq!(users, (0..9).collect::<Vec<_>>());
+    }
+}
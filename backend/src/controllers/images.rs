@@ -0,0 +1,537 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::instrument;
+use utoipa::{IntoParams, ToSchema};
+
+use server::signing::Keys;
+
+use db::{
+    images::{self, Cursor, Entry, Filter, Image, Metadata, NearDuplicate, Sort},
+    result::context,
+    sessions,
+    tags::{self, Tag},
+    users, Pool,
+};
+
+use crate::{
+    controllers::sessions::session_not_found,
+    extractors::auth::AdminAuth,
+    response::{error, error::FieldErrors, success, EmptyResponse, Response},
+    validation,
+};
+
+const TITLE_MAX_LENGTH: usize = 200;
+const DESCRIPTION_MAX_LENGTH: usize = 5000;
+const ALT_TEXT_MAX_LENGTH: usize = 1000;
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Serialize, ToSchema)]
+pub struct ImageModel {
+    id: i32,
+    title: Option<String>,
+    description: Option<String>,
+    alt_text: Option<String>,
+    tags: Vec<String>,
+    /// Served to anyone by `image-host`, otherwise only to admins and to the participants of the
+    /// sessions using it once started.
+    public: bool,
+}
+
+/// Image of a session, with where to download it.
+#[derive(Serialize, ToSchema)]
+pub struct SessionImageModel {
+    id: i32,
+    title: Option<String>,
+    description: Option<String>,
+    alt_text: Option<String>,
+    tags: Vec<String>,
+    public: bool,
+    /// URL of the image on `image-host`. It is signed when the image is not public and the user
+    /// may see it, letting it be downloaded without a token until the `exp` parameter, a Unix time.
+    url: String,
+}
+
+/// Where `image-host` serves the images, and how the URLs of the images that are not public are
+/// signed, see [`server::signing`].
+#[derive(Clone)]
+pub struct ImageUrls {
+    /// Prepended to the path of the images, without a trailing slash.
+    pub base: String,
+    /// Signed URLs expire between one and two `ttl` after they are handed out, they stay the same
+    /// during `ttl` so that clients keep the images they downloaded.
+    pub ttl: Duration,
+    /// URLs are never signed when `None`.
+    pub keys: Option<Keys>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateModel {
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    alt_text: Option<String>,
+    /// Replaces the tags of the image, missing tags are created.
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Serves the image to anyone, see `ImageModel`.
+    #[serde(default)]
+    public: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TagModel {
+    name: String,
+    /// Number of images with this tag.
+    images: i64,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LibraryQuery {
+    /// Comma-separated tags, images must have every one of them.
+    tags: Option<String>,
+    /// RFC 3339 date, inclusive.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    #[param(value_type = Option<String>, format = DateTime)]
+    uploaded_after: Option<OffsetDateTime>,
+    /// RFC 3339 date, exclusive.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    #[param(value_type = Option<String>, format = DateTime)]
+    uploaded_before: Option<OffsetDateTime>,
+    /// Id of the user who uploaded the images.
+    uploader: Option<i32>,
+    /// Only images associated with no session.
+    #[serde(default)]
+    unused: bool,
+    #[serde(default)]
+    #[param(inline)]
+    sort: SortModel,
+    /// `next_cursor` of the previous page, with the same `sort`.
+    cursor: Option<String>,
+    /// Images per page, 50 by default, at most 200.
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortModel {
+    #[default]
+    Newest,
+    Oldest,
+    /// Case-insensitively, untitled images last.
+    Title,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct LibraryPage {
+    images: Vec<LibraryImageModel>,
+    /// Cursor of the next page, `null` on the last one.
+    next_cursor: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct LibraryImageModel {
+    id: i32,
+    title: Option<String>,
+    description: Option<String>,
+    alt_text: Option<String>,
+    tags: Vec<String>,
+    public: bool,
+    #[serde(with = "time::serde::rfc3339")]
+    uploaded_at: OffsetDateTime,
+    /// `null` when uploaded anonymously or by a deleted user.
+    uploader: Option<i32>,
+    /// Sessions the image is associated with.
+    sessions: Vec<i32>,
+}
+
+/// Content of the opaque cursors, tied to a sort so that they cannot be mixed up.
+#[derive(Serialize, Deserialize)]
+struct CursorModel {
+    sort: SortModel,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    uploaded_at: Option<OffsetDateTime>,
+    title: Option<String>,
+    id: i32,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DuplicatesQuery {
+    /// Only compares the images of this session, otherwise those of the whole library whose hashes
+    /// share their top byte.
+    session: Option<i32>,
+    /// Largest number of differing bits between the 64-bit perceptual hashes, 10 by default.
+    distance: Option<i32>,
+}
+
+/// Two images that look alike, such as the same photo resized or re-encoded.
+#[derive(Serialize, ToSchema)]
+pub struct NearDuplicateModel {
+    /// Ids of the images, lowest first.
+    images: [i32; 2],
+    /// Number of bits by which their perceptual hashes differ, 0 for identical images.
+    distance: i32,
+}
+
+impl From<NearDuplicate> for NearDuplicateModel {
+    fn from(duplicate: NearDuplicate) -> Self {
+        Self {
+            images: [duplicate.images.0 .0, duplicate.images.1 .0],
+            distance: duplicate.distance,
+        }
+    }
+}
+
+impl From<SortModel> for Sort {
+    fn from(sort: SortModel) -> Self {
+        match sort {
+            SortModel::Newest => Sort::Newest,
+            SortModel::Oldest => Sort::Oldest,
+            SortModel::Title => Sort::Title,
+        }
+    }
+}
+
+impl From<Entry> for LibraryImageModel {
+    fn from(entry: Entry) -> Self {
+        let image = ImageModel::from(entry.image);
+
+        Self {
+            id: image.id,
+            title: image.title,
+            description: image.description,
+            alt_text: image.alt_text,
+            tags: image.tags,
+            public: image.public,
+            uploaded_at: entry.uploaded_at,
+            uploader: entry.uploader.map(|uploader| uploader.0),
+            sessions: entry
+                .sessions
+                .into_iter()
+                .map(|session| session.0)
+                .collect(),
+        }
+    }
+}
+
+impl SessionImageModel {
+    pub fn new(image: Image, urls: &ImageUrls, sign: bool) -> Self {
+        let image = ImageModel::from(image);
+        let url = urls.url(images::Id(image.id), sign && !image.public);
+
+        Self {
+            id: image.id,
+            title: image.title,
+            description: image.description,
+            alt_text: image.alt_text,
+            tags: image.tags,
+            public: image.public,
+            url,
+        }
+    }
+}
+
+impl ImageUrls {
+    /// URL of the image, signed when `sign` and keys are configured.
+    pub fn url(&self, id: images::Id, sign: bool) -> String {
+        let path = format!("/{}", id.0);
+
+        match &self.keys {
+            Some(keys) if sign => {
+                let ttl = self.ttl.as_secs().max(1);
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                let expires = UNIX_EPOCH + Duration::from_secs((now / ttl + 2) * ttl);
+
+                format!("{}{path}?{}", self.base, keys.sign(&path, expires))
+            }
+            _ => format!("{}{path}", self.base),
+        }
+    }
+}
+
+fn encode_cursor(sort: SortModel, cursor: Cursor) -> String {
+    let model = match cursor {
+        Cursor::Uploaded(uploaded_at, id) => CursorModel {
+            sort,
+            uploaded_at: Some(uploaded_at),
+            title: None,
+            id: id.0,
+        },
+        Cursor::Title(title, id) => CursorModel {
+            sort,
+            uploaded_at: None,
+            title,
+            id: id.0,
+        },
+    };
+
+    URL_SAFE_NO_PAD.encode(serde_json::to_vec(&model).unwrap_or_default())
+}
+
+/// `None` when the cursor is malformed or was returned for another sort.
+fn decode_cursor(sort: SortModel, cursor: &str) -> Option<Cursor> {
+    let bytes = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+    let model: CursorModel = serde_json::from_slice(&bytes).ok()?;
+    let id = images::Id(model.id);
+
+    match (sort, model.uploaded_at) {
+        _ if model.sort != sort => None,
+        (SortModel::Newest | SortModel::Oldest, Some(uploaded_at)) => {
+            Some(Cursor::Uploaded(uploaded_at, id))
+        }
+        (SortModel::Title, _) => Some(Cursor::Title(model.title, id)),
+        _ => None,
+    }
+}
+
+impl From<Image> for ImageModel {
+    fn from(image: Image) -> Self {
+        Self {
+            id: image.id.0,
+            title: image.metadata.title,
+            description: image.metadata.description,
+            alt_text: image.metadata.alt_text,
+            tags: image.tags,
+            public: image.public,
+        }
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/images/{id}",
+    tag = "images",
+    operation_id = "update_image",
+    params(("id" = i32, Path, description = "Image id")),
+    request_body = UpdateModel,
+    responses(
+        (status = 200, description = "Metadata, tags and visibility replaced"),
+        (status = 400, description = "Unknown image or invalid fields", body = Envelope),
+        (status = 403, description = "Not an admin", body = Envelope),
+    ),
+    security(("bearer" = [])),
+)]
+#[instrument(skip_all)]
+pub async fn update(id: images::Id, data: UpdateModel, db: Pool, _: AdminAuth) -> EmptyResponse {
+    let metadata = Metadata {
+        title: validation::normalize_text(data.title),
+        description: validation::normalize_text(data.description),
+        alt_text: validation::normalize_text(data.alt_text),
+    };
+    let tags = validation::normalize_tags(data.tags);
+    let fields: FieldErrors = [
+        (
+            "title",
+            validation::check_text(metadata.title.as_deref(), TITLE_MAX_LENGTH),
+        ),
+        (
+            "description",
+            validation::check_text(metadata.description.as_deref(), DESCRIPTION_MAX_LENGTH),
+        ),
+        (
+            "alt_text",
+            validation::check_text(metadata.alt_text.as_deref(), ALT_TEXT_MAX_LENGTH),
+        ),
+        ("tags", validation::check_tags(&tags)),
+    ]
+    .into_iter()
+    .filter(|(_, errors)| !errors.is_empty())
+    .collect();
+
+    if !fields.is_empty() {
+        return error()
+            .with_status(error::Code::BadRequest)
+            .body(String::from("Invalid fields"))
+            .fields(fields)
+            .into();
+    }
+
+    let result = async {
+        let mut trans = db.begin().await.map_err(context("begin", "transaction"))?;
+
+        images::update(id, &metadata, &mut trans).await?;
+        images::set_public(id, data.public, &mut trans).await?;
+        tags::set(id, &tags, &mut trans).await?;
+        trans
+            .commit()
+            .await
+            .map_err(context("commit", "transaction"))
+    }
+    .await;
+
+    match result {
+        Ok(()) => success(()).into(),
+        Err(err) => err.into(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/images/tags",
+    tag = "images",
+    operation_id = "list_tags",
+    responses(
+        (status = 200, description = "Tags used by at least one image", body = [TagModel]),
+        (status = 403, description = "Not an admin", body = Envelope),
+    ),
+    security(("bearer" = [])),
+)]
+#[instrument(skip_all)]
+pub async fn tags(db: Pool, _: AdminAuth) -> Response<Vec<TagModel>> {
+    match tags::list(&db).await {
+        Ok(tags) => success(
+            tags.into_iter()
+                .map(|Tag { name, images }| TagModel { name, images })
+                .collect(),
+        )
+        .into(),
+        Err(err) => err.into(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/images",
+    tag = "images",
+    operation_id = "list_images",
+    params(LibraryQuery),
+    responses(
+        (status = 200, description = "A page of the image library", body = LibraryPage),
+        (status = 400, description = "Invalid cursor or limit", body = Envelope),
+        (status = 403, description = "Not an admin", body = Envelope),
+    ),
+    security(("bearer" = [])),
+)]
+#[instrument(skip_all)]
+pub async fn library(query: LibraryQuery, db: Pool, _: AdminAuth) -> Response<LibraryPage> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    let after = query
+        .cursor
+        .as_deref()
+        .map(|cursor| decode_cursor(query.sort, cursor));
+    let mut fields = FieldErrors::new();
+
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        fields.insert(
+            "limit",
+            vec![format!("Must be between 1 and {MAX_PAGE_SIZE}")],
+        );
+    }
+    if let Some(None) = after {
+        fields.insert(
+            "cursor",
+            vec![String::from("Invalid cursor, or returned for another sort")],
+        );
+    }
+    if !fields.is_empty() {
+        return error()
+            .with_status(error::Code::BadRequest)
+            .body(String::from("Invalid fields"))
+            .fields(fields)
+            .into();
+    }
+
+    let filter = Filter {
+        tags: query
+            .tags
+            .map(|tags| validation::normalize_tags(tags.split(',').map(String::from).collect()))
+            .unwrap_or_default(),
+        uploaded_after: query.uploaded_after,
+        uploaded_before: query.uploaded_before,
+        uploader: query.uploader.map(users::Id),
+        unused: query.unused,
+    };
+    let sort = Sort::from(query.sort);
+
+    // One more image than requested tells whether there is a next page.
+    match images::library(&filter, sort, after.flatten().as_ref(), limit + 1, &db).await {
+        Ok(mut entries) => {
+            let next_cursor = (entries.len() as i64 > limit)
+                .then(|| {
+                    entries.truncate(limit as usize);
+                    entries.last()
+                })
+                .flatten()
+                .map(|last| encode_cursor(query.sort, last.cursor(sort)));
+
+            success(LibraryPage {
+                images: entries.into_iter().map(LibraryImageModel::from).collect(),
+                next_cursor,
+            })
+            .into()
+        }
+        Err(err) => err.into(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/images/duplicates",
+    tag = "images",
+    operation_id = "list_near_duplicates",
+    params(DuplicatesQuery),
+    responses(
+        (status = 200, description = "Pairs of near-duplicate images, closest first", body = [NearDuplicateModel]),
+        (status = 400, description = "Invalid distance", body = Envelope),
+        (status = 403, description = "Not an admin", body = Envelope),
+        (status = 404, description = "Unknown session", body = Envelope),
+    ),
+    security(("bearer" = [])),
+)]
+#[instrument(skip_all)]
+pub async fn duplicates(
+    query: DuplicatesQuery,
+    db: Pool,
+    _: AdminAuth,
+) -> Response<Vec<NearDuplicateModel>> {
+    let distance = query.distance.unwrap_or(images::NEAR_DUPLICATE_DISTANCE);
+    let session = query.session.map(sessions::Id);
+
+    if !(0..=64).contains(&distance) {
+        return error()
+            .with_status(error::Code::BadRequest)
+            .body(String::from("Invalid fields"))
+            .fields(FieldErrors::from([(
+                "distance",
+                vec![String::from("Must be between 0 and 64")],
+            )]))
+            .into();
+    }
+
+    let result = async {
+        let mut trans = db.begin().await.map_err(context("begin", "transaction"))?;
+
+        if let Some(session) = session {
+            if sessions::progress(session, &mut trans).await?.is_none() {
+                return Ok(None);
+            }
+        }
+
+        images::near_duplicates(session, None, distance, &mut trans)
+            .await
+            .map(Some)
+    }
+    .await;
+
+    match result {
+        Ok(Some(duplicates)) => success(
+            duplicates
+                .into_iter()
+                .map(NearDuplicateModel::from)
+                .collect(),
+        )
+        .into(),
+        Ok(None) => session_not_found().into(),
+        Err(err) => err.into(),
+    }
+}
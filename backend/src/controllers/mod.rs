@@ -1,2 +1,3 @@
+pub mod images;
 pub mod sessions;
 pub mod users;
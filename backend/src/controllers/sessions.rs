@@ -1,11 +1,26 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 use time::OffsetDateTime;
+use tracing::instrument;
+use utoipa::ToSchema;
+use warp::Reply;
 
-use db::{images, images_associations, result::Error, sessions, Pool};
+use db::{
+    images::{self, NearDuplicate},
+    images_associations,
+    result::{context, Error},
+    sessions::{self, State},
+    Pool,
+};
 
-use crate::response::{error, success, EmptyResponse};
+use crate::{
+    controllers::images::{ImageUrls, NearDuplicateModel, SessionImageModel},
+    events::Hub,
+    extractors::auth::{AdminAuth, Auth},
+    response::{error, success, EmptyResponse, Response},
+};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct CreateModel {
     name: String,
     #[serde(with = "time::serde::rfc3339")]
@@ -16,6 +31,18 @@ pub struct CreateModel {
     phase3: OffsetDateTime,
 }
 
+#[utoipa::path(
+    post,
+    path = "/sessions",
+    tag = "sessions",
+    operation_id = "create_session",
+    request_body = CreateModel,
+    responses(
+        (status = 201, description = "Session created"),
+        (status = 400, description = "Phases are not in ascending order", body = Envelope),
+    ),
+)]
+#[instrument(skip_all)]
 pub async fn create(params: CreateModel, db: Pool) -> EmptyResponse {
     let result = sessions::create(
         &params.name,
@@ -28,38 +55,395 @@ pub async fn create(params: CreateModel, db: Pool) -> EmptyResponse {
 
     match result {
         Ok(_) => success(()).with_status(success::Code::Created).into(),
-        Err(err) => match err {
-            Error::InvalidDates => error()
-                .with_status(error::Code::BadRequest)
-                .body(String::from("Invalid dates")),
-            _ => error(),
-        }
-        .into(),
+        Err(err) => err.into(),
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct ImagesModel {
     image: i32,
     session: i32,
 }
 
-pub async fn images(params: ImagesModel, db: Pool) -> EmptyResponse {
-    let result =
-        images_associations::create(images::Id(params.image), sessions::Id(params.session), &db)
-            .await;
+#[derive(Serialize, ToSchema)]
+pub struct AssociationModel {
+    /// Images of the session that look like the one associated.
+    warnings: Vec<NearDuplicateModel>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/sessions/images",
+    tag = "sessions",
+    operation_id = "add_session_image",
+    request_body = ImagesModel,
+    responses(
+        (status = 200, description = "Image associated with the session", body = AssociationModel),
+        (status = 400, description = "Unknown session or image", body = Envelope),
+    ),
+)]
+#[instrument(skip_all)]
+pub async fn images(params: ImagesModel, db: Pool) -> Response<AssociationModel> {
+    let (image, session) = (images::Id(params.image), sessions::Id(params.session));
+    let result = async {
+        images_associations::create(image, session, &db).await?;
+        images::near_duplicates(
+            Some(session),
+            Some(&[image]),
+            images::NEAR_DUPLICATE_DISTANCE,
+            &db,
+        )
+        .await
+    }
+    .await;
 
     match result {
-        Ok(_) => success(()).into(),
-        Err(err) => match err {
-            Error::InvalidSession => error()
-                .with_status(error::Code::BadRequest)
-                .body(String::from("Invalid session id")),
-            Error::InvalidImage => error()
-                .with_status(error::Code::BadRequest)
-                .body(String::from("Invalid image id")),
-            _ => error(),
+        Ok(duplicates) => success(AssociationModel {
+            warnings: duplicates
+                .into_iter()
+                .map(NearDuplicateModel::from)
+                .collect(),
+        })
+        .into(),
+        Err(err) => err.into(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ImageListModel {
+    images: Vec<i32>,
+}
+
+/// What a batch did to each image, in the order they were given.
+#[derive(Serialize, ToSchema)]
+pub struct ImagesReport {
+    images: Vec<ImageOutcome>,
+    /// Images of the session that look alike, including at least one added image.
+    warnings: Vec<NearDuplicateModel>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ImageOutcome {
+    image: i32,
+    outcome: Outcome,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Added,
+    Removed,
+    /// Already associated when adding, or not associated when removing.
+    Unchanged,
+    /// No image with this id, nothing was changed.
+    UnknownImage,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Batch {
+    /// Associates exactly the images given, removing the others.
+    Set,
+    Add,
+    Remove,
+}
+
+/// Lists the images of a session with the URLs to download them.
+///
+/// Admins, and the participants of the session once it started, get every image with signed URLs
+/// when they send their token. Other callers only get the public images.
+#[utoipa::path(
+    get,
+    path = "/sessions/{id}/images",
+    tag = "sessions",
+    operation_id = "list_session_images",
+    params(("id" = i32, Path, description = "Session id")),
+    responses(
+        (status = 200, description = "Images of the session visible to the caller, with their metadata, tags and URLs", body = [SessionImageModel]),
+        (status = 403, description = "Invalid token", body = Envelope),
+        (status = 404, description = "Unknown session", body = Envelope),
+    ),
+    security((), ("bearer" = [])),
+)]
+#[instrument(skip_all)]
+pub async fn list_images(
+    id: sessions::Id,
+    auth: Option<Auth>,
+    db: Pool,
+    urls: ImageUrls,
+    require_admin_totp: bool,
+) -> Response<Vec<SessionImageModel>> {
+    let result = async {
+        let mut trans = db.begin().await.map_err(context("begin", "transaction"))?;
+
+        if sessions::progress(id, &mut trans).await?.is_none() {
+            return Ok(None);
         }
+
+        let access = match auth {
+            Some(auth) => {
+                images::session_access(id, auth.id(), require_admin_totp, &mut trans).await?
+            }
+            None => false,
+        };
+
+        images::by_session(id, &mut trans)
+            .await
+            .map(|images| Some((images, access)))
+    }
+    .await;
+
+    match result {
+        Ok(Some((images, access))) => success(
+            images
+                .into_iter()
+                .filter(|image| access || image.public)
+                .map(|image| SessionImageModel::new(image, &urls, access))
+                .collect(),
+        )
         .into(),
+        Ok(None) => session_not_found().into(),
+        Err(err) => err.into(),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/sessions/{id}/images",
+    tag = "sessions",
+    operation_id = "set_session_images",
+    params(("id" = i32, Path, description = "Session id")),
+    request_body = ImageListModel,
+    responses(
+        (status = 200, description = "Images associated with the session, others removed", body = ImagesReport),
+        (status = 400, description = "Unknown images, the report is in `details`", body = Envelope),
+        (status = 403, description = "Not an admin", body = Envelope),
+        (status = 404, description = "Unknown session", body = Envelope),
+        (status = 409, description = "The first phase of the session started", body = Envelope),
+    ),
+    security(("bearer" = [])),
+)]
+#[instrument(skip_all)]
+pub async fn set_images(
+    id: sessions::Id,
+    params: ImageListModel,
+    db: Pool,
+    _: AdminAuth,
+) -> Response<ImagesReport> {
+    batch(id, params, Batch::Set, db).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/sessions/{id}/images",
+    tag = "sessions",
+    operation_id = "add_session_images",
+    params(("id" = i32, Path, description = "Session id")),
+    request_body = ImageListModel,
+    responses(
+        (status = 200, description = "Images associated with the session", body = ImagesReport),
+        (status = 400, description = "Unknown images, the report is in `details`", body = Envelope),
+        (status = 403, description = "Not an admin", body = Envelope),
+        (status = 404, description = "Unknown session", body = Envelope),
+        (status = 409, description = "The first phase of the session started", body = Envelope),
+    ),
+    security(("bearer" = [])),
+)]
+#[instrument(skip_all)]
+pub async fn add_images(
+    id: sessions::Id,
+    params: ImageListModel,
+    db: Pool,
+    _: AdminAuth,
+) -> Response<ImagesReport> {
+    batch(id, params, Batch::Add, db).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/sessions/{id}/images",
+    tag = "sessions",
+    operation_id = "remove_session_images",
+    params(("id" = i32, Path, description = "Session id")),
+    request_body = ImageListModel,
+    responses(
+        (status = 200, description = "Images removed from the session", body = ImagesReport),
+        (status = 403, description = "Not an admin", body = Envelope),
+        (status = 404, description = "Unknown session", body = Envelope),
+        (status = 409, description = "The first phase of the session started", body = Envelope),
+    ),
+    security(("bearer" = [])),
+)]
+#[instrument(skip_all)]
+pub async fn remove_images(
+    id: sessions::Id,
+    params: ImageListModel,
+    db: Pool,
+    _: AdminAuth,
+) -> Response<ImagesReport> {
+    batch(id, params, Batch::Remove, db).await
+}
+
+async fn batch(
+    id: sessions::Id,
+    params: ImageListModel,
+    batch: Batch,
+    db: Pool,
+) -> Response<ImagesReport> {
+    let mut requested = Vec::new();
+
+    for image in params.images.into_iter().map(images::Id) {
+        if !requested.contains(&image) {
+            requested.push(image);
+        }
+    }
+
+    match apply(id, &requested, batch, &db).await {
+        Ok(report) => success(report).into(),
+        Err(err) => err.into(),
+    }
+}
+
+/// Applies the batch in a transaction, changing nothing when an image is unknown.
+async fn apply(
+    id: sessions::Id,
+    requested: &[images::Id],
+    batch: Batch,
+    db: &Pool,
+) -> Result<ImagesReport, error::Error> {
+    let mut trans = db.begin().await.map_err(context("begin", "transaction"))?;
+    let progress = sessions::progress(id, &mut trans)
+        .await?
+        .ok_or_else(session_not_found)?;
+    let state = progress.session.state(OffsetDateTime::now_utc());
+
+    if state != State::Pending {
+        return Err(error()
+            .with_status(error::Code::Conflict)
+            .kind("session_started")
+            .body(format!(
+                "Images can only be changed before the first phase, the session is in {}",
+                state.as_str()
+            )));
+    }
+
+    let mut outcomes: Vec<_> = requested
+        .iter()
+        .map(|&image| (image, Outcome::Unchanged))
+        .collect();
+
+    if batch != Batch::Remove {
+        let existing = images::existing(requested, &mut trans).await?;
+
+        for (image, outcome) in &mut outcomes {
+            if !existing.contains(image) {
+                *outcome = Outcome::UnknownImage;
+            }
+        }
+        if existing.len() != requested.len() {
+            return Err(error()
+                .with_status(error::Code::BadRequest)
+                .kind(Error::InvalidImage.code())
+                .body(String::from("Unknown images, nothing was changed"))
+                .details(json!(report(outcomes, Vec::new()))));
+        }
+    }
+
+    let removed = match batch {
+        Batch::Add => Vec::new(),
+        Batch::Remove => images_associations::remove(id, requested, &mut trans).await?,
+        Batch::Set => {
+            let stale: Vec<_> = images_associations::by_session(id, &mut trans)
+                .await?
+                .into_iter()
+                .filter(|image| !requested.contains(image))
+                .collect();
+
+            images_associations::remove(id, &stale, &mut trans).await?
+        }
+    };
+    let added = match batch {
+        Batch::Remove => Vec::new(),
+        Batch::Add | Batch::Set => images_associations::add(id, requested, &mut trans).await?,
+    };
+    let warnings = if added.is_empty() {
+        Vec::new()
+    } else {
+        images::near_duplicates(
+            Some(id),
+            Some(&added),
+            images::NEAR_DUPLICATE_DISTANCE,
+            &mut trans,
+        )
+        .await?
+    };
+
+    trans
+        .commit()
+        .await
+        .map_err(context("commit", "transaction"))?;
+
+    for (image, outcome) in [(added, Outcome::Added), (removed, Outcome::Removed)]
+        .into_iter()
+        .flat_map(|(images, outcome)| images.into_iter().map(move |image| (image, outcome)))
+    {
+        match outcomes
+            .iter_mut()
+            .find(|(requested, _)| *requested == image)
+        {
+            Some((_, current)) => *current = outcome,
+            None => outcomes.push((image, outcome)),
+        }
+    }
+
+    Ok(report(outcomes, warnings))
+}
+
+fn report(outcomes: Vec<(images::Id, Outcome)>, warnings: Vec<NearDuplicate>) -> ImagesReport {
+    ImagesReport {
+        warnings: warnings.into_iter().map(NearDuplicateModel::from).collect(),
+        images: outcomes
+            .into_iter()
+            .map(|(image, outcome)| ImageOutcome {
+                image: image.0,
+                outcome,
+            })
+            .collect(),
+    }
+}
+
+pub(super) fn session_not_found() -> error::Error {
+    error()
+        .with_status(error::Code::NotFound)
+        .kind(Error::InvalidSession.code())
+        .body(String::from("No session with this id"))
+}
+
+#[utoipa::path(
+    get,
+    path = "/sessions/{id}/events",
+    tag = "sessions",
+    operation_id = "session_events",
+    params(("id" = i32, Path, description = "Session id")),
+    responses(
+        (
+            status = 200,
+            description = "`phase`, `registrations`, `choices` and `results` events, starting with the current state of the session",
+            content_type = "text/event-stream",
+            body = String,
+        ),
+        (status = 403, description = "Invalid token", body = Envelope),
+        (status = 404, description = "Unknown session", body = Envelope),
+    ),
+    security(("bearer" = [])),
+)]
+#[instrument(skip_all)]
+pub async fn events(id: sessions::Id, _: Auth, hub: Hub, db: Pool) -> warp::reply::Response {
+    match sessions::progress(id, &db).await {
+        Ok(Some(progress)) => {
+            warp::sse::reply(warp::sse::keep_alive().stream(hub.stream(progress, db)))
+                .into_response()
+        }
+        Ok(None) => EmptyResponse::from(session_not_found()).into_response(),
+        Err(err) => EmptyResponse::from(err).into_response(),
     }
 }
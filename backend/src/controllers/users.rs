@@ -1,59 +1,101 @@
+use std::net::IpAddr;
+
 use futures::TryFutureExt;
-use log::*;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::*;
+use utoipa::ToSchema;
 
 use db::{
-    result::Error,
+    login_attempts::{self, Backoff, Scope},
+    login_challenges::{self, Challenge},
+    oidc_states::{self, State},
+    recovery_codes,
+    result::{context, DbResult, Error},
     tokens,
-    users::{self, Summary},
+    users::{self, Summary, Totp},
     Pool,
 };
 use time::OffsetDateTime;
 
 use crate::{
     extractors::auth::{AdminAuth, Auth},
-    response::{error, success, EmptyResponse, Response},
+    oidc,
+    response::{error, error::FieldErrors, success, EmptyResponse, Response, ResponseBody},
+    totp,
+    validation::{self, PasswordPolicy},
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct CredentialModel {
     email: String,
     password: String,
+    /// Current TOTP code or one of the recovery codes, required once the second factor is enabled.
+    #[serde(default)]
+    totp: Option<String>,
 }
 
-pub async fn create(data: CredentialModel, db: Pool) -> EmptyResponse {
-    match users::create(&data.email, &data.password, &db).await {
+#[utoipa::path(
+    post,
+    path = "/users",
+    tag = "users",
+    operation_id = "create_user",
+    request_body = CredentialModel,
+    responses(
+        (status = 201, description = "User created, pending confirmation"),
+        (status = 400, description = "Invalid email or password", body = Envelope),
+        (status = 409, description = "Email already in use", body = Envelope),
+    ),
+)]
+#[instrument(skip_all)]
+pub async fn create(data: CredentialModel, policy: PasswordPolicy, db: Pool) -> EmptyResponse {
+    let email = validation::normalize_email(&data.email);
+    let fields: FieldErrors = [
+        ("email", validation::check_email(&email)),
+        (
+            "password",
+            validation::check_password(&data.password, policy),
+        ),
+    ]
+    .into_iter()
+    .filter(|(_, errors)| !errors.is_empty())
+    .collect();
+
+    if !fields.is_empty() {
+        return error()
+            .with_status(error::Code::BadRequest)
+            .body(String::from("Invalid fields"))
+            .fields(fields)
+            .into();
+    }
+
+    match users::create(&email, &data.password, &db).await {
         Ok(_) => success(()).with_status(success::Code::Created).into(),
-        Err(err) => {
-            error!("{err:?}");
-
-            match err {
-                Error::DuplicateEmail => error()
-                    .body("Email already in use".to_string())
-                    .with_status(error::Code::Conflict),
-                _ => error(),
-            }
-        }
-        .into(),
+        Err(err) => err.into(),
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/users/confirm/{id}",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id")),
+    responses(
+        (status = 200, description = "User confirmed"),
+        (status = 403, description = "Not an admin", body = Envelope),
+        (status = 404, description = "Unknown user", body = Envelope),
+    ),
+    security(("bearer" = [])),
+)]
+#[instrument(skip_all)]
 pub async fn confirm(id: users::Id, db: Pool, _: AdminAuth) -> EmptyResponse {
     match users::confirm(id, &db).await {
         Ok(()) => success(()).into(),
-        Err(err) => {
-            error!("{err:?}");
-
-            match err {
-                Error::InvalidUserId => error().with_status(error::Code::NotFound),
-                _ => error(),
-            }
-            .into()
-        }
+        Err(err) => err.into(),
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct TokenResponse {
     token: String,
     #[serde(with = "time::serde::rfc3339")]
@@ -61,54 +103,542 @@ pub struct TokenResponse {
     admin: bool,
 }
 
-pub async fn login(data: CredentialModel, db: Pool) -> Response<TokenResponse> {
-    let dbref = &db;
+/// Limits applied to failed logins, see [`login_attempts`].
+#[derive(Clone, Copy)]
+pub struct LoginPolicy {
+    pub backoff: Backoff,
+    pub lockout_threshold: i32,
+    pub lockout_duration: f64,
+}
+
+#[utoipa::path(
+    post,
+    path = "/users/login",
+    tag = "users",
+    request_body = CredentialModel,
+    responses(
+        (status = 201, description = "Logged in", body = TokenResponse),
+        (status = 403, description = "Invalid credentials, second factor or locked account", body = Envelope),
+        (status = 429, description = "Too many failed attempts", body = Envelope),
+    ),
+)]
+#[instrument(skip_all)]
+pub async fn login(
+    mut data: CredentialModel,
+    client_ip: Option<IpAddr>,
+    policy: LoginPolicy,
+    db: Pool,
+) -> Response<TokenResponse> {
+    let ip = client_ip.map(|ip| ip.to_string());
 
-    let result = users::find_by_credentials(&data.email, &data.password, dbref)
-        .and_then(|(id, admin)| async move {
-            tokens::create(id, dbref)
-                .await
-                .map(|(token, expiration)| (token.0.to_string(), expiration, admin))
+    data.email = validation::normalize_email(&data.email);
+
+    match authenticate(&data, ip.as_deref(), policy, &db).await {
+        Ok((token, expiration, admin)) => success(TokenResponse {
+            token,
+            expiration,
+            admin,
         })
-        .await;
+        .with_status(success::Code::Created)
+        .into(),
+        Err(err) => err.into(),
+    }
+}
+
+async fn authenticate(
+    data: &CredentialModel,
+    ip: Option<&str>,
+    policy: LoginPolicy,
+    db: &Pool,
+) -> DbResult<(String, OffsetDateTime, bool)> {
+    if let Some(ip) = ip {
+        login_attempts::check(Scope::Ip, ip, policy.backoff, db).await?;
+    }
+    login_attempts::check(Scope::Email, &data.email, policy.backoff, db).await?;
+
+    match users::find_by_credentials(&data.email, &data.password, db).await {
+        Ok((id, admin)) => {
+            admit(id, &data.email, data.totp.as_deref(), ip, policy, db).await?;
+            issue_token(id, admin, db).await
+        }
+        Err(err @ Error::InvalidCredentials) => {
+            record_failure(&data.email, ip, policy, db).await?;
+
+            Err(err)
+        }
+        // Answered like wrong credentials, since the lock is reported whatever the password.
+        Err(Error::AccountLocked) => Err(Error::InvalidCredentials),
+        Err(err) => Err(err),
+    }
+}
+
+/// Lets in a user identified by any login method, unless their account is locked or their second
+/// factor is missing or wrong. Wrong codes count towards the lockout like wrong passwords.
+async fn admit(
+    id: users::Id,
+    email: &str,
+    code: Option<&str>,
+    ip: Option<&str>,
+    policy: LoginPolicy,
+    db: &Pool,
+) -> DbResult<()> {
+    if users::locked(id, db).await? {
+        return Err(Error::AccountLocked);
+    }
+
+    match second_factor(id, code, db).await {
+        Ok(()) => login_attempts::clear(Scope::Email, email, db).await,
+        Err(err @ Error::InvalidSecondFactor) => {
+            record_failure(email, ip, policy, db).await?;
+
+            Err(err)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+async fn issue_token(
+    id: users::Id,
+    admin: bool,
+    db: &Pool,
+) -> DbResult<(String, OffsetDateTime, bool)> {
+    tokens::create(id, db)
+        .await
+        .map(|(token, expiration)| (token.0.to_string(), expiration, admin))
+}
+
+/// Checks `code` against the TOTP secret or the recovery codes of a user, if they enabled it.
+async fn second_factor(id: users::Id, code: Option<&str>, db: &Pool) -> DbResult<()> {
+    match (users::totp(id, db).await?, code) {
+        (
+            Totp {
+                secret: Some(secret),
+                enabled: true,
+            },
+            Some(code),
+        ) => totp::check(id, &secret, code, db).await,
+        (Totp { enabled: true, .. }, None) => Err(Error::SecondFactorRequired),
+        _ => Ok(()),
+    }
+}
+
+async fn record_failure(
+    email: &str,
+    ip: Option<&str>,
+    policy: LoginPolicy,
+    db: &Pool,
+) -> DbResult<()> {
+    if let Some(ip) = ip {
+        login_attempts::record_failure(Scope::Ip, ip, policy.backoff, db).await?;
+    }
+
+    let failures = login_attempts::record_failure(Scope::Email, email, policy.backoff, db).await?;
+
+    if failures >= policy.lockout_threshold {
+        warn!("Locking {email} after {failures} failed login attempts");
+
+        users::lock(email, policy.lockout_duration, db).await?;
+    }
+
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/users/unlock/{id}",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id")),
+    responses(
+        (status = 200, description = "Account unlocked"),
+        (status = 403, description = "Not an admin", body = Envelope),
+        (status = 404, description = "Unknown user", body = Envelope),
+    ),
+    security(("bearer" = [])),
+)]
+#[instrument(skip_all)]
+pub async fn unlock(id: users::Id, db: Pool, auth: AdminAuth) -> EmptyResponse {
+    let result: DbResult<()> = async {
+        let mut trans = db.begin().await.map_err(context("begin", "transaction"))?;
+        let email = users::unlock(id, &mut trans).await?;
+
+        login_attempts::clear(Scope::Email, &email, &mut trans).await?;
+        trans
+            .commit()
+            .await
+            .map_err(context("commit", "transaction"))
+    }
+    .await;
 
     match result {
-        Ok((token, expiration, admin)) => success(TokenResponse {
+        Ok(()) => {
+            info!("User {} unlocked by {}", id.0, auth.id().0);
+
+            success(()).into()
+        }
+        Err(err) => err.into(),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct OidcLoginResponse {
+    url: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/users/oidc/login",
+    tag = "users",
+    responses(
+        (status = 200, description = "Identity provider URL to redirect to", body = OidcLoginResponse),
+        (status = 404, description = "OpenID Connect is not configured", body = Envelope),
+    ),
+)]
+#[instrument(skip_all)]
+pub async fn oidc_login(client: Option<oidc::Client>, db: Pool) -> Response<OidcLoginResponse> {
+    let client = match client {
+        Some(client) => client,
+        None => return oidc_not_configured(),
+    };
+    let nonce = oidc::random_string();
+    let verifier = oidc::random_string();
+
+    let state = match oidc_states::create(&nonce, &verifier, &db).await {
+        Ok(state) => state,
+        Err(err) => return err.into(),
+    };
+
+    match client
+        .authorization_url(&state.0.to_string(), &nonce, &verifier)
+        .await
+    {
+        Ok(url) => success(OidcLoginResponse { url }).into(),
+        Err(err) => {
+            error!("OpenID Connect login failed: {err}");
+
+            error().into()
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct OidcCallbackModel {
+    code: String,
+    state: String,
+}
+
+/// Completes an OpenID Connect login, creating and confirming the user on their first login.
+///
+/// Users who enabled the second factor get a `second_factor_required` error instead, whose
+/// details hold the challenge to send with their code to `/users/oidc/second-factor`.
+#[utoipa::path(
+    post,
+    path = "/users/oidc/callback",
+    tag = "users",
+    request_body = OidcCallbackModel,
+    responses(
+        (status = 201, description = "Logged in", body = TokenResponse),
+        (status = 400, description = "Invalid or expired state", body = Envelope),
+        (status = 403, description = "Authorization refused, second factor required or locked account", body = Envelope),
+        (status = 404, description = "OpenID Connect is not configured", body = Envelope),
+        (status = 429, description = "Too many failed attempts", body = Envelope),
+    ),
+)]
+#[instrument(skip_all)]
+pub async fn oidc_callback(
+    data: OidcCallbackModel,
+    client: Option<oidc::Client>,
+    policy: LoginPolicy,
+    db: Pool,
+) -> Response<TokenResponse> {
+    let client = match client {
+        Some(client) => client,
+        None => return oidc_not_configured(),
+    };
+    let state = match data.state.parse() {
+        Ok(state) => State(state),
+        Err(_) => return Error::InvalidOidcState.into(),
+    };
+
+    let (nonce, verifier) = match oidc_states::consume(state, &db).await {
+        Ok(pending) => pending,
+        Err(err) => return err.into(),
+    };
+
+    let email = match client.exchange(&data.code, &verifier, &nonce).await {
+        Ok(email) => email,
+        Err(oidc::Error::UnverifiedEmail) => {
+            return error()
+                .with_status(error::Code::Forbidden)
+                .kind("unverified_email")
+                .body(String::from("Email not verified by the identity provider"))
+                .into()
+        }
+        Err(err) => {
+            warn!("OpenID Connect login failed: {err}");
+
+            return error()
+                .with_status(error::Code::Forbidden)
+                .kind("invalid_authorization")
+                .body(String::from("Invalid authorization"))
+                .into();
+        }
+    };
+
+    let email = validation::normalize_email(&email);
+    let result = async {
+        login_attempts::check(Scope::Email, &email, policy.backoff, &db).await?;
+
+        let (id, admin) = users::upsert_verified(&email, &db).await?;
+
+        match admit(id, &email, None, None, policy, &db).await {
+            Ok(()) => issue_token(id, admin, &db).await.map(Ok),
+            Err(Error::SecondFactorRequired) => login_challenges::create(id, &db).await.map(Err),
+            Err(err) => Err(err),
+        }
+    }
+    .await;
+
+    match result {
+        Ok(Ok((token, expiration, admin))) => success(TokenResponse {
             token,
             expiration,
             admin,
         })
         .with_status(success::Code::Created)
         .into(),
-        Err(Error::InvalidCredentials) => error()
+        Ok(Err(challenge)) => error()
             .with_status(error::Code::Forbidden)
-            .body(String::from("Invalid credentials"))
+            .kind(Error::SecondFactorRequired.code())
+            .body(String::from("Second factor required"))
+            .details(json!({ "challenge": challenge.0.to_string() }))
             .into(),
-        Err(err) => {
-            error!("{err:?}");
+        Err(err) => err.into(),
+    }
+}
 
-            error().into()
+#[derive(Deserialize, ToSchema)]
+pub struct OidcSecondFactorModel {
+    /// Challenge returned by the callback of a user who enabled the second factor.
+    challenge: String,
+    /// Current TOTP code or one of the recovery codes.
+    totp: String,
+}
+
+/// Completes an OpenID Connect login held back by the second factor of the user.
+#[utoipa::path(
+    post,
+    path = "/users/oidc/second-factor",
+    tag = "users",
+    request_body = OidcSecondFactorModel,
+    responses(
+        (status = 201, description = "Logged in", body = TokenResponse),
+        (status = 400, description = "Invalid or expired challenge", body = Envelope),
+        (status = 403, description = "Invalid second factor or locked account", body = Envelope),
+        (status = 429, description = "Too many failed attempts", body = Envelope),
+    ),
+)]
+#[instrument(skip_all)]
+pub async fn oidc_second_factor(
+    data: OidcSecondFactorModel,
+    client_ip: Option<IpAddr>,
+    policy: LoginPolicy,
+    db: Pool,
+) -> Response<TokenResponse> {
+    let ip = client_ip.map(|ip| ip.to_string());
+    let challenge = match data.challenge.parse() {
+        Ok(challenge) => Challenge(challenge),
+        Err(_) => return Error::InvalidLoginChallenge.into(),
+    };
+
+    let result = async {
+        let (id, email, admin) = login_challenges::find(challenge, &db).await?;
+
+        if let Some(ip) = &ip {
+            login_attempts::check(Scope::Ip, ip, policy.backoff, &db).await?;
+        }
+        login_attempts::check(Scope::Email, &email, policy.backoff, &db).await?;
+
+        admit(id, &email, Some(&data.totp), ip.as_deref(), policy, &db).await?;
+        login_challenges::consume(challenge, &db).await?;
+        issue_token(id, admin, &db).await
+    }
+    .await;
+
+    match result {
+        Ok((token, expiration, admin)) => success(TokenResponse {
+            token,
+            expiration,
+            admin,
+        })
+        .with_status(success::Code::Created)
+        .into(),
+        Err(err) => err.into(),
+    }
+}
+
+fn oidc_not_configured<T: ResponseBody>() -> Response<T> {
+    error()
+        .with_status(error::Code::NotFound)
+        .kind("oidc_not_configured")
+        .body(String::from("OpenID Connect is not configured"))
+        .into()
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TotpEnrollment {
+    secret: String,
+    uri: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/users/totp",
+    tag = "users",
+    responses(
+        (status = 201, description = "Pending secret", body = TotpEnrollment),
+        (status = 403, description = "Invalid token", body = Envelope),
+        (status = 409, description = "Second factor already enabled", body = Envelope),
+    ),
+    security(("bearer" = [])),
+)]
+#[instrument(skip_all)]
+pub async fn enroll_totp(auth: Auth, db: Pool) -> Response<TotpEnrollment> {
+    let secret = totp::generate_secret();
+    let result = users::set_totp_secret(auth.id(), &secret, &db)
+        .and_then(|()| users::email(auth.id(), &db))
+        .await;
+
+    match result {
+        Ok(email) => match totp::provisioning_uri(&secret, &email) {
+            Some(uri) => success(TotpEnrollment { secret, uri })
+                .with_status(success::Code::Created)
+                .into(),
+            None => error().into(),
+        },
+        Err(err) => err.into(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct TotpCodeModel {
+    code: String,
+}
+
+/// Enables the second factor once the user proves their app works, returns single use recovery codes.
+#[utoipa::path(
+    post,
+    path = "/users/totp/enable",
+    tag = "users",
+    request_body = TotpCodeModel,
+    responses(
+        (status = 200, description = "Recovery codes", body = [String]),
+        (status = 403, description = "Invalid token or code", body = Envelope),
+        (status = 409, description = "Second factor already enabled", body = Envelope),
+    ),
+    security(("bearer" = [])),
+)]
+#[instrument(skip_all)]
+pub async fn enable_totp(data: TotpCodeModel, auth: Auth, db: Pool) -> Response<Vec<String>> {
+    let id = auth.id();
+    let codes = totp::recovery_codes();
+    let result: DbResult<()> = async {
+        let factor = users::totp(id, &db).await?;
+
+        if factor.enabled {
+            return Err(Error::SecondFactorAlreadyEnabled);
         }
+        let step = factor
+            .secret
+            .and_then(|secret| totp::verify(&secret, &data.code))
+            .ok_or(Error::InvalidSecondFactor)?;
+
+        let mut trans = db.begin().await.map_err(context("begin", "transaction"))?;
+
+        users::use_totp_step(id, step, &mut trans).await?;
+        users::enable_totp(id, &mut trans).await?;
+        recovery_codes::replace(id, &codes, &mut trans).await?;
+        trans
+            .commit()
+            .await
+            .map_err(context("commit", "transaction"))
+    }
+    .await;
+
+    match result {
+        Ok(()) => success(codes).into(),
+        Err(err) => err.into(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/users/totp/disable",
+    tag = "users",
+    request_body = TotpCodeModel,
+    responses(
+        (status = 200, description = "Second factor disabled"),
+        (status = 403, description = "Invalid token or code", body = Envelope),
+    ),
+    security(("bearer" = [])),
+)]
+#[instrument(skip_all)]
+pub async fn disable_totp(data: TotpCodeModel, auth: Auth, db: Pool) -> EmptyResponse {
+    let id = auth.id();
+    let result: DbResult<()> = async {
+        second_factor(id, Some(&data.code), &db).await?;
+
+        let mut trans = db.begin().await.map_err(context("begin", "transaction"))?;
+
+        users::disable_totp(id, &mut trans).await?;
+        recovery_codes::clear(id, &mut trans).await?;
+        trans
+            .commit()
+            .await
+            .map_err(context("commit", "transaction"))
+    }
+    .await;
+
+    match result {
+        Ok(()) => success(()).into(),
+        Err(err) => err.into(),
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/users/logout",
+    tag = "users",
+    responses(
+        (status = 200, description = "Token revoked"),
+        (status = 403, description = "Invalid token", body = Envelope),
+    ),
+    security(("bearer" = [])),
+)]
+#[instrument(skip_all)]
 pub async fn logout(auth: Auth, db: Pool) -> EmptyResponse {
     match tokens::delete(auth.token(), &db).await {
         Ok(()) => success(()).into(),
-        Err(err) => {
-            error!("{err:?}");
-
-            error().into()
-        }
+        Err(err) => err.into(),
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct CandidateModel {
     id: i32,
     email: String,
 }
 
+#[utoipa::path(
+    post,
+    path = "/users/candidates",
+    tag = "users",
+    responses(
+        (status = 200, description = "Users pending confirmation", body = [CandidateModel]),
+        (status = 403, description = "Not an admin", body = Envelope),
+    ),
+    security(("bearer" = [])),
+)]
+#[instrument(skip_all)]
 pub async fn candidates(db: Pool, _: AdminAuth) -> Response<Vec<CandidateModel>> {
     match users::list_candidates(&db).await {
         Ok(candidates) => success(
@@ -118,10 +648,6 @@ pub async fn candidates(db: Pool, _: AdminAuth) -> Response<Vec<CandidateModel>>
                 .collect(),
         )
         .into(),
-        Err(err) => {
-            error!("{err:?}");
-
-            error().into()
-        }
+        Err(err) => err.into(),
     }
 }
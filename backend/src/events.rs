@@ -0,0 +1,346 @@
+//! Session lifecycle events, streamed to clients with Server-Sent Events.
+//!
+//! Each replica keeps a single connection listening to the notifications sent by the database
+//! triggers, reloads the progress of the session once per notification and broadcasts it to its
+//! streams, which send what changed. Phases change with time rather than writes, so streams also
+//! wake up when the next phase starts.
+
+use std::{collections::VecDeque, convert::Infallible, time::Duration};
+
+use futures::Stream;
+use serde_json::json;
+use time::OffsetDateTime;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{error, warn};
+use warp::sse::Event;
+
+use db::{
+    events::Listener,
+    sessions::{self, Progress, State},
+    Pool,
+};
+use server::shutdown::Shutdown;
+
+/// Notifications buffered for each stream before it misses some and reloads its session.
+const CAPACITY: usize = 256;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy)]
+enum Signal {
+    Changed(Progress),
+    Deleted(sessions::Id),
+    /// Notifications may have been lost while reconnecting, or progress failed to load.
+    Missed,
+}
+
+/// Fans the notifications received by this replica out to its streams.
+#[derive(Clone)]
+pub struct Hub {
+    sender: broadcast::Sender<Signal>,
+    shutdown: Shutdown,
+}
+
+impl Hub {
+    /// Streams end when `shutdown` fires, so that they do not hold the server open.
+    pub fn new(shutdown: Shutdown) -> Self {
+        Self {
+            sender: broadcast::channel(CAPACITY).0,
+            shutdown,
+        }
+    }
+
+    /// Forwards notifications until shutdown, reconnecting whenever the connection is lost.
+    pub async fn listen(self, pool: Pool) {
+        let shutdown = self.shutdown.clone().wait();
+        tokio::pin!(shutdown);
+
+        loop {
+            match Listener::connect(&pool).await {
+                Ok(mut listener) => {
+                    self.send(Signal::Missed);
+
+                    loop {
+                        tokio::select! {
+                            _ = &mut shutdown => return,
+                            notification = listener.recv() => match notification {
+                                Ok(Some(notification)) => {
+                                    self.reload(notification.session, &pool).await
+                                }
+                                Ok(None) => {
+                                    warn!("Session events connection lost, reconnecting");
+                                    break;
+                                }
+                                Err(err) => {
+                                    error!("Failed to receive session events: {err}");
+                                    break;
+                                }
+                            },
+                        }
+                    }
+                }
+                Err(err) => error!("Failed to listen to session events: {err}"),
+            }
+
+            tokio::select! {
+                _ = &mut shutdown => return,
+                _ = tokio::time::sleep(RECONNECT_DELAY) => (),
+            }
+        }
+    }
+
+    /// Loads the progress of a session once for every stream following it.
+    async fn reload(&self, session: sessions::Id, pool: &Pool) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+
+        match sessions::progress(session, pool).await {
+            Ok(Some(progress)) => self.send(Signal::Changed(progress)),
+            Ok(None) => self.send(Signal::Deleted(session)),
+            Err(err) => {
+                warn!("Failed to reload session {}: {err}", session.0);
+                self.send(Signal::Missed);
+            }
+        }
+    }
+
+    fn send(&self, signal: Signal) {
+        // Fails when no stream is open, which is fine.
+        let _ = self.sender.send(signal);
+    }
+
+    /// Events of the session, starting with its current progress.
+    pub fn stream(
+        &self,
+        progress: Progress,
+        pool: Pool,
+    ) -> impl Stream<Item = Result<Event, Infallible>> + Send + 'static {
+        let now = OffsetDateTime::now_utc();
+        let follower = Follower {
+            pending: changes(None, &progress, now).into(),
+            state: progress.session.state(now),
+            progress,
+            receiver: self.sender.subscribe(),
+            shutdown: self.shutdown.clone(),
+            pool,
+        };
+
+        futures::stream::unfold(follower, |mut follower| async move {
+            loop {
+                if let Some(event) = follower.pending.pop_front() {
+                    return Some((Ok(event), follower));
+                }
+                if !follower.wait().await {
+                    return None;
+                }
+            }
+        })
+    }
+}
+
+struct Follower {
+    progress: Progress,
+    state: State,
+    pending: VecDeque<Event>,
+    receiver: broadcast::Receiver<Signal>,
+    shutdown: Shutdown,
+    pool: Pool,
+}
+
+impl Follower {
+    /// Waits for the session to change and queues the resulting events, `false` once the stream
+    /// should end.
+    async fn wait(&mut self) -> bool {
+        let session = self.progress.session.id;
+        let next_phase = self
+            .progress
+            .session
+            .next_phase(OffsetDateTime::now_utc())
+            .map(|phase| {
+                (phase - OffsetDateTime::now_utc())
+                    .try_into()
+                    .unwrap_or_default()
+            });
+        let phase_started = async {
+            match next_phase {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => futures::future::pending().await,
+            }
+        };
+
+        let progress = tokio::select! {
+            _ = self.shutdown.clone().wait() => return false,
+            _ = phase_started => self.progress,
+            signal = self.receiver.recv() => match signal {
+                Ok(Signal::Changed(progress)) if progress.session.id == session => progress,
+                Ok(Signal::Deleted(id)) if id == session => return false,
+                Ok(Signal::Changed(_) | Signal::Deleted(_)) => return true,
+                Ok(Signal::Missed) | Err(RecvError::Lagged(_)) => {
+                    match sessions::progress(session, &self.pool).await {
+                        Ok(Some(progress)) => progress,
+                        // The session was deleted.
+                        Ok(None) => return false,
+                        Err(err) => {
+                            warn!("Failed to reload session {}: {err}", session.0);
+                            return true;
+                        }
+                    }
+                }
+                Err(RecvError::Closed) => return false,
+            },
+        };
+        let now = OffsetDateTime::now_utc();
+
+        self.pending
+            .extend(changes(Some((&self.progress, self.state)), &progress, now));
+        self.state = progress.session.state(now);
+        self.progress = progress;
+        true
+    }
+}
+
+/// Events telling how `progress` differs from `last`, every event when there is none.
+fn changes(
+    last: Option<(&Progress, State)>,
+    progress: &Progress,
+    now: OffsetDateTime,
+) -> Vec<Event> {
+    let state = progress.session.state(now);
+    let counts = [
+        (
+            "registrations",
+            last.map(|(last, _)| last.registrations),
+            progress.registrations,
+        ),
+        (
+            "choices",
+            last.map(|(last, _)| last.choices),
+            progress.choices,
+        ),
+        (
+            "results",
+            last.map(|(last, _)| last.results),
+            progress.results,
+        ),
+    ];
+    let mut events = Vec::new();
+
+    if last.map(|(_, state)| state) != Some(state) {
+        events.push(event("phase", json!({ "phase": state.as_str() })));
+    }
+    for (name, last, count) in counts {
+        if last != Some(count) {
+            events.push(event(name, json!({ "count": count })));
+        }
+    }
+
+    events
+}
+
+fn event(name: &str, data: serde_json::Value) -> Event {
+    Event::default().event(name).data(data.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use time::{Duration, OffsetDateTime};
+
+    use db::sessions::{Id, Progress, Session, State};
+    use server::shutdown::Trigger;
+
+    use super::{changes, Hub, Signal};
+    use crate::testing;
+
+    fn progress(registrations: i64, results: i64) -> Progress {
+        let now = OffsetDateTime::now_utc();
+
+        Progress {
+            session: Session {
+                id: Id(1),
+                phase1: now - Duration::days(1),
+                phase2: now + Duration::days(1),
+                phase3: now + Duration::days(2),
+            },
+            registrations,
+            choices: 0,
+            results,
+        }
+    }
+
+    fn names(events: Vec<warp::sse::Event>) -> Vec<String> {
+        events
+            .into_iter()
+            .map(|event| {
+                let text = event.to_string();
+
+                text.lines()
+                    .find_map(|line| line.strip_prefix("event:"))
+                    .unwrap()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn initial() {
+        let events = changes(None, &progress(3, 0), OffsetDateTime::now_utc());
+
+        assert_eq!(
+            names(events),
+            ["phase", "registrations", "choices", "results"]
+        );
+    }
+
+    #[test]
+    fn counts() {
+        let last = progress(3, 0);
+
+        let events = changes(
+            Some((&last, State::Phase1)),
+            &progress(4, 2),
+            OffsetDateTime::now_utc(),
+        );
+
+        assert_eq!(names(events), ["registrations", "results"]);
+    }
+
+    #[test]
+    fn phase() {
+        let last = progress(3, 0);
+
+        let events = changes(
+            Some((&last, State::Phase1)),
+            &progress(3, 0),
+            OffsetDateTime::now_utc() + Duration::days(1),
+        );
+
+        assert_eq!(names(events), ["phase"]);
+    }
+
+    /// Streams use the progress loaded by the hub, the pool they are given cannot reach a database.
+    #[tokio::test]
+    async fn fan_out() {
+        let trigger = Trigger::new();
+        let hub = Hub::new(trigger.subscribe());
+        let stream = hub.stream(progress(3, 0), testing::offline_pool());
+        let mut other = progress(9, 9);
+
+        other.session.id = Id(2);
+        tokio::pin!(stream);
+
+        let initial: Vec<_> = (&mut stream).take(4).map(Result::unwrap).collect().await;
+
+        hub.send(Signal::Changed(other));
+        hub.send(Signal::Changed(progress(4, 1)));
+
+        let changed: Vec<_> = (&mut stream).take(2).map(Result::unwrap).collect().await;
+
+        hub.send(Signal::Deleted(Id(1)));
+
+        assert_eq!(initial.len(), 4);
+        assert_eq!(names(changed), ["registrations", "results"]);
+        assert!(stream.next().await.is_none());
+    }
+}
@@ -20,7 +20,6 @@ pub mod auth {
 
     pub struct AdminAuth {
         id: db::users::Id,
-        token: Token,
     }
 
     impl Auth {
@@ -37,10 +36,6 @@ pub mod auth {
         pub fn id(&self) -> db::users::Id {
             self.id
         }
-
-        pub fn token(&self) -> Token {
-            self.token
-        }
     }
 
     #[derive(Debug)]
@@ -49,28 +44,36 @@ pub mod auth {
     impl warp::reject::Reject for InvalidToken {}
 
     pub fn auth_filter(pool: Pool) -> impl Filter<Extract = (Auth,), Error = Rejection> + Clone {
-        bearer_filter().and_then(move |token| {
+        bearer_filter().and_then(move |token| authenticate(token, pool.clone()))
+    }
+
+    /// Authenticates the user when the request has a token, still rejecting invalid tokens.
+    pub fn optional_auth_filter(
+        pool: Pool,
+    ) -> impl Filter<Extract = (Option<Auth>,), Error = Rejection> + Clone {
+        warp::header::optional("Authorization").and_then(move |auth: Option<String>| {
             let pool = pool.clone();
 
             async move {
-                match db::tokens::auth(token, &pool).await {
-                    Ok(id) => Ok(Auth { id, token }),
-                    Err(Error::InvalidToken) => Err(warp::reject::custom(InvalidToken {})),
-                    Err(_) => Err(warp::reject::custom(InternalError {})),
+                match auth {
+                    Some(auth) => authenticate(bearer(&auth)?, pool).await.map(Some),
+                    None => Ok(None),
                 }
             }
         })
     }
 
+    /// Authenticates an admin, `require_totp` refuses admins who have not enabled their second factor.
     pub fn admin_auth_filter(
         pool: Pool,
+        require_totp: bool,
     ) -> impl Filter<Extract = (AdminAuth,), Error = Rejection> + Clone {
         bearer_filter().and_then(move |token| {
             let pool = pool.clone();
 
             async move {
-                match db::tokens::auth_admin(token, &pool).await {
-                    Ok(id) => Ok(AdminAuth { id, token }),
+                match db::tokens::auth_admin(token, require_totp, &pool).await {
+                    Ok(id) => Ok(AdminAuth { id }),
                     Err(Error::InvalidToken) => Err(warp::reject::custom(InvalidToken {})),
                     Err(_) => Err(warp::reject::custom(InternalError {})),
                 }
@@ -78,18 +81,28 @@ pub mod auth {
         })
     }
 
+    async fn authenticate(token: Token, pool: Pool) -> Result<Auth, Rejection> {
+        match db::tokens::auth(token, &pool).await {
+            Ok(id) => Ok(Auth { id, token }),
+            Err(Error::InvalidToken) => Err(warp::reject::custom(InvalidToken {})),
+            Err(_) => Err(warp::reject::custom(InternalError {})),
+        }
+    }
+
     fn bearer_filter() -> impl Filter<Extract = (Token,), Error = warp::Rejection> + Clone {
-        warp::header("Authorization").and_then(|auth: String| async move {
-            let mut parts = auth.trim().split(' ');
-
-            match (
-                parts.next(),
-                parts.next().map(FromStr::from_str),
-                parts.next(),
-            ) {
-                (Some("Bearer"), Some(Ok(token)), None) => Ok(Token(token)),
-                _ => Err(warp::reject::custom(InvalidToken {})),
-            }
-        })
+        warp::header("Authorization").and_then(|auth: String| async move { bearer(&auth) })
+    }
+
+    fn bearer(auth: &str) -> Result<Token, Rejection> {
+        let mut parts = auth.trim().split(' ');
+
+        match (
+            parts.next(),
+            parts.next().map(FromStr::from_str),
+            parts.next(),
+        ) {
+            (Some("Bearer"), Some(Ok(token)), None) => Ok(Token(token)),
+            _ => Err(warp::reject::custom(InvalidToken {})),
+        }
     }
 }
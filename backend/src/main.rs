@@ -1,64 +1,144 @@
 mod controllers;
+mod events;
 mod extractors;
+mod metrics;
+mod oidc;
+mod openapi;
 mod response;
 mod routes;
+#[cfg(test)]
+mod testing;
+mod totp;
+mod validation;
 
-use std::{net::SocketAddr, str::FromStr};
+use std::{net::SocketAddr, time::Duration};
 
+use config::{cors, database, image_urls, Loader};
+use controllers::{images::ImageUrls, users::LoginPolicy};
+use db::{login_attempts::Backoff, ConnectOptions};
+use events::Hub;
 use routes::routes;
+use server::{
+    health,
+    shutdown::{self, Trigger},
+};
+use tracing::error;
+use validation::PasswordPolicy;
+use warp::http::header::HeaderName;
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
+    server::logging::init();
 
     let config = config();
-    let pool = db::connect(
-        &config.db_user,
-        &config.db_pass,
-        &config.db_host,
-        config.db_port,
-        &config.db_name,
-    )
-    .await
-    .expect("Failed to connect to database");
 
-    println!("Starting server on {}", config.addr);
+    if config.healthcheck {
+        std::process::exit(if health::probe(config.addr).await {
+            0
+        } else {
+            1
+        });
+    }
+    let pool = match db::connect(&config.db).await {
+        Ok(pool) => pool,
+        Err(err) => {
+            error!("Failed to connect to database: {err}");
+            std::process::exit(1);
+        }
+    };
+
+    let oidc = config.oidc.map(oidc::Client::new);
+    let trigger = Trigger::new();
+    let hub = Hub::new(trigger.subscribe());
+    let listener = tokio::spawn(hub.clone().listen(pool.clone()));
+
+    let routes = routes(
+        pool.clone(),
+        config.login_policy,
+        config.require_admin_totp,
+        oidc,
+        config.password_policy,
+        &config.cors,
+        hub,
+        config.image_urls,
+        config.client_ip_header,
+    );
+
+    let deadline = server::serve(
+        warp::service(routes),
+        config.addr,
+        config.shutdown_timeout,
+        trigger,
+    )
+    .await;
 
-    warp::serve(routes(pool)).run(config.addr).await;
+    shutdown::until(deadline, "the event listener", listener).await;
+    shutdown::until(deadline, "closing the database pool", pool.close()).await;
 }
 
 fn config() -> Config {
-    Config {
-        addr: var_with_default("HOST", || SocketAddr::from(([0, 0, 0, 0], 6060))),
-        db_port: var_with_default("DB_PORT", || 6300),
-        db_host: var_with_default("DB_HOST", || String::from("0.0.0.0")),
-        db_name: var_with_default("DB_NAME", || String::from("db")),
-        db_user: var_with_default("DB_USER", || String::from("postgre")),
-        db_pass: var_with_default("DB_PASS", || String::from("postgre")),
-    }
+    let secrets = database::SECRETS
+        .into_iter()
+        .chain(image_urls::SECRETS)
+        .chain(["oidc.client_secret"]);
+    let mut loader = Loader::load(secrets);
+    let config = Config {
+        addr: loader.get("host", || SocketAddr::from(([0, 0, 0, 0], 6060))),
+        shutdown_timeout: loader.duration("shutdown_timeout", Duration::from_secs(30)),
+        healthcheck: loader.get("healthcheck", || false),
+        db: database::connect_options(&mut loader, "backend"),
+        cors: cors::policy(&mut loader),
+        login_policy: LoginPolicy {
+            backoff: Backoff {
+                threshold: loader.get("login.backoff_threshold", || 5),
+                base_delay: loader.get("login.backoff_base_delay", || 1.),
+                max_delay: loader.get("login.backoff_max_delay", || 900.),
+                reset_after: loader.get("login.backoff_reset_after", || 86400.),
+            },
+            lockout_threshold: loader.get("login.lockout_threshold", || 20),
+            lockout_duration: loader.get("login.lockout_duration", || 3600.),
+        },
+        require_admin_totp: loader.get("require_admin_totp", || false),
+        password_policy: PasswordPolicy {
+            min_length: loader.get("password.min_length", || 8),
+            reject_common: loader.get("password.reject_common", || true),
+        },
+        oidc: loader.optional("oidc.issuer").map(|issuer| oidc::Config {
+            issuer,
+            client_id: loader.required("oidc.client_id"),
+            client_secret: loader.required("oidc.client_secret"),
+            redirect_uri: loader.required("oidc.redirect_uri"),
+        }),
+        image_urls: ImageUrls {
+            base: loader
+                .get("image_urls.base", || String::from("http://localhost:3030"))
+                .trim_end_matches('/')
+                .to_string(),
+            ttl: Duration::from_secs(loader.get("image_urls.ttl", || 3600)),
+            keys: image_urls::keys(&mut loader),
+        },
+        client_ip_header: loader.optional("client_ip_header"),
+    };
+
+    loader.finish_or_exit();
+    config
 }
 
 struct Config {
     addr: SocketAddr,
-    db_port: i16,
-    db_host: String,
-    db_name: String,
-    db_user: String,
-    db_pass: String,
-}
-
-fn var_with_default<T, F>(var: &str, default: F) -> T
-where
-    T: FromStr,
-    F: FnOnce() -> T,
-{
-    std::env::var(var)
-        .map(|var| {
-            var.parse()
-                .unwrap_or_else(|_| panic!("Invalid value for {var}"))
-        })
-        .unwrap_or_else(|e| match e {
-            std::env::VarError::NotPresent => default(),
-            std::env::VarError::NotUnicode(_) => panic!("Invalid unicode in {var}"),
-        })
+    shutdown_timeout: Duration,
+    /// Probe the readiness of a running instance then exit, for container health checks.
+    healthcheck: bool,
+    db: ConnectOptions,
+    cors: server::cors::Policy,
+    login_policy: LoginPolicy,
+    require_admin_totp: bool,
+    password_policy: PasswordPolicy,
+    oidc: Option<oidc::Config>,
+    /// URLs of the images on image-host handed out by the backend.
+    image_urls: ImageUrls,
+    /// Header the reverse proxy in front of the backend puts the address of clients in, such as
+    /// `x-forwarded-for`, used to limit failed logins per client. The address of the connection is
+    /// used when unset, so every client behind a proxy then shares the limit of the proxy.
+    client_ip_header: Option<HeaderName>,
 }
@@ -0,0 +1,60 @@
+//! Business gauges, refreshed from the database on each scrape.
+
+use once_cell::sync::Lazy;
+use prometheus::{register_int_gauge_vec, IntGaugeVec};
+
+use db::{registrations, sessions, Pool};
+use server::metrics::Hooks;
+
+static SESSIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!("sessions", "Sessions by current phase", &["state"]).unwrap()
+});
+
+static REGISTRATIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "session_registrations",
+        "Users registered to sessions, by current phase of the session",
+        &["state"]
+    )
+    .unwrap()
+});
+
+pub fn hooks(pool: Pool) -> Hooks {
+    let sessions_pool = pool.clone();
+    let registrations_pool = pool.clone();
+
+    Hooks::new()
+        .pool(pool)
+        .with("sessions", move || {
+            let pool = sessions_pool.clone();
+
+            async move {
+                let counts = sessions::count_by_state(&pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+
+                SESSIONS.reset();
+                for (state, count) in counts {
+                    SESSIONS.with_label_values(&[state.as_str()]).set(count);
+                }
+                Ok(())
+            }
+        })
+        .with("registrations", move || {
+            let pool = registrations_pool.clone();
+
+            async move {
+                let counts = registrations::count_by_state(&pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+
+                REGISTRATIONS.reset();
+                for (state, count) in counts {
+                    REGISTRATIONS
+                        .with_label_values(&[state.as_str()])
+                        .set(count);
+                }
+                Ok(())
+            }
+        })
+}
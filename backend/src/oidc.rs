@@ -0,0 +1,461 @@
+//! OpenID Connect authorization code flow (with PKCE) against a single identity provider.
+//!
+//! The provider metadata and signing keys are fetched on each login so that key rotations on the
+//! provider side are picked up without restarting.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    jwk::{AlgorithmParameters, EllipticCurve, Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+const DISCOVERY_PATH: &str = "/.well-known/openid-configuration";
+const SCOPES: &str = "openid email";
+const VERIFIER_LENGTH: usize = 64;
+
+#[derive(Clone)]
+pub struct Config {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: String,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Http(reqwest::Error),
+    Token(jsonwebtoken::errors::Error),
+    InvalidMetadata,
+    UnknownKey,
+    UnsupportedKey,
+    AlgorithmMismatch(Algorithm),
+    InvalidNonce,
+    UnverifiedEmail,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "request to the identity provider failed: {e}"),
+            Error::Token(e) => write!(f, "invalid id token: {e}"),
+            Error::InvalidMetadata => write!(f, "invalid provider metadata"),
+            Error::UnknownKey => write!(f, "id token signed with an unknown key"),
+            Error::UnsupportedKey => write!(f, "signing key of an unsupported type or algorithm"),
+            Error::AlgorithmMismatch(alg) => {
+                write!(
+                    f,
+                    "id token signed with {alg:?} instead of the key algorithm"
+                )
+            }
+            Error::InvalidNonce => write!(f, "id token nonce mismatch"),
+            Error::UnverifiedEmail => write!(f, "email missing or not verified"),
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for Error {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        Error::Token(e)
+    }
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    nonce: Option<String>,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+}
+
+#[derive(Clone)]
+pub struct Client {
+    config: Config,
+    http: reqwest::Client,
+}
+
+impl Client {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Builds the provider URL the user has to be sent to.
+    pub async fn authorization_url(
+        &self,
+        state: &str,
+        nonce: &str,
+        verifier: &str,
+    ) -> Result<String, Error> {
+        let metadata = self.metadata().await?;
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        let url = reqwest::Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.config.client_id),
+                ("redirect_uri", &self.config.redirect_uri),
+                ("scope", SCOPES),
+                ("state", state),
+                ("nonce", nonce),
+                ("code_challenge", &challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|_| Error::InvalidMetadata)?;
+
+        Ok(url.into())
+    }
+
+    /// Exchanges an authorization code and returns the verified email of the user.
+    pub async fn exchange(&self, code: &str, verifier: &str, nonce: &str) -> Result<String, Error> {
+        let metadata = self.metadata().await?;
+        let response: TokenResponse = self
+            .http
+            .post(&metadata.token_endpoint)
+            .basic_auth(&self.config.client_id, Some(&self.config.client_secret))
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.config.redirect_uri),
+                ("code_verifier", verifier),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let claims = self.verify(&response.id_token, &metadata).await?;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(Error::InvalidNonce);
+        }
+
+        match claims.email {
+            Some(email) if claims.email_verified => Ok(email),
+            _ => Err(Error::UnverifiedEmail),
+        }
+    }
+
+    async fn verify(&self, id_token: &str, metadata: &Metadata) -> Result<Claims, Error> {
+        let header = jsonwebtoken::decode_header(id_token)?;
+        let keys: JwkSet = self
+            .http
+            .get(&metadata.jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let jwk = match &header.kid {
+            Some(kid) => keys.find(kid),
+            None => keys.keys.first(),
+        }
+        .ok_or(Error::UnknownKey)?;
+        let alg = key_algorithm(jwk).ok_or(Error::UnsupportedKey)?;
+
+        if header.alg != alg {
+            return Err(Error::AlgorithmMismatch(header.alg));
+        }
+
+        let mut validation = Validation::new(alg);
+
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_issuer(&[&metadata.issuer]);
+
+        jsonwebtoken::decode(id_token, &DecodingKey::from_jwk(jwk)?, &validation)
+            .map(|data| data.claims)
+            .map_err(Error::Token)
+    }
+
+    async fn metadata(&self) -> Result<Metadata, Error> {
+        let url = format!(
+            "{}{DISCOVERY_PATH}",
+            self.config.issuer.trim_end_matches('/')
+        );
+
+        Ok(self
+            .http
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+}
+
+/// Signature algorithm of a key, its `alg` when the provider published one and otherwise the one
+/// its type implies, so that the unverified header of a token cannot pick it. Symmetric keys are
+/// refused, they would be known to anyone who can read the key set.
+fn key_algorithm(jwk: &Jwk) -> Option<Algorithm> {
+    let implied = match &jwk.algorithm {
+        AlgorithmParameters::RSA(_) => Algorithm::RS256,
+        AlgorithmParameters::EllipticCurve(params) => match params.curve {
+            EllipticCurve::P256 => Algorithm::ES256,
+            EllipticCurve::P384 => Algorithm::ES384,
+            _ => return None,
+        },
+        AlgorithmParameters::OctetKeyPair(_) => Algorithm::EdDSA,
+        AlgorithmParameters::OctetKey(_) => return None,
+    };
+    let alg = match jwk.common.key_algorithm {
+        Some(alg) => alg.to_string().parse().ok()?,
+        None => return Some(implied),
+    };
+
+    match (alg, implied) {
+        (
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512,
+            Algorithm::RS256,
+        ) => Some(alg),
+        (alg, implied) if alg == implied => Some(alg),
+        _ => None,
+    }
+}
+
+/// Random string used as a nonce or PKCE verifier.
+pub fn random_string() -> String {
+    rand::thread_rng()
+        .sample_iter(Alphanumeric)
+        .take(VERIFIER_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{jwk::Jwk, Algorithm, EncodingKey, Header};
+    use serde_json::json;
+
+    use super::{key_algorithm, Error};
+    use crate::testing::Provider;
+
+    const NONCE: &str = "nonce";
+    const EMAIL: &str = "user@provider.test";
+
+    #[tokio::test]
+    async fn authorization_url() {
+        let provider = Provider::start();
+
+        let url = provider
+            .client()
+            .authorization_url("state", NONCE, "verifier")
+            .await
+            .unwrap();
+        let url = reqwest::Url::parse(&url).unwrap();
+        let params: Vec<_> = url.query_pairs().collect();
+
+        assert_eq!(url.path(), "/authorize");
+        for (key, value) in [
+            ("client_id", Provider::CLIENT_ID),
+            ("state", "state"),
+            ("nonce", NONCE),
+            ("code_challenge_method", "S256"),
+        ] {
+            assert!(params.contains(&(key.into(), value.into())), "{key}");
+        }
+    }
+
+    #[tokio::test]
+    async fn exchange() {
+        let provider = Provider::start();
+
+        provider.sign(&provider.claims(NONCE, EMAIL));
+
+        let email = provider
+            .client()
+            .exchange("code", "verifier", NONCE)
+            .await
+            .unwrap();
+
+        assert_eq!(email, EMAIL);
+    }
+
+    #[tokio::test]
+    async fn exchange_invalid_code() {
+        let provider = Provider::start();
+
+        provider.sign(&provider.claims(NONCE, EMAIL));
+
+        let error = provider
+            .client()
+            .exchange("other", "verifier", NONCE)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, Error::Http(_)), "{error}");
+    }
+
+    #[tokio::test]
+    async fn invalid_claims() {
+        let provider = Provider::start();
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let cases = [
+            ("nonce", json!("other")),
+            ("aud", json!("other")),
+            ("iss", json!("http://other.test")),
+            ("exp", json!(now - 3600)),
+            ("email_verified", json!(false)),
+        ];
+
+        for (claim, value) in cases {
+            let mut claims = provider.claims(NONCE, EMAIL);
+
+            claims[claim] = value;
+            provider.sign(&claims);
+
+            let error = provider
+                .client()
+                .exchange("code", "verifier", NONCE)
+                .await
+                .unwrap_err();
+
+            assert!(
+                matches!(
+                    (claim, &error),
+                    ("nonce", Error::InvalidNonce)
+                        | ("aud" | "iss" | "exp", Error::Token(_))
+                        | ("email_verified", Error::UnverifiedEmail)
+                ),
+                "{claim}: {error}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn header_algorithm_not_trusted() {
+        let provider = Provider::start();
+        let mut header = Header::new(Algorithm::HS256);
+
+        header.kid = Some(String::from(Provider::KEY_ID));
+        provider.set_id_token(
+            jsonwebtoken::encode(
+                &header,
+                &provider.claims(NONCE, EMAIL),
+                &EncodingKey::from_secret(b"secret"),
+            )
+            .unwrap(),
+        );
+
+        let error = provider
+            .client()
+            .exchange("code", "verifier", NONCE)
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(error, Error::AlgorithmMismatch(Algorithm::HS256)),
+            "{error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_key() {
+        let provider = Provider::start();
+        let mut header = Header::new(Algorithm::HS256);
+
+        header.kid = Some(String::from("other"));
+        provider.set_id_token(
+            jsonwebtoken::encode(
+                &header,
+                &provider.claims(NONCE, EMAIL),
+                &EncodingKey::from_secret(b"secret"),
+            )
+            .unwrap(),
+        );
+
+        let error = provider
+            .client()
+            .exchange("code", "verifier", NONCE)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, Error::UnknownKey), "{error}");
+    }
+
+    fn jwk(value: serde_json::Value) -> Jwk {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn key_algorithm_from_key() {
+        let cases = [
+            (
+                json!({ "kty": "RSA", "n": "AQAB", "e": "AQAB" }),
+                Some(Algorithm::RS256),
+            ),
+            (
+                json!({ "kty": "EC", "crv": "P-384", "x": "AA", "y": "AA" }),
+                Some(Algorithm::ES384),
+            ),
+            (
+                json!({ "kty": "OKP", "crv": "Ed25519", "x": "AA" }),
+                Some(Algorithm::EdDSA),
+            ),
+            (json!({ "kty": "oct", "k": "c2VjcmV0" }), None),
+        ];
+
+        for (key, expected) in cases {
+            assert_eq!(key_algorithm(&jwk(key.clone())), expected, "{key}");
+        }
+    }
+
+    #[test]
+    fn key_algorithm_from_alg() {
+        let cases = [
+            (
+                json!({ "kty": "RSA", "alg": "PS512", "n": "AQAB", "e": "AQAB" }),
+                Some(Algorithm::PS512),
+            ),
+            (
+                json!({ "kty": "RSA", "alg": "ES256", "n": "AQAB", "e": "AQAB" }),
+                None,
+            ),
+            (
+                json!({ "kty": "RSA", "alg": "HS256", "n": "AQAB", "e": "AQAB" }),
+                None,
+            ),
+            (
+                json!({ "kty": "EC", "alg": "ES256", "crv": "P-384", "x": "AA", "y": "AA" }),
+                None,
+            ),
+            (
+                json!({ "kty": "oct", "alg": "HS256", "k": "c2VjcmV0" }),
+                None,
+            ),
+        ];
+
+        for (key, expected) in cases {
+            assert_eq!(key_algorithm(&jwk(key.clone())), expected, "{key}");
+        }
+    }
+}
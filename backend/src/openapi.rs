@@ -0,0 +1,222 @@
+//! OpenAPI description of the backend, served at `/openapi.json`.
+//!
+//! The committed `openapi.json` is checked against the generated document by the tests below,
+//! run them with `UPDATE_OPENAPI=1` to regenerate it after changing a route.
+
+use utoipa::{
+    openapi::security::{Http, HttpAuthScheme, SecurityScheme},
+    Modify, OpenApi,
+};
+
+use crate::{
+    controllers::images, controllers::sessions, controllers::users, response::error::Envelope,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "ImageMatch backend"),
+    paths(
+        users::create,
+        users::confirm,
+        users::unlock,
+        users::login,
+        users::logout,
+        users::enroll_totp,
+        users::enable_totp,
+        users::disable_totp,
+        users::oidc_login,
+        users::oidc_callback,
+        users::oidc_second_factor,
+        users::candidates,
+        sessions::create,
+        sessions::images,
+        sessions::list_images,
+        sessions::set_images,
+        sessions::add_images,
+        sessions::remove_images,
+        sessions::events,
+        images::library,
+        images::update,
+        images::tags,
+        images::duplicates,
+    ),
+    components(schemas(
+        Envelope,
+        users::CredentialModel,
+        users::TokenResponse,
+        users::OidcLoginResponse,
+        users::OidcCallbackModel,
+        users::OidcSecondFactorModel,
+        users::TotpEnrollment,
+        users::TotpCodeModel,
+        users::CandidateModel,
+        sessions::CreateModel,
+        sessions::ImagesModel,
+        sessions::ImageListModel,
+        sessions::ImagesReport,
+        sessions::ImageOutcome,
+        sessions::Outcome,
+        images::ImageModel,
+        images::SessionImageModel,
+        images::UpdateModel,
+        images::TagModel,
+        images::SortModel,
+        images::LibraryPage,
+        images::LibraryImageModel,
+        images::NearDuplicateModel,
+        sessions::AssociationModel,
+    )),
+    modifiers(&BearerAuth),
+    tags((name = "users"), (name = "sessions"), (name = "images")),
+)]
+pub struct ApiDoc;
+
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "bearer",
+                SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use utoipa::{openapi::PathItemType, OpenApi};
+    use warp::{http::StatusCode, Filter, Reply};
+
+    use super::ApiDoc;
+    use crate::testing;
+
+    const SPEC_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+
+    #[test]
+    fn spec_is_up_to_date() {
+        let generated = ApiDoc::openapi().to_pretty_json().unwrap() + "\n";
+
+        if std::env::var_os("UPDATE_OPENAPI").is_some() {
+            std::fs::write(SPEC_PATH, &generated).unwrap();
+        }
+
+        let committed = std::fs::read_to_string(SPEC_PATH).unwrap_or_default();
+
+        assert!(
+            committed == generated,
+            "openapi.json is out of date, rerun the tests with UPDATE_OPENAPI=1"
+        );
+    }
+
+    /// Routes deliberately left out of the document.
+    const UNDOCUMENTED: [(&str, &str); 1] = [("GET", "/openapi.json")];
+    const METHODS: [(&str, PathItemType); 5] = [
+        ("GET", PathItemType::Get),
+        ("POST", PathItemType::Post),
+        ("PUT", PathItemType::Put),
+        ("DELETE", PathItemType::Delete),
+        ("PATCH", PathItemType::Patch),
+    ];
+
+    /// Documented methods and paths, with `{id}` replaced by `1`.
+    fn documented() -> BTreeSet<(&'static str, String)> {
+        ApiDoc::openapi()
+            .paths
+            .paths
+            .into_iter()
+            .flat_map(|(path, item)| {
+                let path = path.replace("{id}", "1");
+
+                METHODS
+                    .iter()
+                    .filter(move |(_, method)| item.operations.contains_key(method))
+                    .map(move |(name, _)| (*name, path.clone()))
+            })
+            .collect()
+    }
+
+    /// Whether a route handled the request, rather than rejecting its path or method.
+    async fn routed(
+        routes: &(impl Filter<Extract = impl Reply> + Clone + 'static),
+        method: &str,
+        path: &str,
+    ) -> bool {
+        let response = warp::test::request()
+            .method(method)
+            .path(path)
+            .reply(routes)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap_or_default();
+
+        match response.status() {
+            StatusCode::METHOD_NOT_ALLOWED => false,
+            StatusCode::NOT_FOUND => body["code"] != "not_found",
+            _ => true,
+        }
+    }
+
+    #[tokio::test]
+    async fn documented_routes_exist() {
+        let routes = testing::routes(testing::offline_pool());
+
+        for (method, path) in documented() {
+            assert!(
+                routed(&routes, method, &path).await,
+                "{method} {path} is documented but not routed"
+            );
+        }
+    }
+
+    /// Requests every path made of the literal segments of the routes, up to the depth of the
+    /// documented ones, and checks that those handled are documented.
+    #[tokio::test]
+    async fn routes_are_documented() {
+        let routes = testing::routes(testing::offline_pool());
+        let documented = documented();
+        let sources = [
+            include_str!("routes/mod.rs"),
+            include_str!("routes/users.rs"),
+            include_str!("routes/sessions.rs"),
+            include_str!("routes/images.rs"),
+        ];
+        let segments: BTreeSet<_> = sources
+            .iter()
+            .flat_map(|source| source.split("warp::path(\"").skip(1))
+            .filter_map(|rest| rest.split_once('"').map(|(segment, _)| segment))
+            .chain(["1"])
+            .collect();
+        let depth = documented
+            .iter()
+            .map(|(_, path)| path.matches('/').count())
+            .max()
+            .unwrap();
+        let mut paths = vec![String::new()];
+
+        for _ in 0..depth {
+            paths = paths
+                .iter()
+                .flat_map(|path| {
+                    segments
+                        .iter()
+                        .map(move |segment| format!("{path}/{segment}"))
+                })
+                .collect();
+
+            for path in &paths {
+                for (method, _) in METHODS {
+                    let known = documented.contains(&(method, path.clone()))
+                        || UNDOCUMENTED.contains(&(method, path.as_str()));
+
+                    assert!(
+                        known || !routed(&routes, method, path).await,
+                        "{method} {path} is routed but not documented"
+                    );
+                }
+            }
+        }
+    }
+}
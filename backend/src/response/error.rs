@@ -1,26 +1,58 @@
+use std::collections::BTreeMap;
+
+use db::result::Error as DbError;
+use serde::Serialize;
+use serde_json::{json, Value};
+use server::request;
+use tracing::{debug, error};
+use utoipa::ToSchema;
 use warp::hyper::StatusCode;
 
 use super::{EmptyResponse, Response, ResponseBody};
 
 pub struct Error {
     body: Option<Body>,
+    kind: Option<&'static str>,
+    details: Option<Value>,
     code: Code,
 }
 
 pub type Body = String;
 
+/// Validation errors keyed by the name of the offending field.
+pub type FieldErrors = BTreeMap<&'static str, Vec<String>>;
+
+/// Body of every error response.
+#[derive(Serialize, ToSchema)]
+pub struct Envelope {
+    /// Stable identifier clients can branch on, unlike `message`.
+    #[schema(value_type = String, example = "invalid_credentials")]
+    code: &'static str,
+    message: Body,
+    #[schema(value_type = Option<Object>)]
+    details: Option<Value>,
+    /// Id of the request, also sent in the `x-request-id` header, to find it in the logs.
+    request_id: Option<String>,
+}
+
 #[derive(Clone, Copy)]
 pub enum Code {
     BadRequest,
     Forbidden,
     NotFound,
+    MethodNotAllowed,
     Conflict,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    TooManyRequests,
     Internal,
 }
 
 pub fn error() -> Error {
     Error {
         body: None,
+        kind: None,
+        details: None,
         code: Code::Internal,
     }
 }
@@ -36,6 +68,34 @@ impl Error {
             ..self
         }
     }
+
+    /// Overrides the error code sent to clients, which defaults to one derived from the status.
+    pub fn kind(self, kind: &'static str) -> Self {
+        Self {
+            kind: Some(kind),
+            ..self
+        }
+    }
+
+    pub fn details(self, details: Value) -> Self {
+        Self {
+            details: Some(details),
+            ..self
+        }
+    }
+
+    pub fn fields(self, fields: FieldErrors) -> Self {
+        self.kind("invalid_fields").details(json!(fields))
+    }
+
+    fn into_envelope(self) -> Envelope {
+        Envelope {
+            code: self.kind.unwrap_or_else(|| self.code.kind()),
+            message: self.body.unwrap_or_else(|| self.code.as_str().to_string()),
+            details: self.details,
+            request_id: request::id(),
+        }
+    }
 }
 
 impl<T> From<Error> for Response<T>
@@ -43,42 +103,112 @@ where
     T: ResponseBody,
 {
     fn from(error: Error) -> Self {
-        let body = Err(error
-            .body
-            .unwrap_or_else(|| error.code.as_str().to_string()));
+        let code = error.code.into();
 
         Self {
-            body,
-            code: error.code.into(),
+            body: Err(error.into_envelope()),
+            code,
         }
     }
 }
 
 impl From<Error> for EmptyResponse {
     fn from(error: Error) -> Self {
-        let message = Some(
-            error
-                .body
-                .unwrap_or_else(|| error.code.as_str().to_string()),
-        );
+        let code = error.code.into();
 
         Self {
-            error: message,
-            code: error.code.into(),
+            error: Some(error.into_envelope()),
+            code,
         }
     }
 }
 
+/// Maps database errors to a status and the stable code of the error, hiding internal ones.
+impl From<DbError> for Error {
+    fn from(err: DbError) -> Self {
+        let code = match err {
+            DbError::InvalidUserId => Code::NotFound,
+            DbError::InvalidToken
+            | DbError::InvalidCredentials
+            | DbError::AccountLocked
+            | DbError::SecondFactorRequired
+            | DbError::InvalidSecondFactor => Code::Forbidden,
+            DbError::TooManyAttempts => Code::TooManyRequests,
+            DbError::SecondFactorAlreadyEnabled | DbError::DuplicateEmail => Code::Conflict,
+            DbError::InvalidOidcState
+            | DbError::InvalidLoginChallenge
+            | DbError::InvalidImageAssociation
+            | DbError::InvalidImage
+            | DbError::InvalidSession
+            | DbError::InvalidDates => Code::BadRequest,
+            DbError::UnknownForeignKey | DbError::Sqlx { .. } => {
+                error!("{err}");
+
+                return error();
+            }
+        };
+
+        debug!("{err}");
+
+        error()
+            .with_status(code)
+            .kind(err.code())
+            .body(capitalize(&err.to_string()))
+    }
+}
+
+impl<T> From<DbError> for Response<T>
+where
+    T: ResponseBody,
+{
+    fn from(err: DbError) -> Self {
+        Error::from(err).into()
+    }
+}
+
+impl From<DbError> for EmptyResponse {
+    fn from(err: DbError) -> Self {
+        Error::from(err).into()
+    }
+}
+
+fn capitalize(message: &str) -> String {
+    let mut chars = message.chars();
+
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
 impl Code {
     fn as_str(self) -> &'static str {
         match self {
             Code::BadRequest => "Bad Request",
             Code::Forbidden => "Forbidden",
             Code::NotFound => "Not Found",
+            Code::MethodNotAllowed => "Method Not Allowed",
             Code::Conflict => "Conflict",
+            Code::PayloadTooLarge => "Payload Too Large",
+            Code::UnsupportedMediaType => "Unsupported Media Type",
+            Code::TooManyRequests => "Too Many Requests",
             Code::Internal => "Internal Server Error",
         }
     }
+
+    fn kind(self) -> &'static str {
+        match self {
+            Code::BadRequest => "bad_request",
+            Code::Forbidden => "forbidden",
+            Code::NotFound => "not_found",
+            Code::MethodNotAllowed => "method_not_allowed",
+            Code::Conflict => "conflict",
+            Code::PayloadTooLarge => "payload_too_large",
+            Code::UnsupportedMediaType => "unsupported_media_type",
+            Code::TooManyRequests => "too_many_requests",
+            Code::Internal => "internal",
+        }
+    }
 }
 
 impl From<Code> for StatusCode {
@@ -87,7 +217,11 @@ impl From<Code> for StatusCode {
             Code::BadRequest => StatusCode::BAD_REQUEST,
             Code::Forbidden => StatusCode::FORBIDDEN,
             Code::NotFound => StatusCode::NOT_FOUND,
+            Code::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
             Code::Conflict => StatusCode::CONFLICT,
+            Code::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Code::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Code::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
             Code::Internal => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -18,12 +18,12 @@ pub struct Response<T>
 where
     T: ResponseBody,
 {
-    body: Result<T, error::Body>,
+    body: Result<T, error::Envelope>,
     code: StatusCode,
 }
 
 pub struct EmptyResponse {
-    error: Option<error::Body>,
+    error: Option<error::Envelope>,
     code: StatusCode,
 }
 
@@ -0,0 +1,88 @@
+use warp::{Filter, Rejection};
+
+use db::{images, Pool};
+
+use crate::{controllers, extractors};
+
+pub fn router(
+    pool: Pool,
+    require_admin_totp: bool,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    warp::path("images").and(
+        library(pool.clone(), require_admin_totp)
+            .or(tags(pool.clone(), require_admin_totp))
+            .or(duplicates(pool.clone(), require_admin_totp))
+            .or(update(pool, require_admin_totp)),
+    )
+}
+
+pub fn library(
+    pool: Pool,
+    require_admin_totp: bool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    let auth_pool = pool.clone();
+
+    warp::path::end()
+        .and(warp::get())
+        .and(warp::query())
+        .and(warp::any().map(move || pool.clone()))
+        .and(extractors::auth::admin_auth_filter(
+            auth_pool,
+            require_admin_totp,
+        ))
+        .then(controllers::images::library)
+}
+
+pub fn update(
+    pool: Pool,
+    require_admin_totp: bool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    let auth_pool = pool.clone();
+
+    warp::path::param()
+        .map(images::Id)
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(warp::body::json())
+        .and(warp::any().map(move || pool.clone()))
+        .and(extractors::auth::admin_auth_filter(
+            auth_pool,
+            require_admin_totp,
+        ))
+        .then(controllers::images::update)
+}
+
+pub fn duplicates(
+    pool: Pool,
+    require_admin_totp: bool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    let auth_pool = pool.clone();
+
+    warp::path("duplicates")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query())
+        .and(warp::any().map(move || pool.clone()))
+        .and(extractors::auth::admin_auth_filter(
+            auth_pool,
+            require_admin_totp,
+        ))
+        .then(controllers::images::duplicates)
+}
+
+pub fn tags(
+    pool: Pool,
+    require_admin_totp: bool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    let auth_pool = pool.clone();
+
+    warp::path("tags")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::any().map(move || pool.clone()))
+        .and(extractors::auth::admin_auth_filter(
+            auth_pool,
+            require_admin_totp,
+        ))
+        .then(controllers::images::tags)
+}
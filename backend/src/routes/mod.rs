@@ -1,38 +1,123 @@
-use log::error;
+use server::{
+    cors,
+    health::{self, Checks},
+};
+use tracing::{debug, error};
+use utoipa::OpenApi;
 use warp::{
-    filters::body::BodyDeserializeError, hyper::StatusCode, reply, Filter, Rejection, Reply,
+    filters::body::BodyDeserializeError,
+    http::header::HeaderName,
+    reject::{
+        InvalidQuery, MethodNotAllowed, MissingHeader, PayloadTooLarge, UnsupportedMediaType,
+    },
+    Filter, Rejection, Reply,
 };
 
-use crate::extractors::{auth::InvalidToken, InternalError};
+use crate::{
+    controllers::{images::ImageUrls, users::LoginPolicy},
+    events::Hub,
+    extractors::{auth::InvalidToken, InternalError},
+    oidc,
+    openapi::ApiDoc,
+    response::{error, EmptyResponse},
+    validation::PasswordPolicy,
+};
 
+mod images;
 mod sessions;
 mod users;
 
-pub fn routes(pool: db::Pool) -> impl Filter<Extract = impl warp::Reply> + Clone {
-    users::router(pool.clone())
-        .or(sessions::router(pool))
-        .recover(handle_rejection)
+#[allow(clippy::too_many_arguments)]
+pub fn routes(
+    pool: db::Pool,
+    policy: LoginPolicy,
+    require_admin_totp: bool,
+    oidc: Option<oidc::Client>,
+    password_policy: PasswordPolicy,
+    cors: &cors::Policy,
+    hub: Hub,
+    image_urls: ImageUrls,
+    client_ip_header: Option<HeaderName>,
+) -> impl Filter<Extract = impl warp::Reply> + Clone {
+    users::router(
+        pool.clone(),
+        policy,
+        require_admin_totp,
+        oidc,
+        password_policy,
+        client_ip_header,
+    )
+    .or(sessions::router(
+        pool.clone(),
+        hub,
+        image_urls,
+        require_admin_totp,
+    ))
+    .or(images::router(pool.clone(), require_admin_totp))
+    .or(openapi())
+    .or(health::routes(Checks::new().database(pool.clone())))
+    .or(server::metrics::routes(crate::metrics::hooks(pool)))
+    .recover(handle_rejection)
+    .with(cors.filter())
+    .with(server::metrics::http(
+        ApiDoc::openapi()
+            .paths
+            .paths
+            .into_keys()
+            .chain([String::from("/openapi.json")]),
+    ))
+}
+
+fn openapi() -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    warp::path("openapi.json")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(|| warp::reply::json(&ApiDoc::openapi()))
 }
 
 async fn handle_rejection(err: Rejection) -> Result<impl Reply, std::convert::Infallible> {
-    error!("{err:?}");
+    debug!(?err, "Request rejected");
 
-    if err.is_not_found() {
-        Ok(reply::with_status("NOT_FOUND", StatusCode::NOT_FOUND))
+    let response = if err.is_not_found() {
+        error().with_status(error::Code::NotFound)
     } else if err.find::<InvalidToken>().is_some() {
-        Ok(reply::with_status("Invalid Token", StatusCode::FORBIDDEN))
-    } else if err.find::<BodyDeserializeError>().is_some() {
-        Ok(reply::with_status("BAD_REQUEST", StatusCode::BAD_REQUEST))
+        invalid_token("Invalid token")
+    } else if let Some(e) = err.find::<MissingHeader>() {
+        match e.name() {
+            "Authorization" => invalid_token("Missing token"),
+            _ => error()
+                .with_status(error::Code::BadRequest)
+                .body(e.to_string()),
+        }
+    } else if let Some(e) = err.find::<BodyDeserializeError>() {
+        error()
+            .with_status(error::Code::BadRequest)
+            .kind("invalid_body")
+            .body(e.to_string())
+    } else if let Some(e) = err.find::<InvalidQuery>() {
+        error()
+            .with_status(error::Code::BadRequest)
+            .kind("invalid_query")
+            .body(e.to_string())
+    } else if err.find::<MethodNotAllowed>().is_some() {
+        error().with_status(error::Code::MethodNotAllowed)
+    } else if err.find::<PayloadTooLarge>().is_some() {
+        error().with_status(error::Code::PayloadTooLarge)
+    } else if err.find::<UnsupportedMediaType>().is_some() {
+        error().with_status(error::Code::UnsupportedMediaType)
     } else if err.find::<InternalError>().is_some() {
-        Ok(reply::with_status(
-            "INTERNAL_SERVER_ERROR",
-            StatusCode::INTERNAL_SERVER_ERROR,
-        ))
+        error()
     } else {
-        eprintln!("unhandled rejection: {:?}", err);
-        Ok(reply::with_status(
-            "INTERNAL_SERVER_ERROR",
-            StatusCode::INTERNAL_SERVER_ERROR,
-        ))
-    }
+        error!(?err, "Unhandled rejection");
+        error()
+    };
+
+    Ok(EmptyResponse::from(response))
+}
+
+fn invalid_token(message: &str) -> error::Error {
+    error()
+        .with_status(error::Code::Forbidden)
+        .kind(db::result::Error::InvalidToken.code())
+        .body(message.to_string())
 }
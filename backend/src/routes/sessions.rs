@@ -1,11 +1,26 @@
 use warp::{Filter, Rejection};
 
-use db::Pool;
+use db::{sessions, Pool};
 
-use crate::controllers;
+use crate::{
+    controllers::{self, images::ImageUrls},
+    events::Hub,
+    extractors,
+};
 
-pub fn router(pool: Pool) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
-    warp::path("sessions").and(create(pool.clone()).or(images(pool)))
+pub fn router(
+    pool: Pool,
+    hub: Hub,
+    urls: ImageUrls,
+    require_admin_totp: bool,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    warp::path("sessions").and(
+        create(pool.clone())
+            .or(images(pool.clone()))
+            .or(list_images(pool.clone(), urls, require_admin_totp))
+            .or(batch_images(pool.clone(), require_admin_totp))
+            .or(events(pool, hub)),
+    )
 }
 
 pub fn create(pool: Pool) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
@@ -24,3 +39,220 @@ pub fn images(pool: Pool) -> impl Filter<Extract = (impl warp::Reply,), Error =
         .and(warp::any().map(move || pool.clone()))
         .then(controllers::sessions::images)
 }
+
+pub fn list_images(
+    pool: Pool,
+    urls: ImageUrls,
+    require_admin_totp: bool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    let auth_pool = pool.clone();
+
+    warp::path::param()
+        .map(sessions::Id)
+        .and(warp::path("images"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(extractors::auth::optional_auth_filter(auth_pool))
+        .and(warp::any().map(move || pool.clone()))
+        .and(warp::any().map(move || urls.clone()))
+        .and(warp::any().map(move || require_admin_totp))
+        .then(controllers::sessions::list_images)
+}
+
+/// `PUT`, `POST` and `DELETE` on `{id}/images` set, add and remove a list of images.
+pub fn batch_images(
+    pool: Pool,
+    require_admin_totp: bool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    let batch = warp::path::param()
+        .map(sessions::Id)
+        .and(warp::path("images"))
+        .and(warp::path::end());
+    let auth = extractors::auth::admin_auth_filter(pool.clone(), require_admin_totp);
+    let pool = warp::any().map(move || pool.clone());
+
+    warp::put()
+        .and(batch)
+        .and(warp::body::json())
+        .and(pool.clone())
+        .and(auth.clone())
+        .then(controllers::sessions::set_images)
+        .or(warp::post()
+            .and(batch)
+            .and(warp::body::json())
+            .and(pool.clone())
+            .and(auth.clone())
+            .then(controllers::sessions::add_images))
+        .unify()
+        .or(warp::delete()
+            .and(batch)
+            .and(warp::body::json())
+            .and(pool)
+            .and(auth)
+            .then(controllers::sessions::remove_images))
+        .unify()
+}
+
+pub fn events(
+    pool: Pool,
+    hub: Hub,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    warp::path::param()
+        .map(sessions::Id)
+        .and(warp::path("events"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(extractors::auth::auth_filter(pool.clone()))
+        .and(warp::any().map(move || hub.clone()))
+        .and(warp::any().map(move || pool.clone()))
+        .then(controllers::sessions::events)
+}
+
+#[cfg(test)]
+mod tests {
+    use db::{images, images_associations, sessions};
+    use serde_json::{json, Value};
+    use time::{Duration, OffsetDateTime};
+    use warp::http::StatusCode;
+
+    use crate::testing::{self, User};
+
+    #[tokio::test]
+    async fn batch_images_require_an_admin() {
+        let pool = testing::connect().await;
+        let routes = testing::routes(pool.clone());
+        let user = User::create(false, &pool).await;
+        let admin = User::create(true, &pool).await;
+
+        for method in ["PUT", "POST", "DELETE"] {
+            let anonymous = warp::test::request()
+                .method(method)
+                .path("/sessions/1/images")
+                .json(&json!({ "images": [1] }))
+                .reply(&routes)
+                .await;
+            let not_admin = warp::test::request()
+                .method(method)
+                .path("/sessions/1/images")
+                .header("authorization", user.bearer())
+                .json(&json!({ "images": [1] }))
+                .reply(&routes)
+                .await;
+
+            assert_eq!(
+                testing::error(&anonymous),
+                (StatusCode::FORBIDDEN, String::from("invalid_token")),
+                "anonymous {method}"
+            );
+            assert_eq!(
+                testing::error(&not_admin),
+                (StatusCode::FORBIDDEN, String::from("invalid_token")),
+                "non-admin {method}"
+            );
+
+            let unknown = warp::test::request()
+                .method(method)
+                .path("/sessions/-1/images")
+                .header("authorization", admin.bearer())
+                .json(&json!({ "images": [1] }))
+                .reply(&routes)
+                .await;
+
+            assert_eq!(unknown.status(), StatusCode::NOT_FOUND, "admin {method}");
+        }
+
+        user.delete(&pool).await;
+        admin.delete(&pool).await;
+    }
+
+    #[tokio::test]
+    async fn list_images_hides_private_images() {
+        let pool = testing::connect().await;
+        let routes = testing::routes(pool.clone());
+        let user = User::create(false, &pool).await;
+        let admin = User::create(true, &pool).await;
+        let now = OffsetDateTime::now_utc();
+        let session = sessions::create(
+            "list_images",
+            now + Duration::days(1),
+            now + Duration::days(2),
+            now + Duration::days(3),
+            &pool,
+        )
+        .await
+        .unwrap();
+        let public = images::create(&pool).await.unwrap();
+        let private = images::create(&pool).await.unwrap();
+
+        images::set_public(public, true, &pool).await.unwrap();
+        for image in [public, private] {
+            images_associations::create(image, session, &pool)
+                .await
+                .unwrap();
+        }
+
+        let path = format!("/sessions/{}/images", session.0);
+        let listed = |authorization: Option<String>| {
+            let mut request = warp::test::request().method("GET").path(&path);
+
+            if let Some(authorization) = authorization {
+                request = request.header("authorization", authorization);
+            }
+
+            async {
+                let response = request.reply(&routes).await;
+                let body: Value = serde_json::from_slice(response.body()).unwrap();
+
+                body.as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|image| image["id"].as_i64().unwrap() as i32)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(listed(None).await, [public.0]);
+        assert_eq!(listed(Some(user.bearer())).await, [public.0]);
+        assert_eq!(listed(Some(admin.bearer())).await, [public.0, private.0]);
+
+        let unknown = warp::test::request()
+            .method("GET")
+            .path("/sessions/-1/images")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(
+            testing::error(&unknown),
+            (StatusCode::NOT_FOUND, String::from("invalid_session"))
+        );
+
+        sqlx::query("delete from sessions where id=$1")
+            .bind(session.0)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("delete from images where id=any($1)")
+            .bind(vec![public.0, private.0])
+            .execute(&pool)
+            .await
+            .unwrap();
+        user.delete(&pool).await;
+        admin.delete(&pool).await;
+    }
+
+    #[tokio::test]
+    async fn events_require_a_token() {
+        let routes = testing::routes(testing::offline_pool());
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/sessions/1/events")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(
+            testing::error(&response),
+            (StatusCode::FORBIDDEN, String::from("invalid_token"))
+        );
+    }
+}
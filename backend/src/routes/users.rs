@@ -1,29 +1,47 @@
-use warp::{Filter, Rejection};
+use warp::{http::header::HeaderName, Filter, Rejection};
 
 use db::{users, Pool};
+use server::request::client_ip;
 
-use crate::{controllers, extractors};
+use crate::{
+    controllers, controllers::users::LoginPolicy, extractors, oidc, validation::PasswordPolicy,
+};
 
-pub fn router(pool: Pool) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+pub fn router(
+    pool: Pool,
+    policy: LoginPolicy,
+    require_admin_totp: bool,
+    oidc_client: Option<oidc::Client>,
+    password_policy: PasswordPolicy,
+    client_ip_header: Option<HeaderName>,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
     warp::path("users").and(
-        create(pool.clone())
-            .or(confirm(pool.clone()))
-            .or(login(pool.clone()))
+        create(pool.clone(), password_policy)
+            .or(confirm(pool.clone(), require_admin_totp))
+            .or(unlock(pool.clone(), require_admin_totp))
+            .or(login(pool.clone(), policy, client_ip_header.clone()))
             .or(logout(pool.clone()))
-            .or(candidates(pool)),
+            .or(totp(pool.clone()))
+            .or(oidc(pool.clone(), policy, oidc_client, client_ip_header))
+            .or(candidates(pool, require_admin_totp)),
     )
 }
 
-pub fn create(pool: Pool) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+pub fn create(
+    pool: Pool,
+    policy: PasswordPolicy,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
     warp::post()
         .and(warp::path::end())
         .and(warp::body::json())
+        .and(warp::any().map(move || policy))
         .and(warp::any().map(move || pool.clone()))
         .then(controllers::users::create)
 }
 
 pub fn confirm(
     pool: Pool,
+    require_admin_totp: bool,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
     let auth_pool = pool.clone();
 
@@ -32,15 +50,42 @@ pub fn confirm(
         .and(warp::post())
         .and(warp::path::end())
         .and(warp::any().map(move || pool.clone()))
-        .and(extractors::auth::admin_auth_filter(auth_pool))
+        .and(extractors::auth::admin_auth_filter(
+            auth_pool,
+            require_admin_totp,
+        ))
         .then(controllers::users::confirm)
 }
 
-pub fn login(pool: Pool) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+pub fn unlock(
+    pool: Pool,
+    require_admin_totp: bool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    let auth_pool = pool.clone();
+
+    warp::path("unlock")
+        .and(warp::path::param().map(users::Id))
+        .and(warp::post())
+        .and(warp::path::end())
+        .and(warp::any().map(move || pool.clone()))
+        .and(extractors::auth::admin_auth_filter(
+            auth_pool,
+            require_admin_totp,
+        ))
+        .then(controllers::users::unlock)
+}
+
+pub fn login(
+    pool: Pool,
+    policy: LoginPolicy,
+    client_ip_header: Option<HeaderName>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
     warp::path("login")
         .and(warp::path::end())
         .and(warp::post())
         .and(warp::body::json())
+        .and(client_ip(client_ip_header))
+        .and(warp::any().map(move || policy))
         .and(warp::any().map(move || pool.clone()))
         .then(controllers::users::login)
 }
@@ -56,6 +101,7 @@ pub fn logout(pool: Pool) -> impl Filter<Extract = (impl warp::Reply,), Error =
 
 pub fn candidates(
     pool: Pool,
+    require_admin_totp: bool,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
     let auth_pool = pool.clone();
 
@@ -63,6 +109,262 @@ pub fn candidates(
         .and(warp::path::end())
         .and(warp::post())
         .and(warp::any().map(move || pool.clone()))
-        .and(extractors::auth::admin_auth_filter(auth_pool))
+        .and(extractors::auth::admin_auth_filter(
+            auth_pool,
+            require_admin_totp,
+        ))
         .then(controllers::users::candidates)
 }
+
+pub fn totp(pool: Pool) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    warp::path("totp").and(
+        enroll_totp(pool.clone())
+            .or(enable_totp(pool.clone()))
+            .or(disable_totp(pool)),
+    )
+}
+
+pub fn enroll_totp(
+    pool: Pool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    warp::path::end()
+        .and(warp::post())
+        .and(extractors::auth::auth_filter(pool.clone()))
+        .and(warp::any().map(move || pool.clone()))
+        .then(controllers::users::enroll_totp)
+}
+
+pub fn enable_totp(
+    pool: Pool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    warp::path("enable")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(extractors::auth::auth_filter(pool.clone()))
+        .and(warp::any().map(move || pool.clone()))
+        .then(controllers::users::enable_totp)
+}
+
+pub fn disable_totp(
+    pool: Pool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    warp::path("disable")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(extractors::auth::auth_filter(pool.clone()))
+        .and(warp::any().map(move || pool.clone()))
+        .then(controllers::users::disable_totp)
+}
+
+pub fn oidc(
+    pool: Pool,
+    policy: LoginPolicy,
+    client: Option<oidc::Client>,
+    client_ip_header: Option<HeaderName>,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    warp::path("oidc").and(
+        oidc_login(pool.clone(), client.clone())
+            .or(oidc_callback(pool.clone(), policy, client))
+            .or(oidc_second_factor(pool, policy, client_ip_header)),
+    )
+}
+
+pub fn oidc_login(
+    pool: Pool,
+    client: Option<oidc::Client>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    warp::path("login")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::any().map(move || client.clone()))
+        .and(warp::any().map(move || pool.clone()))
+        .then(controllers::users::oidc_login)
+}
+
+pub fn oidc_callback(
+    pool: Pool,
+    policy: LoginPolicy,
+    client: Option<oidc::Client>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    warp::path("callback")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::any().map(move || client.clone()))
+        .and(warp::any().map(move || policy))
+        .and(warp::any().map(move || pool.clone()))
+        .then(controllers::users::oidc_callback)
+}
+
+pub fn oidc_second_factor(
+    pool: Pool,
+    policy: LoginPolicy,
+    client_ip_header: Option<HeaderName>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    warp::path("second-factor")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(client_ip(client_ip_header))
+        .and(warp::any().map(move || policy))
+        .and(warp::any().map(move || pool.clone()))
+        .then(controllers::users::oidc_second_factor)
+}
+
+#[cfg(test)]
+mod tests {
+    use db::{
+        login_attempts::{self, Backoff, Scope},
+        users,
+    };
+    use serde_json::{json, Value};
+    use totp_rs::{Algorithm, Secret, TOTP};
+    use warp::http::StatusCode;
+
+    use crate::{
+        controllers::users::LoginPolicy,
+        testing::{self, Provider, User},
+        totp,
+    };
+
+    #[tokio::test]
+    async fn locked_accounts_refuse_any_password() {
+        let pool = testing::connect().await;
+        let backoff = Backoff {
+            threshold: 100,
+            ..testing::login_policy().backoff
+        };
+        let policy = LoginPolicy {
+            backoff,
+            lockout_threshold: 2,
+            lockout_duration: 3600.,
+        };
+        let routes = super::login(pool.clone(), policy, None);
+        let user = User::create(false, &pool).await;
+        let login = |password: &str| {
+            warp::test::request()
+                .method("POST")
+                .path("/login")
+                .json(&json!({ "email": user.email, "password": password }))
+                .reply(&routes)
+        };
+        let invalid = (StatusCode::FORBIDDEN, String::from("invalid_credentials"));
+
+        assert_eq!(testing::error(&login("wrong").await), invalid);
+        assert_eq!(testing::error(&login("wrong").await), invalid);
+        assert!(users::locked(user.id, &pool).await.unwrap());
+        assert_eq!(testing::error(&login("password").await), invalid);
+
+        // The failures leading to the lock still slow down the next attempts.
+        let slowed = Backoff {
+            threshold: 2,
+            ..backoff
+        };
+        assert!(
+            login_attempts::check(Scope::Email, &user.email, slowed, &pool)
+                .await
+                .is_err()
+        );
+
+        login_attempts::clear(Scope::Email, &user.email, &pool)
+            .await
+            .unwrap();
+        user.delete(&pool).await;
+    }
+
+    #[tokio::test]
+    async fn oidc_login_applies_the_second_factor_and_lockout() {
+        let pool = testing::connect().await;
+        let provider = Provider::start();
+        let routes = testing::routes_with_oidc(pool.clone(), Some(provider.client()));
+        let user = User::create(false, &pool).await;
+        let secret = totp::generate_secret();
+
+        users::set_totp_secret(user.id, &secret, &pool)
+            .await
+            .unwrap();
+        users::enable_totp(user.id, &pool).await.unwrap();
+
+        let callback = || async {
+            let login = warp::test::request()
+                .method("POST")
+                .path("/users/oidc/login")
+                .reply(&routes)
+                .await;
+            let login: Value = serde_json::from_slice(login.body()).unwrap();
+            let url = reqwest::Url::parse(login["url"].as_str().unwrap()).unwrap();
+            let param = |name| {
+                url.query_pairs()
+                    .find(|(key, _)| key == name)
+                    .unwrap()
+                    .1
+                    .into_owned()
+            };
+
+            provider.sign(&provider.claims(&param("nonce"), &user.email));
+
+            warp::test::request()
+                .method("POST")
+                .path("/users/oidc/callback")
+                .json(&json!({ "code": "code", "state": param("state") }))
+                .reply(&routes)
+                .await
+        };
+        let second_factor = |challenge: &str, code: &str| {
+            warp::test::request()
+                .method("POST")
+                .path("/users/oidc/second-factor")
+                .json(&json!({ "challenge": challenge, "totp": code }))
+                .reply(&routes)
+        };
+
+        let response = callback().await;
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        let challenge = body["details"]["challenge"].as_str().unwrap();
+
+        assert_eq!(
+            testing::error(&response),
+            (
+                StatusCode::FORBIDDEN,
+                String::from("second_factor_required")
+            )
+        );
+        assert_eq!(
+            testing::error(&second_factor(challenge, "not a code").await),
+            (StatusCode::FORBIDDEN, String::from("invalid_second_factor"))
+        );
+
+        let code = TOTP::new_unchecked(
+            Algorithm::SHA1,
+            6,
+            1,
+            30,
+            Secret::Encoded(secret).to_bytes().unwrap(),
+            None,
+            String::new(),
+        )
+        .generate_current()
+        .unwrap();
+        let response = second_factor(challenge, &code).await;
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(
+            testing::error(&second_factor(challenge, &code).await),
+            (
+                StatusCode::BAD_REQUEST,
+                String::from("invalid_login_challenge")
+            )
+        );
+
+        users::lock(&user.email, 3600., &pool).await.unwrap();
+
+        assert_eq!(
+            testing::error(&callback().await),
+            (StatusCode::FORBIDDEN, String::from("account_locked"))
+        );
+
+        user.delete(&pool).await;
+    }
+}
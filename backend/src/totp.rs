@@ -0,0 +1,174 @@
+//! Time-based one-time passwords (RFC 6238) used as a second authentication factor.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use db::{recovery_codes, result::DbResult, users, Pool};
+use rand::{distributions::Alphanumeric, Rng};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+const ISSUER: &str = "ImageMatch";
+const STEP: u64 = 30;
+/// Steps accepted on each side of the current one, for clocks running late or early.
+const SKEW: u64 = 1;
+const RECOVERY_CODES: usize = 10;
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+/// Generates a new base32 encoded secret.
+pub fn generate_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+/// Builds the `otpauth://` URI to be rendered as a QR code by authenticator apps.
+pub fn provisioning_uri(secret: &str, email: &str) -> Option<String> {
+    totp(secret, email).map(|totp| totp.get_url())
+}
+
+/// Time step of a currently valid code, to be recorded with [`users::use_totp_step`].
+pub fn verify(secret: &str, code: &str) -> Option<i64> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+
+    verify_at(secret, code, now)
+}
+
+fn verify_at(secret: &str, code: &str, time: u64) -> Option<i64> {
+    let totp = totp(secret, "")?;
+    let current = time / STEP;
+
+    (current.saturating_sub(SKEW)..=current + SKEW)
+        .find(|step| totp.check(code.trim(), step * STEP))
+        .and_then(|step| step.try_into().ok())
+}
+
+/// Checks `code` against the TOTP secret of a user, then against their recovery codes.
+///
+/// A TOTP code is refused unless its step is newer than the last one accepted, so that a code
+/// cannot be replayed while it is still valid, and recovery codes are deleted once used.
+pub async fn check(id: users::Id, secret: &str, code: &str, db: &Pool) -> DbResult<()> {
+    match verify(secret, code) {
+        Some(step) => users::use_totp_step(id, step, db).await,
+        None => recovery_codes::consume(id, code, db).await,
+    }
+}
+
+pub fn recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            rand::thread_rng()
+                .sample_iter(Alphanumeric)
+                .take(RECOVERY_CODE_LENGTH)
+                .map(char::from)
+                .collect()
+        })
+        .collect()
+}
+
+fn totp(secret: &str, email: &str) -> Option<TOTP> {
+    let secret = Secret::Encoded(secret.to_string()).to_bytes().ok()?;
+
+    Some(TOTP::new_unchecked(
+        Algorithm::SHA1,
+        6,
+        0,
+        STEP,
+        secret,
+        Some(ISSUER.to_string()),
+        email.to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use db::{recovery_codes, result::Error};
+
+    use super::{check, generate_secret, totp, verify, verify_at, STEP};
+    use crate::testing::{self, User};
+
+    const TIME: u64 = 1_700_000_000;
+
+    fn code(secret: &str, time: u64) -> String {
+        totp(secret, "").unwrap().generate(time)
+    }
+
+    #[test]
+    fn valid_code() {
+        let secret = generate_secret();
+
+        assert_eq!(
+            verify_at(&secret, &code(&secret, TIME), TIME),
+            Some((TIME / STEP) as i64)
+        );
+        assert_eq!(
+            verify_at(&secret, &format!(" {} ", code(&secret, TIME)), TIME),
+            Some((TIME / STEP) as i64)
+        );
+        assert_eq!(verify_at(&secret, "abcdef", TIME), None);
+        assert_eq!(verify_at("not base32!", "123456", TIME), None);
+    }
+
+    #[test]
+    fn skew() {
+        let secret = generate_secret();
+        let cases = [
+            (TIME - 2 * STEP, None),
+            (TIME - STEP, Some((TIME / STEP - 1) as i64)),
+            (TIME + STEP, Some((TIME / STEP + 1) as i64)),
+            (TIME + 2 * STEP, None),
+        ];
+
+        for (time, expected) in cases {
+            assert_eq!(
+                verify_at(&secret, &code(&secret, time), TIME),
+                expected,
+                "{time}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn replayed_code() {
+        let pool = testing::connect().await;
+        let user = User::create(false, &pool).await;
+        let secret = generate_secret();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let current = code(&secret, now);
+
+        assert!(verify(&secret, &current).is_some());
+        check(user.id, &secret, &current, &pool).await.unwrap();
+        assert!(matches!(
+            check(user.id, &secret, &current, &pool).await.unwrap_err(),
+            Error::InvalidSecondFactor
+        ));
+        assert!(matches!(
+            check(user.id, &secret, &code(&secret, now - STEP), &pool)
+                .await
+                .unwrap_err(),
+            Error::InvalidSecondFactor
+        ));
+
+        user.delete(&pool).await;
+    }
+
+    #[tokio::test]
+    async fn recovery_code_consumed_once() {
+        let pool = testing::connect().await;
+        let user = User::create(false, &pool).await;
+        let secret = generate_secret();
+
+        recovery_codes::replace(user.id, &[String::from("recovery")], &pool)
+            .await
+            .unwrap();
+        check(user.id, &secret, "recovery", &pool).await.unwrap();
+
+        assert!(matches!(
+            check(user.id, &secret, "recovery", &pool)
+                .await
+                .unwrap_err(),
+            Error::InvalidSecondFactor
+        ));
+
+        user.delete(&pool).await;
+    }
+}
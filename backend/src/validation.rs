@@ -0,0 +1,245 @@
+//! Validation and normalization of user supplied credentials and image metadata.
+
+/// Passwords found in public breach corpora, one per line, lowercase.
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+/// Bcrypt, used by `pgcrypto` to hash passwords, ignores anything past 72 bytes.
+const PASSWORD_MAX_BYTES: usize = 72;
+const EMAIL_MAX_LENGTH: usize = 254;
+const EMAIL_LOCAL_MAX_LENGTH: usize = 64;
+pub const TAG_MAX_LENGTH: usize = 50;
+pub const TAGS_MAX_COUNT: usize = 32;
+
+#[derive(Clone, Copy)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub reject_common: bool,
+}
+
+/// Trims and lowercases an email so that it can be compared and stored in a canonical form.
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Checks the syntax of a normalized email, returning every rule it breaks.
+pub fn check_email(email: &str) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    if email.len() > EMAIL_MAX_LENGTH {
+        errors.push(format!(
+            "Must be at most {EMAIL_MAX_LENGTH} characters long"
+        ));
+    }
+
+    match email.split_once('@') {
+        Some((local, domain)) if !domain.contains('@') => {
+            if local.is_empty() || local.len() > EMAIL_LOCAL_MAX_LENGTH {
+                errors.push(format!(
+                    "Local part must be between 1 and {EMAIL_LOCAL_MAX_LENGTH} characters long"
+                ));
+            }
+            if local.starts_with('.') || local.ends_with('.') || local.contains("..") {
+                errors.push(String::from("Local part has misplaced dots"));
+            }
+            if !local.chars().all(is_local_char) {
+                errors.push(String::from("Local part contains invalid characters"));
+            }
+            if !is_domain(domain) {
+                errors.push(String::from("Invalid domain"));
+            }
+        }
+        _ => errors.push(String::from("Must contain a single @")),
+    }
+
+    errors
+}
+
+/// Trims a text field, dropping it when nothing is left.
+pub fn normalize_text(text: Option<String>) -> Option<String> {
+    text.map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty())
+}
+
+/// Trims tags, dropping empty and duplicate ones, which are compared case-insensitively.
+pub fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+
+    for tag in tags {
+        let tag = tag.trim();
+
+        if !tag.is_empty()
+            && !normalized
+                .iter()
+                .any(|t| t.to_lowercase() == tag.to_lowercase())
+        {
+            normalized.push(tag.to_string());
+        }
+    }
+
+    normalized
+}
+
+pub fn check_text(text: Option<&str>, max_length: usize) -> Vec<String> {
+    match text {
+        Some(text) if text.chars().count() > max_length => {
+            vec![format!("Must be at most {max_length} characters long")]
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Checks normalized tags, returning every rule they break.
+pub fn check_tags(tags: &[String]) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    if tags.len() > TAGS_MAX_COUNT {
+        errors.push(format!("Must have at most {TAGS_MAX_COUNT} tags"));
+    }
+    if tags.iter().any(|tag| tag.chars().count() > TAG_MAX_LENGTH) {
+        errors.push(format!(
+            "Tags must be at most {TAG_MAX_LENGTH} characters long"
+        ));
+    }
+    if tags.iter().any(|tag| tag.contains(',')) {
+        errors.push(String::from("Tags cannot contain commas"));
+    }
+
+    errors
+}
+
+/// Checks a password against `policy`, returning every rule it breaks.
+pub fn check_password(password: &str, policy: PasswordPolicy) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    if password.chars().count() < policy.min_length {
+        errors.push(format!(
+            "Must be at least {} characters long",
+            policy.min_length
+        ));
+    }
+    if password.len() > PASSWORD_MAX_BYTES {
+        errors.push(format!("Must be at most {PASSWORD_MAX_BYTES} bytes long"));
+    }
+    if policy.reject_common && is_common(password) {
+        errors.push(String::from("Too common"));
+    }
+
+    errors
+}
+
+fn is_common(password: &str) -> bool {
+    let password = password.to_lowercase();
+
+    COMMON_PASSWORDS.lines().any(|common| common == password)
+}
+
+fn is_local_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~.".contains(c)
+}
+
+fn is_domain(domain: &str) -> bool {
+    let labels: Vec<_> = domain.split('.').collect();
+
+    labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_email, check_password, normalize_email, PasswordPolicy};
+
+    const POLICY: PasswordPolicy = PasswordPolicy {
+        min_length: 8,
+        reject_common: true,
+    };
+
+    #[test]
+    fn normalize_email_trims_and_lowercases() {
+        let cases = [
+            ("user@example.com", "user@example.com"),
+            ("  User@Example.COM\n", "user@example.com"),
+            ("ÉLODIE@example.com", "élodie@example.com"),
+            ("", ""),
+        ];
+
+        for (email, expected) in cases {
+            assert_eq!(normalize_email(email), expected, "{email:?}");
+        }
+    }
+
+    #[test]
+    fn check_email_rules() {
+        let long_local = format!("{}@example.com", "a".repeat(65));
+        let long_label = format!("user@{}.com", "a".repeat(64));
+        let too_long = format!("user@{}.com", ["a"; 125].join("."));
+        let cases = [
+            ("user@example.com", vec![]),
+            ("first.last+tag@sub.example.co", vec![]),
+            ("o'hara@example-domain.org", vec![]),
+            ("user", vec!["Must contain a single @"]),
+            ("a@b@example.com", vec!["Must contain a single @"]),
+            (
+                "@example.com",
+                vec!["Local part must be between 1 and 64 characters long"],
+            ),
+            (
+                &long_local,
+                vec!["Local part must be between 1 and 64 characters long"],
+            ),
+            (".user@example.com", vec!["Local part has misplaced dots"]),
+            ("us..er@example.com", vec!["Local part has misplaced dots"]),
+            (
+                "us er@example.com",
+                vec!["Local part contains invalid characters"],
+            ),
+            ("user@localhost", vec!["Invalid domain"]),
+            ("user@example..com", vec!["Invalid domain"]),
+            ("user@-example.com", vec!["Invalid domain"]),
+            ("user@exa_mple.com", vec!["Invalid domain"]),
+            (&long_label, vec!["Invalid domain"]),
+            (&too_long, vec!["Must be at most 254 characters long"]),
+        ];
+
+        for (email, expected) in cases {
+            assert_eq!(check_email(email), expected, "{email:?}");
+        }
+    }
+
+    #[test]
+    fn check_password_rules() {
+        let long = "a".repeat(73);
+        let multibyte = "é".repeat(37);
+        let cases = [
+            ("correct horse battery", POLICY, vec![]),
+            ("short", POLICY, vec!["Must be at least 8 characters long"]),
+            ("éééééééé", POLICY, vec![]),
+            (&long, POLICY, vec!["Must be at most 72 bytes long"]),
+            (&multibyte, POLICY, vec!["Must be at most 72 bytes long"]),
+            ("password", POLICY, vec!["Too common"]),
+            ("PassWord", POLICY, vec!["Too common"]),
+            (
+                "password",
+                PasswordPolicy {
+                    reject_common: false,
+                    ..POLICY
+                },
+                vec![],
+            ),
+            (
+                "123456",
+                POLICY,
+                vec!["Must be at least 8 characters long", "Too common"],
+            ),
+        ];
+
+        for (password, policy, expected) in cases {
+            assert_eq!(check_password(password, policy), expected, "{password:?}");
+        }
+    }
+}
@@ -0,0 +1,17 @@
+//! CORS keys shared by every binary.
+
+use server::cors::Policy;
+
+use crate::{List, Loader};
+
+/// Reads the `cors.*` keys, no origin is allowed unless `cors.origins` is set.
+pub fn policy(loader: &mut Loader) -> Policy {
+    let defaults = Policy::default();
+
+    Policy {
+        origins: loader.get("cors.origins", || List(defaults.origins)).0,
+        methods: loader.get("cors.methods", || List(defaults.methods)).0,
+        headers: loader.get("cors.headers", || List(defaults.headers)).0,
+        max_age: loader.duration("cors.max_age", defaults.max_age),
+    }
+}
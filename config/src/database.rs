@@ -0,0 +1,39 @@
+//! Database keys shared by every binary.
+
+use db::pool::{ConnectOptions, Target};
+
+use crate::Loader;
+
+/// Keys holding credentials, to pass to [`Loader::load`].
+pub const SECRETS: [&str; 2] = ["database_url", "db.pass"];
+
+/// Reads `database_url`, or the individual `db.*` parts when it is not set, and the pool options.
+pub fn connect_options(loader: &mut Loader, application_name: &str) -> ConnectOptions {
+    let target = match loader.optional("database_url") {
+        Some(url) => Target::Url(url),
+        None => Target::Parts {
+            user: loader.get("db.user", || String::from("postgre")),
+            password: loader.get("db.pass", || String::from("postgre")),
+            host: loader.get("db.host", || String::from("0.0.0.0")),
+            port: loader.get("db.port", || 6300),
+            database: loader.get("db.name", || String::from("db")),
+        },
+    };
+    let defaults = ConnectOptions::new(target);
+
+    ConnectOptions {
+        max_connections: loader.get("db.max_connections", || defaults.max_connections),
+        min_connections: loader.get("db.min_connections", || defaults.min_connections),
+        acquire_timeout: loader.duration("db.acquire_timeout", defaults.acquire_timeout),
+        idle_timeout: loader
+            .optional_duration("db.idle_timeout")
+            .or(defaults.idle_timeout),
+        statement_timeout: loader.optional_duration("db.statement_timeout"),
+        tls_mode: loader.optional("db.tls_mode"),
+        ca_file: loader.optional("db.ca_file"),
+        application_name: Some(
+            loader.get("db.application_name", || String::from(application_name)),
+        ),
+        ..defaults
+    }
+}
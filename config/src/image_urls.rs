@@ -0,0 +1,14 @@
+//! Keys signing image URLs, shared by the backend handing them out and image-host serving them.
+
+use server::signing::Keys;
+
+use crate::Loader;
+
+/// Keys holding credentials, to pass to [`Loader::load`].
+pub const SECRETS: [&str; 1] = ["image_urls.keys"];
+
+/// Reads `image_urls.keys`, comma separated and the signing one first, URLs are not signed when it
+/// is not set.
+pub fn keys(loader: &mut Loader) -> Option<Keys> {
+    loader.optional("image_urls.keys")
+}
@@ -0,0 +1,475 @@
+//! Configuration shared by the binaries, layered from a TOML file, environment variables and
+//! command line flags, each overriding the previous one.
+//!
+//! Keys are dotted lowercase names such as `db.port`, which is read from `port` in the `[db]`
+//! table of the file, from `DB_PORT` in the environment and from `--db.port` on the command line.
+//! The file is given with `--config` or `CONFIG_FILE`, and `--print-config` prints the effective
+//! configuration then exits. Lists are comma separated, or arrays in the file.
+
+pub mod cors;
+pub mod database;
+pub mod image_urls;
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Debug, Display},
+    str::FromStr,
+    time::Duration,
+};
+
+use tracing::{error, info};
+
+const REDACTED: &str = "<redacted>";
+
+/// Where a value comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    File(String),
+    Env(String),
+    Cli(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    Invalid {
+        key: &'static str,
+        value: String,
+        origin: Origin,
+        reason: String,
+    },
+    Missing {
+        key: &'static str,
+    },
+    Unknown {
+        key: String,
+        origin: Origin,
+    },
+    File {
+        path: String,
+        reason: String,
+    },
+    Args {
+        reason: String,
+    },
+}
+
+/// Every error found while loading the configuration.
+#[derive(Debug)]
+pub struct Errors(pub Vec<Error>);
+
+/// Comma separated values.
+#[derive(Clone, PartialEq, Eq)]
+pub struct List<T>(pub Vec<T>);
+
+/// Non-negative and possibly fractional number of seconds, read with [`Loader::duration`].
+struct Seconds(Duration);
+
+type Env = Box<dyn Fn(&str) -> Option<String>>;
+
+/// Collects values as they are read, along with every error, so that they are reported at once.
+pub struct Loader {
+    file: BTreeMap<String, String>,
+    file_path: String,
+    cli: BTreeMap<String, String>,
+    env: Env,
+    secrets: Vec<&'static str>,
+    print: bool,
+    used: Vec<String>,
+    effective: Vec<(&'static str, String, Origin)>,
+    errors: Vec<Error>,
+}
+
+/// Values the configuration was built from, displayed with secrets redacted.
+pub struct Effective {
+    entries: Vec<(&'static str, String, Origin)>,
+    print: bool,
+}
+
+impl Loader {
+    /// Loads the file, environment and flags of the running process, `secrets` are never displayed.
+    pub fn load<S>(secrets: S) -> Self
+    where
+        S: IntoIterator<Item = &'static str>,
+    {
+        Self::new(
+            std::env::args().skip(1),
+            |var| std::env::var_os(var).map(|value| value.to_string_lossy().into_owned()),
+            |path| std::fs::read_to_string(path).map_err(|e| e.to_string()),
+            secrets,
+        )
+    }
+
+    /// Same as [`Loader::load`] with the process replaced by `args`, `env` and `read_file`.
+    pub fn new<A, E, R, S>(args: A, env: E, read_file: R, secrets: S) -> Self
+    where
+        A: IntoIterator<Item = String>,
+        S: IntoIterator<Item = &'static str>,
+        E: Fn(&str) -> Option<String> + 'static,
+        R: FnOnce(&str) -> Result<String, String>,
+    {
+        let mut loader = Self {
+            file: BTreeMap::new(),
+            file_path: String::new(),
+            cli: BTreeMap::new(),
+            env: Box::new(env),
+            secrets: secrets.into_iter().collect(),
+            print: false,
+            used: Vec::new(),
+            effective: Vec::new(),
+            errors: Vec::new(),
+        };
+
+        loader.parse_args(args);
+
+        let path = loader
+            .cli
+            .remove("config")
+            .or_else(|| (loader.env)("CONFIG_FILE"));
+
+        if let Some(path) = path {
+            match read_file(&path).and_then(|content| parse_file(&content)) {
+                Ok(file) => loader.file = file,
+                Err(reason) => loader.errors.push(Error::File {
+                    path: path.clone(),
+                    reason,
+                }),
+            }
+            loader.file_path = path;
+        }
+
+        loader
+    }
+
+    fn parse_args<A>(&mut self, args: A)
+    where
+        A: IntoIterator<Item = String>,
+    {
+        let mut args = args.into_iter().peekable();
+
+        while let Some(arg) = args.next() {
+            let flag = match arg.strip_prefix("--") {
+                Some(flag) if !flag.is_empty() => flag,
+                _ => {
+                    self.errors.push(Error::Args {
+                        reason: format!("Unexpected argument {arg}"),
+                    });
+                    continue;
+                }
+            };
+            let (key, value) = match flag.split_once('=') {
+                Some((key, value)) => (key, value.to_string()),
+                None => match args.next_if(|next| !next.starts_with("--")) {
+                    Some(value) => (flag, value),
+                    None => (flag, String::from("true")),
+                },
+            };
+            let key = key.replace('-', "_");
+
+            if key == "print_config" {
+                self.print = value == "true";
+            } else {
+                self.cli.insert(key, value);
+            }
+        }
+    }
+
+    /// Reads `key`, falling back to `default` when it is not set or invalid.
+    pub fn get<T, F>(&mut self, key: &'static str, default: F) -> T
+    where
+        T: FromStr + Debug,
+        T::Err: Display,
+        F: FnOnce() -> T,
+    {
+        self.parse(key).unwrap_or_else(|| {
+            let value = default();
+
+            self.record(key, &value, Origin::Default);
+            value
+        })
+    }
+
+    pub fn optional<T>(&mut self, key: &'static str) -> Option<T>
+    where
+        T: FromStr + Debug,
+        T::Err: Display,
+    {
+        self.parse(key)
+    }
+
+    /// Reads a number of seconds from `key`, falling back to `default` when it is not set or invalid.
+    pub fn duration(&mut self, key: &'static str, default: Duration) -> Duration {
+        self.get(key, || Seconds(default)).0
+    }
+
+    pub fn optional_duration(&mut self, key: &'static str) -> Option<Duration> {
+        self.optional(key).map(|Seconds(duration)| duration)
+    }
+
+    /// Reads `key`, reporting an error when it is not set.
+    ///
+    /// The returned default value only lets the caller carry on, the error fails [`Loader::finish`].
+    pub fn required<T>(&mut self, key: &'static str) -> T
+    where
+        T: FromStr + Debug + Default,
+        T::Err: Display,
+    {
+        if self.lookup(key).is_none() {
+            self.errors.push(Error::Missing { key });
+        }
+
+        self.parse(key).unwrap_or_default()
+    }
+
+    fn lookup(&self, key: &str) -> Option<(String, Origin)> {
+        let var = key.replace('.', "_").to_uppercase();
+
+        self.cli
+            .get(key)
+            .map(|value| (value.clone(), Origin::Cli(format!("--{key}"))))
+            .or_else(|| (self.env)(&var).map(|value| (value, Origin::Env(var))))
+            .or_else(|| {
+                self.file
+                    .get(key)
+                    .map(|value| (value.clone(), Origin::File(self.file_path.clone())))
+            })
+    }
+
+    fn parse<T>(&mut self, key: &'static str) -> Option<T>
+    where
+        T: FromStr + Debug,
+        T::Err: Display,
+    {
+        self.used.push(key.to_string());
+
+        let (raw, origin) = self.lookup(key)?;
+
+        match raw.parse() {
+            Ok(value) => {
+                self.record(key, &value, origin);
+                Some(value)
+            }
+            Err(e) => {
+                let value = if self.secrets.contains(&key) {
+                    String::from(REDACTED)
+                } else {
+                    raw
+                };
+
+                self.errors.push(Error::Invalid {
+                    key,
+                    value,
+                    origin,
+                    reason: e.to_string(),
+                });
+                None
+            }
+        }
+    }
+
+    fn record<T: Debug>(&mut self, key: &'static str, value: &T, origin: Origin) {
+        let value = if self.secrets.contains(&key) {
+            String::from(REDACTED)
+        } else {
+            format!("{value:?}")
+        };
+
+        self.effective.push((key, value, origin));
+    }
+
+    /// Reports keys of the file and flags that were never read, then every error found.
+    pub fn finish(mut self) -> Result<Effective, Errors> {
+        let file_path = self.file_path.clone();
+        let unused = |keys: BTreeMap<String, String>, used: &[String]| {
+            keys.into_keys()
+                .filter(|key| !used.contains(key))
+                .collect::<Vec<_>>()
+        };
+
+        for key in unused(std::mem::take(&mut self.file), &self.used) {
+            self.errors.push(Error::Unknown {
+                key,
+                origin: Origin::File(file_path.clone()),
+            });
+        }
+        for key in unused(std::mem::take(&mut self.cli), &self.used) {
+            self.errors.push(Error::Unknown {
+                origin: Origin::Cli(format!("--{key}")),
+                key,
+            });
+        }
+
+        if self.errors.is_empty() {
+            Ok(Effective {
+                entries: self.effective,
+                print: self.print,
+            })
+        } else {
+            Err(Errors(self.errors))
+        }
+    }
+
+    /// Logs the effective configuration, or prints it and exits when `--print-config` was given.
+    /// Exits after logging every error when the configuration is invalid.
+    pub fn finish_or_exit(self) {
+        match self.finish() {
+            Ok(effective) if effective.print => {
+                print!("{effective}");
+                std::process::exit(0);
+            }
+            Ok(effective) => info!("Effective configuration:\n{effective}"),
+            Err(errors) => {
+                error!("Invalid configuration:\n{errors}");
+                std::process::exit(2);
+            }
+        }
+    }
+}
+
+/// Flattens the tables of a TOML document into dotted keys.
+fn parse_file(content: &str) -> Result<BTreeMap<String, String>, String> {
+    fn flatten(
+        prefix: &str,
+        table: toml::Table,
+        keys: &mut BTreeMap<String, String>,
+    ) -> Result<(), String> {
+        for (key, value) in table {
+            let key = match prefix {
+                "" => key,
+                _ => format!("{prefix}.{key}"),
+            };
+
+            match value {
+                toml::Value::Table(table) => flatten(&key, table, keys)?,
+                toml::Value::Array(values) => {
+                    let values = values
+                        .into_iter()
+                        .map(scalar)
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(|| format!("Unexpected nested value in {key}"))?;
+
+                    keys.insert(key, values.join(","));
+                }
+                value => {
+                    keys.insert(key, scalar(value).unwrap_or_default());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Text of a value, `None` for tables and arrays.
+    fn scalar(value: toml::Value) -> Option<String> {
+        match value {
+            toml::Value::String(value) => Some(value),
+            toml::Value::Table(_) | toml::Value::Array(_) => None,
+            value => Some(value.to_string()),
+        }
+    }
+
+    let mut keys = BTreeMap::new();
+
+    flatten("", content.parse().map_err(|e| format!("{e}"))?, &mut keys)?;
+    Ok(keys)
+}
+
+impl<T> FromStr for List<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| item.parse().map_err(|e| format!("{item}: {e}")))
+            .collect::<Result<_, _>>()
+            .map(List)
+    }
+}
+
+impl FromStr for Seconds {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let seconds: f64 = s.trim().parse().map_err(|e| format!("{e}"))?;
+
+        if !(seconds.is_finite() && seconds >= 0.) {
+            return Err(String::from("expected a non-negative number of seconds"));
+        }
+
+        Duration::try_from_secs_f64(seconds)
+            .map(Seconds)
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl Debug for Seconds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0.as_secs_f64())
+    }
+}
+
+impl<T: Debug> Debug for List<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(&self.0).finish()
+    }
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::File(path) => write!(f, "file {path}"),
+            Origin::Env(var) => write!(f, "env {var}"),
+            Origin::Cli(flag) => write!(f, "flag {flag}"),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Invalid {
+                key,
+                value,
+                origin,
+                reason,
+            } => write!(f, "{key}: invalid value {value:?} from {origin}: {reason}"),
+            Error::Missing { key } => write!(f, "{key}: required"),
+            Error::Unknown { key, origin } => {
+                write!(f, "{key}: unknown or unused key from {origin}")
+            }
+            Error::File { path, reason } => write!(f, "{path}: {reason}"),
+            Error::Args { reason } => write!(f, "{reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Display for Errors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|error| writeln!(f, "  {error}"))
+    }
+}
+
+impl Effective {
+    pub fn get(&self, key: &str) -> Option<(&str, &Origin)> {
+        self.entries
+            .iter()
+            .find(|(entry, ..)| *entry == key)
+            .map(|(_, value, origin)| (value.as_str(), origin))
+    }
+}
+
+impl Display for Effective {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.entries
+            .iter()
+            .try_for_each(|(key, value, origin)| writeln!(f, "{key} = {value} # {origin}"))
+    }
+}
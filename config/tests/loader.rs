@@ -0,0 +1,249 @@
+mod common {
+    use config::Loader;
+
+    pub const FILE: &str = r#"
+host = "127.0.0.1:8000"
+
+[db]
+port = 5432
+pass = "file"
+"#;
+
+    pub const LISTS: &str = r#"
+[cors]
+methods = ["GET", "POST"]
+headers = [["authorization"]]
+"#;
+
+    pub fn loader(args: &[&str], env: &'static [(&'static str, &'static str)]) -> Loader {
+        Loader::new(
+            args.iter().map(|arg| arg.to_string()),
+            move |var| {
+                env.iter()
+                    .find(|(name, _)| *name == var)
+                    .map(|(_, value)| value.to_string())
+            },
+            |path| match path {
+                "config.toml" => Ok(FILE.to_string()),
+                "lists.toml" => Ok(LISTS.to_string()),
+                _ => Err(String::from("not found")),
+            },
+            ["db.pass"],
+        )
+    }
+}
+
+mod get {
+    use crate::common::loader;
+
+    use config::Origin;
+
+    #[test]
+    fn default() {
+        let mut loader = loader(&[], &[]);
+
+        assert_eq!(loader.get("db.port", || 6300), 6300);
+
+        let effective = loader.finish().unwrap();
+
+        assert_eq!(effective.get("db.port"), Some(("6300", &Origin::Default)));
+    }
+
+    #[test]
+    fn layers() {
+        let mut loader = loader(
+            &["--config", "config.toml", "--db.port=7000"],
+            &[("DB_PORT", "6000"), ("HOST", "0.0.0.0:9000")],
+        );
+
+        assert_eq!(loader.get::<u16, _>("db.port", || 0), 7000);
+        assert_eq!(loader.get::<String, _>("host", String::new), "0.0.0.0:9000");
+        assert_eq!(loader.get::<String, _>("db.pass", String::new), "file");
+        assert!(loader.finish().is_ok());
+    }
+
+    #[test]
+    fn redacted() {
+        let mut loader = loader(&["--db.pass", "hunter2"], &[]);
+
+        loader.get::<String, _>("db.pass", String::new);
+
+        let effective = loader.finish().unwrap();
+
+        assert!(!effective.to_string().contains("hunter2"));
+    }
+}
+
+mod required {
+    use crate::common::loader;
+
+    use config::Error;
+
+    #[test]
+    fn missing() {
+        let mut loader = loader(&[], &[]);
+
+        assert_eq!(loader.required::<String>("oidc.client_id"), "");
+
+        let errors = loader.finish().err().unwrap();
+
+        assert_eq!(
+            errors.0,
+            vec![Error::Missing {
+                key: "oidc.client_id"
+            }]
+        );
+    }
+}
+
+mod finish {
+    use crate::common::loader;
+
+    use config::{Error, Origin};
+
+    #[test]
+    fn every_error() {
+        let mut loader = loader(
+            &["--config", "config.toml", "--db.max_connections", "many"],
+            &[("DB_PORT", "http"), ("DB_PASS", "secret")],
+        );
+
+        loader.get("db.port", || 6300u16);
+        loader.get("db.max_connections", || 10u32);
+        loader.get("db.pass", || 0u32);
+
+        let errors = loader.finish().err().unwrap().0;
+
+        assert_eq!(errors.len(), 4, "{errors:?}");
+        assert!(matches!(
+            &errors[0],
+            Error::Invalid { key: "db.port", origin: Origin::Env(var), .. } if var == "DB_PORT"
+        ));
+        assert!(matches!(
+            &errors[1],
+            Error::Invalid {
+                key: "db.max_connections",
+                origin: Origin::Cli(_),
+                ..
+            }
+        ));
+        assert!(matches!(
+            &errors[2],
+            Error::Invalid { key: "db.pass", value, .. } if value == "<redacted>"
+        ));
+        assert_eq!(
+            errors[3],
+            Error::Unknown {
+                key: String::from("host"),
+                origin: Origin::File(String::from("config.toml")),
+            }
+        );
+    }
+
+    #[test]
+    fn missing_file() {
+        let loader = loader(&["--config", "missing.toml"], &[]);
+
+        let errors = loader.finish().err().unwrap().0;
+
+        assert!(matches!(&errors[..], [Error::File { path, .. }] if path == "missing.toml"));
+    }
+
+    #[test]
+    fn unexpected_argument() {
+        let loader = loader(&["serve"], &[]);
+
+        let errors = loader.finish().err().unwrap().0;
+
+        assert!(matches!(&errors[..], [Error::Args { .. }]));
+    }
+}
+
+mod list {
+    use crate::common::loader;
+
+    use config::{Error, List};
+
+    #[test]
+    fn comma_separated() {
+        let mut loader = loader(&["--ports", "80, 443,"], &[]);
+
+        assert_eq!(loader.get("ports", || List(vec![])), List(vec![80u16, 443]));
+        assert!(loader.finish().is_ok());
+    }
+
+    #[test]
+    fn invalid_item() {
+        let mut loader = loader(&["--ports=80,http"], &[]);
+
+        loader.get("ports", || List::<u16>(vec![]));
+
+        let errors = loader.finish().err().unwrap().0;
+
+        assert!(
+            matches!(&errors[..], [Error::Invalid { reason, .. }] if reason.starts_with("http:"))
+        );
+    }
+
+    #[test]
+    fn nested_array() {
+        let loader = loader(&["--config", "lists.toml"], &[]);
+
+        let errors = loader.finish().err().unwrap().0;
+
+        assert!(
+            matches!(&errors[..], [Error::File { reason, .. }] if reason.contains("cors.headers"))
+        );
+    }
+}
+
+mod duration {
+    use std::time::Duration;
+
+    use crate::common::loader;
+
+    use config::{Error, Origin};
+
+    #[test]
+    fn fractional() {
+        let mut loader = loader(&["--shutdown_timeout", "1.5"], &[]);
+
+        assert_eq!(
+            loader.duration("shutdown_timeout", Duration::from_secs(30)),
+            Duration::from_millis(1500)
+        );
+        assert_eq!(loader.optional_duration("db.idle_timeout"), None);
+
+        let effective = loader.finish().unwrap();
+
+        assert_eq!(
+            effective.get("shutdown_timeout"),
+            Some(("1.5", &Origin::Cli(String::from("--shutdown_timeout"))))
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        for value in ["-1", "NaN", "inf", "1e30", "soon"] {
+            let mut loader = loader(&["--shutdown_timeout", value], &[]);
+
+            assert_eq!(
+                loader.duration("shutdown_timeout", Duration::from_secs(30)),
+                Duration::from_secs(30)
+            );
+
+            let errors = loader.finish().err().unwrap().0;
+
+            assert!(
+                matches!(
+                    &errors[..],
+                    [Error::Invalid {
+                        key: "shutdown_timeout",
+                        ..
+                    }]
+                ),
+                "{value}: {errors:?}"
+            );
+        }
+    }
+}
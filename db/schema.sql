@@ -1,16 +1,22 @@
 create extension if not exists pgcrypto;
 
-drop table if exists users,tokens,sessions,registrations,images,images_associations,results,choices;
+drop table if exists users,tokens,sessions,registrations,images,images_associations,tags,images_tags,results,choices,login_attempts,recovery_codes,oidc_states,login_challenges;
 
 create table if not exists users
 (
     id serial primary key,
-    email text unique not null,
+    email text not null,
     password text not null,
     admin boolean not null default false,
-    confirm_limit timestamptz default CURRENT_TIMESTAMP + make_interval(days => 3)
+    confirm_limit timestamptz default CURRENT_TIMESTAMP + make_interval(days => 3),
+    locked_until timestamptz,
+    totp_secret text,
+    totp_enabled boolean not null default false,
+    totp_last_step bigint
 );
 
+create unique index if not exists users_email_key on users (lower(email));
+
 create table if not exists tokens
 (
     token uuid primary key default gen_random_uuid(),
@@ -19,6 +25,14 @@ create table if not exists tokens
         references users (id) on delete cascade
 );
 
+create table if not exists recovery_codes
+(
+    id serial primary key,
+    user_id integer not null
+        references users (id) on delete cascade,
+    code text not null
+);
+
 create table if not exists sessions
 (
     id serial primary key,
@@ -30,9 +44,40 @@ create table if not exists sessions
 
 create table if not exists images
 (
-    id serial primary key
+    id serial primary key,
+    created_at timestamptz not null default CURRENT_TIMESTAMP,
+    uploader_id integer
+        references users(id) on delete set null,
+    title text,
+    description text,
+    alt_text text,
+    -- Perceptual hash of the content, null until computed or when the image cannot be decoded
+    phash bigint,
+    -- Served to anyone, otherwise only to admins and to the participants of the sessions using it
+    public boolean not null default false
 );
 
+create index if not exists images_created_at on images (created_at, id);
+
+create table if not exists tags
+(
+    id serial primary key,
+    name text not null
+);
+
+create unique index if not exists tags_name_key on tags (lower(name));
+
+create table if not exists images_tags
+(
+    image_id integer not null
+        references images(id) on delete cascade,
+    tag_id integer not null
+        references tags(id) on delete cascade,
+    primary key (image_id, tag_id)
+);
+
+create index if not exists images_tags_tag_id on images_tags (tag_id);
+
 create table if not exists registrations
 (
     id serial primary key,
@@ -79,3 +124,64 @@ create table if not exists results
     user_3_id integer
         references users(id) on delete cascade
 );
+
+create table if not exists login_attempts
+(
+    id serial primary key,
+    scope text not null,
+    key text not null,
+    failures integer not null default 1,
+    last_failure timestamptz not null default CURRENT_TIMESTAMP,
+    unique (scope, key)
+);
+
+create table if not exists oidc_states
+(
+    state uuid primary key default gen_random_uuid(),
+    nonce text not null,
+    verifier text not null,
+    expiration timestamptz not null default CURRENT_TIMESTAMP + make_interval(mins => 10)
+);
+
+create table if not exists login_challenges
+(
+    challenge uuid primary key default gen_random_uuid(),
+    user_id integer not null
+        references users(id) on delete cascade,
+    expiration timestamptz not null default CURRENT_TIMESTAMP + make_interval(mins => 5)
+);
+
+-- Publishes '<session id>:<table>' on session_events whenever something a session's event stream
+-- reports changes, the backend forwards them to the clients following the session.
+create or replace function notify_session_event() returns trigger as $$
+declare
+    session integer;
+begin
+    if TG_TABLE_NAME = 'sessions' then
+        session := new.id;
+    elsif TG_OP = 'DELETE' then
+        session := old.session_id;
+    else
+        session := new.session_id;
+    end if;
+
+    perform pg_notify('session_events', session || ':' || TG_TABLE_NAME);
+    return null;
+end;
+$$ language plpgsql;
+
+create or replace trigger sessions_events
+    after update of phase1, phase2, phase3 on sessions
+    for each row execute function notify_session_event();
+
+create or replace trigger registrations_events
+    after insert or delete on registrations
+    for each row execute function notify_session_event();
+
+create or replace trigger choices_events
+    after insert or update or delete on choices
+    for each row execute function notify_session_event();
+
+create or replace trigger results_events
+    after insert or delete on results
+    for each row execute function notify_session_event();
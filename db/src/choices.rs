@@ -0,0 +1,63 @@
+use sqlx::PgExecutor;
+use tracing::instrument;
+
+use crate::{
+    images,
+    result::{code_to_error, context, Context, DbResult, FOREIGN_KEYS_HANDLER},
+    sessions, users,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Id(pub i32);
+
+/// Records the image chosen by a user, replacing their previous choice in the session.
+#[instrument(skip_all)]
+pub async fn create<'a, E>(
+    user: users::Id,
+    session: sessions::Id,
+    image: images::Id,
+    db: E,
+) -> DbResult<Id>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str =
+        "insert into choices(user_id,session_id,image_id)values($1,$2,$3) on conflict(user_id,session_id) do update set image_id=excluded.image_id returning id";
+
+    sqlx::query_as(QUERY)
+        .bind(user.0)
+        .bind(session.0)
+        .bind(image.0)
+        .fetch_one(db)
+        .await
+        .map(|(id,)| Id(id))
+        .map_err(code_to_error(
+            Context::new("create", "choices"),
+            &[FOREIGN_KEYS_HANDLER],
+        ))
+}
+
+/// Image chosen by each user of the session, ordered by image.
+#[instrument(skip_all)]
+pub async fn by_session<'a, E>(
+    session: sessions::Id,
+    db: E,
+) -> DbResult<Vec<(users::Id, images::Id)>>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str =
+        "select user_id,image_id from choices where session_id=$1 order by image_id,user_id";
+
+    sqlx::query_as(QUERY)
+        .bind(session.0)
+        .fetch_all(db)
+        .await
+        .map(|choices: Vec<(i32, i32)>| {
+            choices
+                .into_iter()
+                .map(|(user, image)| (users::Id(user), images::Id(image)))
+                .collect()
+        })
+        .map_err(context("by_session", "choices"))
+}
@@ -0,0 +1,81 @@
+use sqlx::postgres::PgListener;
+use tracing::{instrument, warn};
+
+use crate::{
+    result::{context, DbResult},
+    sessions, Pool,
+};
+
+/// Channel the triggers of `schema.sql` notify on, with `<session id>:<table>` payloads.
+pub const CHANNEL: &str = "session_events";
+
+/// What changed in a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Phases,
+    Registrations,
+    Choices,
+    Results,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Notification {
+    pub session: sessions::Id,
+    pub event: Event,
+}
+
+/// Dedicated connection receiving the notifications of every session.
+pub struct Listener(PgListener);
+
+impl Listener {
+    #[instrument(skip_all)]
+    pub async fn connect(pool: &Pool) -> DbResult<Self> {
+        let mut listener = PgListener::connect_with(pool)
+            .await
+            .map_err(context("connect", "session_events"))?;
+
+        listener
+            .listen(CHANNEL)
+            .await
+            .map_err(context("listen", "session_events"))?;
+        Ok(Self(listener))
+    }
+
+    /// Waits for the next notification.
+    ///
+    /// Returns `None` when the connection was lost, notifications sent until it is reestablished
+    /// by the next call are missed.
+    pub async fn recv(&mut self) -> DbResult<Option<Notification>> {
+        loop {
+            let notification = match self.0.try_recv().await {
+                Ok(Some(notification)) => notification,
+                Ok(None) => return Ok(None),
+                Err(err) => return Err(context("recv", "session_events")(err)),
+            };
+
+            match parse(notification.payload()) {
+                Some(notification) => return Ok(Some(notification)),
+                None => warn!(
+                    payload = notification.payload(),
+                    "Ignoring malformed session event"
+                ),
+            }
+        }
+    }
+}
+
+fn parse(payload: &str) -> Option<Notification> {
+    let (session, table) = payload.split_once(':')?;
+    let event = match table {
+        "sessions" => Event::Phases,
+        "registrations" => Event::Registrations,
+        "choices" => Event::Choices,
+        "results" => Event::Results,
+        _ => return None,
+    };
+
+    Some(Notification {
+        session: sessions::Id(session.parse().ok()?),
+        event,
+    })
+}
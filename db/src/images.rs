@@ -1,19 +1,393 @@
-use sqlx::PgExecutor;
+use sqlx::{postgres::PgRow, types::time::OffsetDateTime, PgExecutor, Row};
+use tracing::instrument;
 
-use crate::result::{DbResult, Error};
+use crate::{
+    result::{
+        at_least_one, code_to_error, context, Context, DbResult, Error, FOREIGN_KEYS_HANDLER,
+    },
+    sessions, users,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Id(pub i32);
 
+/// Largest number of bits by which the perceptual hashes of near-duplicates differ by default.
+pub const NEAR_DUPLICATE_DISTANCE: i32 = 10;
+
+/// Optional text describing an image to participants.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// Text alternative for screen readers.
+    pub alt_text: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub id: Id,
+    pub metadata: Metadata,
+    /// Names of the tags, sorted case-insensitively.
+    pub tags: Vec<String>,
+    /// Served to anyone, see [`access`].
+    pub public: bool,
+}
+
+/// Who may be served an image, see [`access`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Anyone, the image is public.
+    Public,
+    /// The user only, as an admin or a participant of a session using the image.
+    Granted,
+    Denied,
+}
+
+/// Two images that look alike, the first one having the lowest id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NearDuplicate {
+    pub images: (Id, Id),
+    /// Number of bits by which their perceptual hashes differ.
+    pub distance: i32,
+}
+
+/// Image of the library, with how it is used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub image: Image,
+    pub uploaded_at: OffsetDateTime,
+    /// `None` when uploaded anonymously or by a deleted user.
+    pub uploader: Option<users::Id>,
+    pub sessions: Vec<sessions::Id>,
+}
+
+/// Restricts the images of the library, every image matches the default.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    /// Images with every one of these tags, compared case-insensitively.
+    pub tags: Vec<String>,
+    pub uploaded_after: Option<OffsetDateTime>,
+    pub uploaded_before: Option<OffsetDateTime>,
+    pub uploader: Option<users::Id>,
+    /// Images associated with no session.
+    pub unused: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sort {
+    Newest,
+    Oldest,
+    /// Case-insensitively, untitled images last.
+    Title,
+}
+
+/// Position of the last image of a page, the next page starts after it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cursor {
+    Uploaded(OffsetDateTime, Id),
+    Title(Option<String>, Id),
+}
+
+impl Entry {
+    pub fn cursor(&self, sort: Sort) -> Cursor {
+        match sort {
+            Sort::Newest | Sort::Oldest => Cursor::Uploaded(self.uploaded_at, self.image.id),
+            Sort::Title => Cursor::Title(self.image.metadata.title.clone(), self.image.id),
+        }
+    }
+}
+
+/// Columns read by the `FromRow` implementation of [`Image`], for a query on `images i`.
+const IMAGE_COLUMNS: &str = "i.id,i.title,i.description,i.alt_text,array(select t.name from images_tags it join tags t on t.id=it.tag_id where it.image_id=i.id order by lower(t.name)),i.public";
+
+impl<'r> sqlx::FromRow<'r, PgRow> for Image {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            id: Id(row.try_get(0)?),
+            metadata: Metadata {
+                title: row.try_get(1)?,
+                description: row.try_get(2)?,
+                alt_text: row.try_get(3)?,
+            },
+            tags: row.try_get(4)?,
+            public: row.try_get(5)?,
+        })
+    }
+}
+
+#[instrument(skip_all)]
 pub async fn create<'a, E>(db: E) -> DbResult<Id>
 where
     E: PgExecutor<'a>,
 {
-    const QUERY: &str = "insert into images values(default)returning id";
+    create_by(None, db).await
+}
+
+/// Creates an image, recording the user who uploaded it.
+#[instrument(skip_all)]
+pub async fn create_by<'a, E>(uploader: Option<users::Id>, db: E) -> DbResult<Id>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "insert into images(uploader_id)values($1)returning id";
 
     sqlx::query_as(QUERY)
+        .bind(uploader.map(|uploader| uploader.0))
         .fetch_one(db)
         .await
         .map(|(id,)| Id(id))
-        .map_err(Error::Sqlx)
+        .map_err(code_to_error(
+            Context::new("create", "images"),
+            &[FOREIGN_KEYS_HANDLER],
+        ))
+}
+
+/// The ids of `images` that exist.
+#[instrument(skip_all)]
+pub async fn existing<'a, E>(images: &[Id], db: E) -> DbResult<Vec<Id>>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "select id from images where id=any($1)";
+
+    sqlx::query(QUERY)
+        .bind(images.iter().map(|image| image.0).collect::<Vec<_>>())
+        .try_map(|row: PgRow| Ok(Id(row.try_get(0)?)))
+        .fetch_all(db)
+        .await
+        .map_err(context("existing", "images"))
+}
+
+/// Replaces the metadata of the image.
+#[instrument(skip_all)]
+pub async fn update<'a, E>(id: Id, metadata: &Metadata, db: E) -> DbResult<()>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "update images set title=$2,description=$3,alt_text=$4 where id=$1";
+
+    sqlx::query(QUERY)
+        .bind(id.0)
+        .bind(&metadata.title)
+        .bind(&metadata.description)
+        .bind(&metadata.alt_text)
+        .execute(db)
+        .await
+        .map_err(context("update", "images"))
+        .and_then(at_least_one(Error::InvalidImage))
+}
+
+/// Serves the image to anyone or only to the users allowed to see it, see [`access`].
+#[instrument(skip_all)]
+pub async fn set_public<'a, E>(id: Id, public: bool, db: E) -> DbResult<()>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "update images set public=$2 where id=$1";
+
+    sqlx::query(QUERY)
+        .bind(id.0)
+        .bind(public)
+        .execute(db)
+        .await
+        .map_err(context("set_public", "images"))
+        .and_then(at_least_one(Error::InvalidImage))
+}
+
+/// Whether the image may be served to `user`, `None` for anonymous requests.
+///
+/// Apart from public images, admins see every image and users see the images of the sessions they
+/// are registered in once their first phase has begun. Unknown images are [`Access::Denied`].
+#[instrument(skip_all)]
+pub async fn access<'a, E>(
+    id: Id,
+    user: Option<users::Id>,
+    require_admin_totp: bool,
+    db: E,
+) -> DbResult<Access>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "select i.public,exists(select from users u where u.id=$2 and u.admin and (u.totp_enabled or not $3)) or exists(select from images_associations a join sessions s on s.id=a.session_id join registrations r on r.session_id=a.session_id where a.image_id=i.id and r.user_id=$2 and s.phase1<=CURRENT_TIMESTAMP) from images i where i.id=$1";
+
+    sqlx::query_as(QUERY)
+        .bind(id.0)
+        .bind(user.map(|user| user.0))
+        .bind(require_admin_totp)
+        .fetch_optional(db)
+        .await
+        .map(|row| match row {
+            Some((true, _)) => Access::Public,
+            Some((false, true)) => Access::Granted,
+            Some((false, false)) | None => Access::Denied,
+        })
+        .map_err(context("access", "images"))
+}
+
+/// Whether `user` may be served every image of the session, as [`access`] grants them.
+#[instrument(skip_all)]
+pub async fn session_access<'a, E>(
+    session: sessions::Id,
+    user: users::Id,
+    require_admin_totp: bool,
+    db: E,
+) -> DbResult<bool>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "select exists(select from users u where u.id=$2 and u.admin and (u.totp_enabled or not $3)) or exists(select from sessions s join registrations r on r.session_id=s.id where s.id=$1 and r.user_id=$2 and s.phase1<=CURRENT_TIMESTAMP)";
+
+    sqlx::query_scalar(QUERY)
+        .bind(session.0)
+        .bind(user.0)
+        .bind(require_admin_totp)
+        .fetch_one(db)
+        .await
+        .map_err(context("session_access", "images"))
+}
+
+/// Images associated with the session, with their metadata and tags.
+#[instrument(skip_all)]
+pub async fn by_session<'a, E>(session: sessions::Id, db: E) -> DbResult<Vec<Image>>
+where
+    E: PgExecutor<'a>,
+{
+    let query = format!(
+        "select {IMAGE_COLUMNS} from images i join images_associations a on a.image_id=i.id where a.session_id=$1 order by i.id"
+    );
+
+    sqlx::query_as(&query)
+        .bind(session.0)
+        .fetch_all(db)
+        .await
+        .map_err(context("by_session", "images"))
+}
+
+/// A page of at most `limit` images matching `filter`, starting after `after`.
+#[instrument(skip_all)]
+pub async fn library<'a, E>(
+    filter: &Filter,
+    sort: Sort,
+    after: Option<&Cursor>,
+    limit: i64,
+    db: E,
+) -> DbResult<Vec<Entry>>
+where
+    E: PgExecutor<'a>,
+{
+    const FILTER: &str = "(cardinality($1::text[])=0 or (select count(*) from images_tags it join tags t on t.id=it.tag_id where it.image_id=i.id and lower(t.name)=any($1))=cardinality($1)) and ($2::timestamptz is null or i.created_at>=$2) and ($3::timestamptz is null or i.created_at<$3) and ($4::integer is null or i.uploader_id=$4) and (not $5 or not exists(select 1 from images_associations a where a.image_id=i.id))";
+
+    let (order, position) = match sort {
+        Sort::Newest => ("i.created_at desc,i.id desc", "(i.created_at,i.id)<($7,$8)"),
+        Sort::Oldest => ("i.created_at,i.id", "(i.created_at,i.id)>($7,$8)"),
+        Sort::Title => (
+            "i.title is null,coalesce(lower(i.title),''),i.id",
+            "(i.title is null,coalesce(lower(i.title),''),i.id)>($7 is null,coalesce(lower($7),''),$8)",
+        ),
+    };
+    let query = format!(
+        "select {IMAGE_COLUMNS},i.created_at,i.uploader_id,array(select session_id from images_associations where image_id=i.id order by session_id) from images i where {FILTER} and (not $6 or {position}) order by {order} limit $9"
+    );
+    let mut tags: Vec<_> = filter.tags.iter().map(|tag| tag.to_lowercase()).collect();
+
+    tags.sort();
+    tags.dedup();
+
+    let query = sqlx::query(&query)
+        .bind(tags)
+        .bind(filter.uploaded_after)
+        .bind(filter.uploaded_before)
+        .bind(filter.uploader.map(|uploader| uploader.0))
+        .bind(filter.unused)
+        .bind(after.is_some());
+    let query = match after {
+        Some(Cursor::Uploaded(uploaded_at, id)) => query.bind(*uploaded_at).bind(id.0),
+        Some(Cursor::Title(title, id)) => query.bind(title.clone()).bind(id.0),
+        None if sort == Sort::Title => query.bind(None::<String>).bind(0),
+        None => query.bind(None::<OffsetDateTime>).bind(0),
+    };
+
+    query
+        .bind(limit)
+        .try_map(|row: PgRow| {
+            Ok(Entry {
+                image: sqlx::FromRow::from_row(&row)?,
+                uploaded_at: row.try_get(6)?,
+                uploader: row.try_get::<Option<i32>, _>(7)?.map(users::Id),
+                sessions: row
+                    .try_get::<Vec<i32>, _>(8)?
+                    .into_iter()
+                    .map(sessions::Id)
+                    .collect(),
+            })
+        })
+        .fetch_all(db)
+        .await
+        .map_err(context("library", "images"))
+}
+
+/// Stores the perceptual hash of the image, computed from its content.
+#[instrument(skip_all)]
+pub async fn set_phash<'a, E>(id: Id, phash: u64, db: E) -> DbResult<()>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "update images set phash=$2 where id=$1";
+
+    sqlx::query(QUERY)
+        .bind(id.0)
+        .bind(phash as i64)
+        .execute(db)
+        .await
+        .map_err(context("set_phash", "images"))
+        .and_then(at_least_one(Error::InvalidImage))
+}
+
+/// Images whose perceptual hash was never computed.
+#[instrument(skip_all)]
+pub async fn without_phash<'a, E>(db: E) -> DbResult<Vec<Id>>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "select id from images where phash is null order by id";
+
+    sqlx::query(QUERY)
+        .try_map(|row: PgRow| Ok(Id(row.try_get(0)?)))
+        .fetch_all(db)
+        .await
+        .map_err(context("without_phash", "images"))
+}
+
+/// Pairs of images whose perceptual hashes differ by at most `distance` bits, closest first.
+///
+/// Only the images of `session` are compared when given, and only pairs including one of `of` are
+/// returned when given. Across the whole library, only images whose hashes share their top byte
+/// are compared, which keeps the self-join to buckets of the library at the cost of missing the
+/// pairs differing there.
+#[instrument(skip_all)]
+pub async fn near_duplicates<'a, E>(
+    session: Option<sessions::Id>,
+    of: Option<&[Id]>,
+    distance: i32,
+    db: E,
+) -> DbResult<Vec<NearDuplicate>>
+where
+    E: PgExecutor<'a>,
+{
+    // Counts the differing bits portably, bit_count only exists since PostgreSQL 14.
+    const QUERY: &str = "with h as (select id,phash,case when $1::integer is null then phash>>56 else 0 end k from images i where phash is not null and ($1::integer is null or exists(select 1 from images_associations a where a.image_id=i.id and a.session_id=$1))),p as (select a.id a,b.id b,length(replace((a.phash#b.phash)::bit(64)::text,'0','')) d from h a join h b on a.k=b.k and a.id<b.id where $2::integer[] is null or a.id=any($2) or b.id=any($2)) select a,b,d from p where d<=$3 order by d,a,b";
+
+    sqlx::query(QUERY)
+        .bind(session.map(|session| session.0))
+        .bind(of.map(|of| of.iter().map(|image| image.0).collect::<Vec<_>>()))
+        .bind(distance)
+        .try_map(|row: PgRow| {
+            Ok(NearDuplicate {
+                images: (Id(row.try_get(0)?), Id(row.try_get(1)?)),
+                distance: row.try_get(2)?,
+            })
+        })
+        .fetch_all(db)
+        .await
+        .map_err(context("near_duplicates", "images"))
 }
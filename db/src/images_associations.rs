@@ -1,14 +1,18 @@
 use sqlx::{postgres::PgRow, PgExecutor, Row};
+use tracing::instrument;
 
 use crate::{
     images,
-    result::{at_least_one, code_to_error, DbResult, Error, FOREIGN_KEYS_HANDLER},
+    result::{
+        at_least_one, code_to_error, context, Context, DbResult, Error, FOREIGN_KEYS_HANDLER,
+    },
     sessions,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Id(pub i32);
 
+#[instrument(skip_all)]
 pub async fn create<'a, E>(image: images::Id, session: sessions::Id, db: E) -> DbResult<Id>
 where
     E: PgExecutor<'a>,
@@ -22,9 +26,13 @@ where
         .fetch_one(db)
         .await
         .map(|(id,)| Id(id))
-        .map_err(code_to_error(&[FOREIGN_KEYS_HANDLER]))
+        .map_err(code_to_error(
+            Context::new("create", "images_associations"),
+            &[FOREIGN_KEYS_HANDLER],
+        ))
 }
 
+#[instrument(skip_all)]
 pub async fn delete<'a, E>(id: Id, db: E) -> DbResult<()>
 where
     E: PgExecutor<'a>,
@@ -36,10 +44,11 @@ where
         .bind(id.0)
         .execute(db)
         .await
-        .map_err(Error::Sqlx)
+        .map_err(context("delete", "images_associations"))
         .and_then(at_least_one(Error::InvalidImageAssociation))
 }
 
+#[instrument(skip_all)]
 pub async fn delete_by_session<'a, E>(
     image: images::Id,
     session: sessions::Id,
@@ -56,10 +65,60 @@ where
         .bind(session.0)
         .execute(db)
         .await
-        .map_err(Error::Sqlx)
+        .map_err(context("delete_by_session", "images_associations"))
         .and_then(at_least_one(Error::InvalidImageAssociation))
 }
 
+/// Associates the images with the session, skipping those already associated, and returns the
+/// images newly associated.
+#[instrument(skip_all)]
+pub async fn add<'a, E>(
+    session: sessions::Id,
+    images: &[images::Id],
+    db: E,
+) -> DbResult<Vec<images::Id>>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "insert into images_associations(image_id,session_id)select distinct image_id,$1 from unnest($2::integer[]) as image_id on conflict do nothing returning image_id";
+
+    sqlx::query(QUERY)
+        .bind(session.0)
+        .bind(images.iter().map(|image| image.0).collect::<Vec<_>>())
+        .try_map(|row: PgRow| Ok(images::Id(row.try_get(0)?)))
+        .fetch_all(db)
+        .await
+        .map_err(code_to_error(
+            Context::new("add", "images_associations"),
+            &[FOREIGN_KEYS_HANDLER],
+        ))
+}
+
+/// Dissociates the images from the session and returns those that were associated.
+///
+/// Unlike [`delete_by_session`], the phases of the session are not checked.
+#[instrument(skip_all)]
+pub async fn remove<'a, E>(
+    session: sessions::Id,
+    images: &[images::Id],
+    db: E,
+) -> DbResult<Vec<images::Id>>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str =
+        "delete from images_associations where session_id=$1 and image_id=any($2) returning image_id";
+
+    sqlx::query(QUERY)
+        .bind(session.0)
+        .bind(images.iter().map(|image| image.0).collect::<Vec<_>>())
+        .try_map(|row: PgRow| Ok(images::Id(row.try_get(0)?)))
+        .fetch_all(db)
+        .await
+        .map_err(context("remove", "images_associations"))
+}
+
+#[instrument(skip_all)]
 pub async fn by_session<'a, E>(session: sessions::Id, db: E) -> DbResult<Vec<images::Id>>
 where
     E: PgExecutor<'a>,
@@ -71,9 +130,10 @@ where
         .try_map(|row: PgRow| Ok(images::Id(row.try_get(0)?)))
         .fetch_all(db)
         .await
-        .map_err(Error::Sqlx)
+        .map_err(context("by_session", "images_associations"))
 }
 
+#[instrument(skip_all)]
 pub async fn by_image<'a, E>(image: images::Id, db: E) -> DbResult<Vec<sessions::Id>>
 where
     E: PgExecutor<'a>,
@@ -85,5 +145,5 @@ where
         .try_map(|row: PgRow| Ok(sessions::Id(row.try_get(0)?)))
         .fetch_all(db)
         .await
-        .map_err(Error::Sqlx)
+        .map_err(context("by_image", "images_associations"))
 }
@@ -1,10 +1,18 @@
+pub mod choices;
+pub mod events;
 pub mod images;
 pub mod images_associations;
+pub mod login_attempts;
+pub mod login_challenges;
+pub mod oidc_states;
 pub mod pool;
+pub mod recovery_codes;
 pub mod registrations;
 pub mod result;
+pub mod results;
 pub mod sessions;
+pub mod tags;
 pub mod tokens;
 pub mod users;
 
-pub use pool::{connect, Pool};
+pub use pool::{connect, ConnectOptions, Pool};
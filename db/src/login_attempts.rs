@@ -0,0 +1,96 @@
+use sqlx::PgExecutor;
+use tracing::instrument;
+
+use crate::result::{context, DbResult, Error};
+
+/// What a login attempt is tracked against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Ip,
+    Email,
+}
+
+impl Scope {
+    fn as_str(self) -> &'static str {
+        match self {
+            Scope::Ip => "ip",
+            Scope::Email => "email",
+        }
+    }
+}
+
+/// Exponential backoff applied to failed login attempts.
+///
+/// Once `threshold` consecutive failures are reached, new attempts are refused for
+/// `base_delay * 2^(failures - threshold)` seconds, capped at `max_delay`.
+/// The failure count starts over when the last failure is older than `reset_after` seconds.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub threshold: i32,
+    pub base_delay: f64,
+    pub max_delay: f64,
+    pub reset_after: f64,
+}
+
+#[instrument(skip_all)]
+pub async fn check<'a, E>(scope: Scope, key: &str, backoff: Backoff, db: E) -> DbResult<()>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str =
+        "select count(*) from login_attempts where scope=$1 and key=$2 and failures>=$3 and last_failure + make_interval(secs => least($4 * power(2, failures - $3), $5)) > CURRENT_TIMESTAMP";
+
+    sqlx::query_as(QUERY)
+        .bind(scope.as_str())
+        .bind(key)
+        .bind(backoff.threshold)
+        .bind(backoff.base_delay)
+        .bind(backoff.max_delay)
+        .fetch_one(db)
+        .await
+        .map_err(context("check", "login_attempts"))
+        .and_then(|(count,): (i64,)| match count {
+            0 => Ok(()),
+            _ => Err(Error::TooManyAttempts),
+        })
+}
+
+/// Records a failed attempt and returns the number of consecutive failures.
+#[instrument(skip_all)]
+pub async fn record_failure<'a, E>(
+    scope: Scope,
+    key: &str,
+    backoff: Backoff,
+    db: E,
+) -> DbResult<i32>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str =
+        "insert into login_attempts as a(scope,key)values($1,$2) on conflict(scope,key) do update set failures=case when a.last_failure < CURRENT_TIMESTAMP - make_interval(secs => $3) then 1 else a.failures + 1 end,last_failure=CURRENT_TIMESTAMP returning failures";
+
+    sqlx::query_as(QUERY)
+        .bind(scope.as_str())
+        .bind(key)
+        .bind(backoff.reset_after)
+        .fetch_one(db)
+        .await
+        .map(|(failures,)| failures)
+        .map_err(context("record_failure", "login_attempts"))
+}
+
+#[instrument(skip_all)]
+pub async fn clear<'a, E>(scope: Scope, key: &str, db: E) -> DbResult<()>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "delete from login_attempts where scope=$1 and key=$2";
+
+    sqlx::query(QUERY)
+        .bind(scope.as_str())
+        .bind(key)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(context("clear", "login_attempts"))
+}
@@ -0,0 +1,64 @@
+use sqlx::{types::Uuid, PgExecutor};
+use tracing::instrument;
+
+use crate::{
+    result::{at_least_one, context, DbResult, Error},
+    users,
+};
+
+/// Pending login of a user who authenticated with an identity provider and still has to enter
+/// their second factor.
+#[derive(Clone, Copy)]
+pub struct Challenge(pub Uuid);
+
+#[instrument(skip_all)]
+pub async fn create<'a, E>(user: users::Id, db: E) -> DbResult<Challenge>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "insert into login_challenges(user_id)values($1)returning challenge";
+
+    sqlx::query_as(QUERY)
+        .bind(user.0)
+        .fetch_one(db)
+        .await
+        .map(|(challenge,)| Challenge(challenge))
+        .map_err(context("create", "login_challenges"))
+}
+
+/// Returns the id, email and admin flag of the user of a pending challenge.
+#[instrument(skip_all)]
+pub async fn find<'a, E>(challenge: Challenge, db: E) -> DbResult<(users::Id, String, bool)>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str =
+        "select u.id,u.email,u.admin from login_challenges c join users u on u.id=c.user_id where c.challenge=$1 and c.expiration > CURRENT_TIMESTAMP";
+
+    sqlx::query_as(QUERY)
+        .bind(challenge.0)
+        .fetch_optional(db)
+        .await
+        .map_err(context("find", "login_challenges"))
+        .and_then(|opt| {
+            opt.map(|(id, email, admin)| (users::Id(id), email, admin))
+                .ok_or(Error::InvalidLoginChallenge)
+        })
+}
+
+/// Deletes a challenge once its login completed, so it cannot be completed again.
+#[instrument(skip_all)]
+pub async fn consume<'a, E>(challenge: Challenge, db: E) -> DbResult<()>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str =
+        "delete from login_challenges where challenge=$1 and expiration > CURRENT_TIMESTAMP";
+
+    sqlx::query(QUERY)
+        .bind(challenge.0)
+        .execute(db)
+        .await
+        .map_err(context("consume", "login_challenges"))
+        .and_then(at_least_one(Error::InvalidLoginChallenge))
+}
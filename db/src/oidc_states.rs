@@ -0,0 +1,41 @@
+use sqlx::{types::Uuid, PgExecutor};
+use tracing::instrument;
+
+use crate::result::{context, DbResult, Error};
+
+/// Opaque value round-tripped through the identity provider to bind a callback to its login.
+#[derive(Clone, Copy)]
+pub struct State(pub Uuid);
+
+#[instrument(skip_all)]
+pub async fn create<'a, E>(nonce: &str, verifier: &str, db: E) -> DbResult<State>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "insert into oidc_states(nonce,verifier)values($1,$2)returning state";
+
+    sqlx::query_as(QUERY)
+        .bind(nonce)
+        .bind(verifier)
+        .fetch_one(db)
+        .await
+        .map(|(state,)| State(state))
+        .map_err(context("create", "oidc_states"))
+}
+
+/// Deletes a pending state and returns its nonce and PKCE verifier.
+#[instrument(skip_all)]
+pub async fn consume<'a, E>(state: State, db: E) -> DbResult<(String, String)>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str =
+        "delete from oidc_states where state=$1 and expiration > CURRENT_TIMESTAMP returning nonce,verifier";
+
+    sqlx::query_as(QUERY)
+        .bind(state.0)
+        .fetch_optional(db)
+        .await
+        .map_err(context("consume", "oidc_states"))
+        .and_then(|opt| opt.ok_or(Error::InvalidOidcState))
+}
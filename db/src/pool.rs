@@ -1,20 +1,111 @@
-use sqlx::{postgres::PgPoolOptions, PgPool};
+use std::{path::PathBuf, time::Duration};
 
-use crate::result::{DbResult, Error};
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    PgPool,
+};
+use tracing::instrument;
+
+pub use sqlx::postgres::PgSslMode as TlsMode;
+
+use crate::result::{context, DbResult};
 
 pub type Pool = PgPool;
 
-pub async fn connect(
-    user: &str,
-    password: &str,
-    host: &str,
-    port: i16,
-    database: &str,
-) -> DbResult<Pool> {
-    let url = format!("postgresql://{user}:{password}@{host}:{port}/{database}");
+/// Where to connect, either a full `postgresql://` URL or its individual parts.
+#[derive(Debug, Clone)]
+pub enum Target {
+    Url(String),
+    Parts {
+        user: String,
+        password: String,
+        host: String,
+        port: u16,
+        database: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectOptions {
+    pub target: Target,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout: Duration,
+    /// Idle connections above `min_connections` are closed after this long.
+    pub idle_timeout: Option<Duration>,
+    /// Queries running longer than this are cancelled by the server.
+    pub statement_timeout: Option<Duration>,
+    /// Defaults to the `sslmode` of the URL, or [`TlsMode::Prefer`].
+    pub tls_mode: Option<TlsMode>,
+    /// Certificate authority used to verify the server with [`TlsMode::VerifyCa`] and [`TlsMode::VerifyFull`].
+    pub ca_file: Option<PathBuf>,
+    pub application_name: Option<String>,
+}
+
+impl ConnectOptions {
+    /// Options with the same pool defaults as sqlx.
+    pub fn new(target: Target) -> Self {
+        Self {
+            target,
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout: Duration::from_secs(30),
+            idle_timeout: Some(Duration::from_secs(600)),
+            statement_timeout: None,
+            tls_mode: None,
+            ca_file: None,
+            application_name: None,
+        }
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn connect(options: &ConnectOptions) -> DbResult<Pool> {
+    let mut connect: PgConnectOptions = match &options.target {
+        Target::Url(url) => url.parse().map_err(context("parse", "database url"))?,
+        Target::Parts {
+            user,
+            password,
+            host,
+            port,
+            database,
+        } => PgConnectOptions::new()
+            .username(user)
+            .password(password)
+            .host(host)
+            .port(*port)
+            .database(database),
+    };
+
+    if let Some(tls_mode) = options.tls_mode {
+        connect = connect.ssl_mode(tls_mode);
+    }
+    if let Some(application_name) = &options.application_name {
+        connect = connect.application_name(application_name);
+    }
+    if let Some(ca_file) = &options.ca_file {
+        connect = connect.ssl_root_cert(ca_file);
+    }
+    if let Some(timeout) = options.statement_timeout {
+        connect = connect.options([("statement_timeout", timeout.as_millis().to_string())]);
+    }
 
     PgPoolOptions::new()
-        .connect(&url)
+        .max_connections(options.max_connections)
+        .min_connections(options.min_connections)
+        .acquire_timeout(options.acquire_timeout)
+        .idle_timeout(options.idle_timeout)
+        .connect_with(connect)
+        .await
+        .map_err(context("connect", "database"))
+}
+
+/// Checks that a connection can be acquired and used.
+#[instrument(skip_all)]
+pub async fn ping(pool: &Pool) -> DbResult<()> {
+    sqlx::query("select 1")
+        .execute(pool)
         .await
-        .map_err(Error::Sqlx)
+        .map(|_| ())
+        .map_err(context("ping", "database"))
 }
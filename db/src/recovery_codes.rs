@@ -0,0 +1,58 @@
+use sqlx::PgExecutor;
+use tracing::instrument;
+
+use crate::{
+    result::{at_least_one, context, DbResult, Error},
+    users,
+};
+
+/// Replaces every recovery code of a user, codes are stored hashed.
+#[instrument(skip_all)]
+pub async fn replace<'a, E>(user: users::Id, codes: &[String], db: E) -> DbResult<()>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str =
+        "with d as (delete from recovery_codes where user_id=$1) insert into recovery_codes(user_id,code) select $1,crypt(c,gen_salt('bf')) from unnest($2::text[]) c";
+
+    sqlx::query(QUERY)
+        .bind(user.0)
+        .bind(codes)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(context("replace", "recovery_codes"))
+}
+
+/// Deletes a matching recovery code so that it can only be used once.
+#[instrument(skip_all)]
+pub async fn consume<'a, E>(user: users::Id, code: &str, db: E) -> DbResult<()>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str =
+        "delete from recovery_codes where id=(select id from recovery_codes where user_id=$1 and code=crypt($2,code) limit 1)";
+
+    sqlx::query(QUERY)
+        .bind(user.0)
+        .bind(code.trim())
+        .execute(db)
+        .await
+        .map_err(context("consume", "recovery_codes"))
+        .and_then(at_least_one(Error::InvalidSecondFactor))
+}
+
+#[instrument(skip_all)]
+pub async fn clear<'a, E>(user: users::Id, db: E) -> DbResult<()>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "delete from recovery_codes where user_id=$1";
+
+    sqlx::query(QUERY)
+        .bind(user.0)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(context("clear", "recovery_codes"))
+}
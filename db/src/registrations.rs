@@ -1,13 +1,15 @@
 use sqlx::PgExecutor;
+use tracing::instrument;
 
 use crate::{
-    result::{DbResult, Error},
+    result::{context, DbResult},
     sessions, users,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Id(pub i32);
 
+#[instrument(skip_all)]
 pub async fn create<'a, E>(user: users::Id, session: sessions::Id, db: E) -> DbResult<Id>
 where
     E: PgExecutor<'a>,
@@ -20,9 +22,10 @@ where
         .fetch_one(db)
         .await
         .map(|(id,)| Id(id))
-        .map_err(Error::Sqlx)
+        .map_err(context("create", "registrations"))
 }
 
+#[instrument(skip_all)]
 pub async fn by_user<'a, E>(user: users::Id, db: E) -> DbResult<Vec<sessions::Session>>
 where
     E: PgExecutor<'a>,
@@ -34,9 +37,10 @@ where
         .bind(user.0)
         .fetch_all(db)
         .await
-        .map_err(Error::Sqlx)
+        .map_err(context("by_user", "registrations"))
 }
 
+#[instrument(skip_all)]
 pub async fn by_session<'a, E>(session: sessions::Id, db: E) -> DbResult<Vec<users::Summary>>
 where
     E: PgExecutor<'a>,
@@ -48,5 +52,27 @@ where
         .bind(session.0)
         .fetch_all(db)
         .await
-        .map_err(Error::Sqlx)
+        .map_err(context("by_session", "registrations"))
+}
+
+/// Number of registrations to the sessions in each state, states without registrations are
+/// omitted.
+#[instrument(skip_all)]
+pub async fn count_by_state<'a, E>(db: E) -> DbResult<Vec<(sessions::State, i64)>>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str =
+        "select case when CURRENT_TIMESTAMP < s.phase1 then 0 when CURRENT_TIMESTAMP < s.phase2 then 1 when CURRENT_TIMESTAMP < s.phase3 then 2 else 3 end as state,count(*) from registrations r join sessions s on s.id=r.session_id group by state order by state";
+
+    sqlx::query_as(QUERY)
+        .fetch_all(db)
+        .await
+        .map(|counts: Vec<(i32, i64)>| {
+            counts
+                .into_iter()
+                .map(|(state, count)| (sessions::STATES[state as usize], count))
+                .collect()
+        })
+        .map_err(context("count_by_state", "registrations"))
 }
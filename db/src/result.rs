@@ -1,25 +1,114 @@
-use log::error;
 use sqlx::postgres::{PgDatabaseError, PgQueryResult};
+use tracing::error;
 
 #[derive(Debug)]
 pub enum Error {
     InvalidToken,
     InvalidUserId,
     InvalidCredentials,
+    TooManyAttempts,
+    AccountLocked,
+    SecondFactorRequired,
+    InvalidSecondFactor,
+    SecondFactorAlreadyEnabled,
+    InvalidOidcState,
+    InvalidLoginChallenge,
     InvalidImageAssociation,
     InvalidImage,
     InvalidSession,
     InvalidDates,
     DuplicateEmail,
     UnknownForeignKey,
-    Sqlx(sqlx::Error),
+    Sqlx {
+        context: Context,
+        source: sqlx::Error,
+    },
+}
+
+/// Operation and table a failed query was about.
+#[derive(Debug, Clone, Copy)]
+pub struct Context {
+    pub operation: &'static str,
+    pub entity: &'static str,
 }
 
 pub type DbResult<T> = Result<T, Error>;
 
-impl From<sqlx::Error> for Error {
-    fn from(e: sqlx::Error) -> Self {
-        Error::Sqlx(e)
+impl Context {
+    pub const fn new(operation: &'static str, entity: &'static str) -> Self {
+        Self { operation, entity }
+    }
+}
+
+/// Wraps a sqlx error with the operation and table it happened on.
+pub fn context(operation: &'static str, entity: &'static str) -> impl FnOnce(sqlx::Error) -> Error {
+    move |source| Error::Sqlx {
+        context: Context::new(operation, entity),
+        source,
+    }
+}
+
+impl Error {
+    /// Stable identifier of the error, meant to be exposed to clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::InvalidToken => "invalid_token",
+            Error::InvalidUserId => "invalid_user_id",
+            Error::InvalidCredentials => "invalid_credentials",
+            Error::TooManyAttempts => "too_many_attempts",
+            Error::AccountLocked => "account_locked",
+            Error::SecondFactorRequired => "second_factor_required",
+            Error::InvalidSecondFactor => "invalid_second_factor",
+            Error::SecondFactorAlreadyEnabled => "second_factor_already_enabled",
+            Error::InvalidOidcState => "invalid_oidc_state",
+            Error::InvalidLoginChallenge => "invalid_login_challenge",
+            Error::InvalidImageAssociation => "invalid_image_association",
+            Error::InvalidImage => "invalid_image",
+            Error::InvalidSession => "invalid_session",
+            Error::InvalidDates => "invalid_dates",
+            Error::DuplicateEmail => "duplicate_email",
+            Error::UnknownForeignKey => "unknown_foreign_key",
+            Error::Sqlx { .. } => "database",
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidToken => write!(f, "invalid or expired token"),
+            Error::InvalidUserId => write!(f, "no user with this id"),
+            Error::InvalidCredentials => write!(f, "invalid credentials"),
+            Error::TooManyAttempts => write!(f, "too many failed login attempts"),
+            Error::AccountLocked => write!(f, "account locked"),
+            Error::SecondFactorRequired => write!(f, "second factor required"),
+            Error::InvalidSecondFactor => write!(f, "invalid second factor"),
+            Error::SecondFactorAlreadyEnabled => write!(f, "second factor already enabled"),
+            Error::InvalidOidcState => write!(f, "unknown or expired OpenID Connect state"),
+            Error::InvalidLoginChallenge => write!(f, "unknown or expired login challenge"),
+            Error::InvalidImageAssociation => {
+                write!(f, "no removable association between this image and session")
+            }
+            Error::InvalidImage => write!(f, "no image with this id"),
+            Error::InvalidSession => write!(f, "no session with this id"),
+            Error::InvalidDates => write!(f, "session phases are not in ascending order"),
+            Error::DuplicateEmail => write!(f, "email already in use"),
+            Error::UnknownForeignKey => write!(f, "violation of an unhandled foreign key"),
+            Error::Sqlx { context, source } => write!(
+                f,
+                "{} on {} failed: {source}",
+                context.operation, context.entity
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Sqlx { source, .. } => Some(source),
+            _ => None,
+        }
     }
 }
 
@@ -37,8 +126,11 @@ pub mod codes {
     pub const CHECK: &str = "23514";
 }
 
-pub fn code_to_error<'a>(matches: &'a [ErrorMatch<'a>]) -> impl 'a + FnOnce(sqlx::Error) -> Error {
-    |error| {
+pub fn code_to_error<'a>(
+    context: Context,
+    matches: &'a [ErrorMatch<'a>],
+) -> impl 'a + FnOnce(sqlx::Error) -> Error {
+    move |error| {
         error
             .as_database_error()
             .and_then(|e| e.try_downcast_ref())
@@ -50,7 +142,10 @@ pub fn code_to_error<'a>(matches: &'a [ErrorMatch<'a>]) -> impl 'a + FnOnce(sqlx
                     .find(|(exp_code, _)| *exp_code == code)
                     .map(|(_, f)| f(e))
             })
-            .unwrap_or(Error::Sqlx(error))
+            .unwrap_or(Error::Sqlx {
+                context,
+                source: error,
+            })
     }
 }
 
@@ -58,9 +153,10 @@ pub const FOREIGN_KEYS_HANDLER: ErrorMatch = (codes::FOREIGN_KEY, foreign_key_ha
 
 fn foreign_key_handler(error: &PgDatabaseError) -> Error {
     type Association<'a> = (&'a str, fn() -> Error);
-    const FOREIGN_KEYS: [Association; 2] = [
+    const FOREIGN_KEYS: [Association; 3] = [
         ("session_id_fkey", || Error::InvalidSession),
         ("image_id_fkey", || Error::InvalidImage),
+        ("user_id_fkey", || Error::InvalidUserId),
     ];
 
     error
@@ -0,0 +1,67 @@
+use sqlx::PgExecutor;
+use tracing::instrument;
+
+use crate::{
+    images,
+    result::{code_to_error, context, Context, DbResult, FOREIGN_KEYS_HANDLER},
+    sessions, users,
+};
+
+/// Users matched together on the image they all chose.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub image: images::Id,
+    pub users: (users::Id, users::Id, Option<users::Id>),
+}
+
+/// Replaces every result of a session.
+#[instrument(skip_all)]
+pub async fn replace<'a, E>(session: sessions::Id, matches: &[Match], db: E) -> DbResult<()>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str =
+        "with d as (delete from results where session_id=$1) insert into results(session_id,image_id,user_1_id,user_2_id,user_3_id) select $1,i,u1,u2,u3 from unnest($2::int[],$3::int[],$4::int[],$5::int[]) t(i,u1,u2,u3)";
+
+    let images: Vec<_> = matches.iter().map(|m| m.image.0).collect();
+    let first: Vec<_> = matches.iter().map(|m| m.users.0 .0).collect();
+    let second: Vec<_> = matches.iter().map(|m| m.users.1 .0).collect();
+    let third: Vec<_> = matches.iter().map(|m| m.users.2.map(|u| u.0)).collect();
+
+    sqlx::query(QUERY)
+        .bind(session.0)
+        .bind(images)
+        .bind(first)
+        .bind(second)
+        .bind(third)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(code_to_error(
+            Context::new("replace", "results"),
+            &[FOREIGN_KEYS_HANDLER],
+        ))
+}
+
+#[instrument(skip_all)]
+pub async fn by_session<'a, E>(session: sessions::Id, db: E) -> DbResult<Vec<Match>>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str =
+        "select image_id,user_1_id,user_2_id,user_3_id from results where session_id=$1 order by id";
+
+    sqlx::query_as(QUERY)
+        .bind(session.0)
+        .fetch_all(db)
+        .await
+        .map(|rows: Vec<(i32, i32, i32, Option<i32>)>| {
+            rows.into_iter()
+                .map(|(image, first, second, third)| Match {
+                    image: images::Id(image),
+                    users: (users::Id(first), users::Id(second), third.map(users::Id)),
+                })
+                .collect()
+        })
+        .map_err(context("by_session", "results"))
+}
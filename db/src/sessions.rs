@@ -1,10 +1,12 @@
 use sqlx::{postgres::PgRow, types::time::OffsetDateTime, PgExecutor, Row};
+use tracing::instrument;
 
-use crate::result::{code_to_error, codes, DbResult, Error};
+use crate::result::{code_to_error, codes, context, Context, DbResult, Error};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Id(pub i32);
 
+#[derive(Debug, Clone, Copy)]
 pub struct Session {
     pub id: Id,
     pub phase1: OffsetDateTime,
@@ -12,7 +14,58 @@ pub struct Session {
     pub phase3: OffsetDateTime,
 }
 
-pub enum State {}
+/// States in the order of the numbers queries compute them as, see [`count_by_state`].
+pub(crate) const STATES: [State; 4] = [State::Pending, State::Phase1, State::Phase2, State::Phase3];
+
+/// Where a session stands relative to its phases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Pending,
+    Phase1,
+    Phase2,
+    Phase3,
+}
+
+impl State {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            State::Pending => "pending",
+            State::Phase1 => "phase1",
+            State::Phase2 => "phase2",
+            State::Phase3 => "phase3",
+        }
+    }
+}
+
+/// A session with the counts reported by its event stream.
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    pub session: Session,
+    pub registrations: i64,
+    pub choices: i64,
+    pub results: i64,
+}
+
+impl Session {
+    pub fn state(&self, now: OffsetDateTime) -> State {
+        if now < self.phase1 {
+            State::Pending
+        } else if now < self.phase2 {
+            State::Phase1
+        } else if now < self.phase3 {
+            State::Phase2
+        } else {
+            State::Phase3
+        }
+    }
+
+    /// Start of the next phase after `now`, `None` once the last one started.
+    pub fn next_phase(&self, now: OffsetDateTime) -> Option<OffsetDateTime> {
+        [self.phase1, self.phase2, self.phase3]
+            .into_iter()
+            .find(|phase| now < *phase)
+    }
+}
 
 const LIST_QUERY: &str = "select id,phase1,phase2,phase3 from sessions";
 
@@ -27,6 +80,7 @@ impl<'r> sqlx::FromRow<'r, PgRow> for Session {
     }
 }
 
+#[instrument(skip_all)]
 pub async fn create<'a, E>(
     name: &str,
     phase1: OffsetDateTime,
@@ -48,9 +102,13 @@ where
         .fetch_one(db)
         .await
         .map(|(id,)| Id(id))
-        .map_err(code_to_error(&[(codes::CHECK, |_| Error::InvalidDates)]))
+        .map_err(code_to_error(
+            Context::new("create", "sessions"),
+            &[(codes::CHECK, |_| Error::InvalidDates)],
+        ))
 }
 
+#[instrument(skip_all)]
 pub async fn list<'a, E>(db: E) -> DbResult<Vec<Session>>
 where
     E: PgExecutor<'a>,
@@ -58,5 +116,72 @@ where
     sqlx::query_as(LIST_QUERY)
         .fetch_all(db)
         .await
-        .map_err(Error::Sqlx)
+        .map_err(context("list", "sessions"))
+}
+
+/// Number of sessions in each state, states without sessions are omitted.
+#[instrument(skip_all)]
+pub async fn count_by_state<'a, E>(db: E) -> DbResult<Vec<(State, i64)>>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str =
+        "select case when CURRENT_TIMESTAMP < phase1 then 0 when CURRENT_TIMESTAMP < phase2 then 1 when CURRENT_TIMESTAMP < phase3 then 2 else 3 end as state,count(*) from sessions group by state order by state";
+
+    sqlx::query_as(QUERY)
+        .fetch_all(db)
+        .await
+        .map(|counts: Vec<(i32, i64)>| {
+            counts
+                .into_iter()
+                .map(|(state, count)| (STATES[state as usize], count))
+                .collect()
+        })
+        .map_err(context("count_by_state", "sessions"))
+}
+
+/// Locks the session until the end of the transaction, so that checks of its state hold until then.
+#[instrument(skip_all)]
+pub async fn lock<'a, E>(id: Id, db: E) -> DbResult<Session>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "select id,phase1,phase2,phase3 from sessions where id=$1 for update";
+
+    sqlx::query_as(QUERY)
+        .bind(id.0)
+        .fetch_optional(db)
+        .await
+        .map_err(context("lock", "sessions"))
+        .and_then(|opt| opt.ok_or(Error::InvalidSession))
+}
+
+/// The session with its registration, choice and result counts, `None` when it does not exist.
+#[instrument(skip_all)]
+pub async fn progress<'a, E>(id: Id, db: E) -> DbResult<Option<Progress>>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "select s.id,s.phase1,s.phase2,s.phase3,(select count(*) from registrations where session_id=s.id),(select count(*) from choices where session_id=s.id),(select count(*) from results where session_id=s.id) from sessions s where s.id=$1";
+
+    sqlx::query_as(QUERY)
+        .bind(id.0)
+        .fetch_optional(db)
+        .await
+        .map(|row| {
+            row.map(
+                |(id, phase1, phase2, phase3, registrations, choices, results)| Progress {
+                    session: Session {
+                        id: Id(id),
+                        phase1,
+                        phase2,
+                        phase3,
+                    },
+                    registrations,
+                    choices,
+                    results,
+                },
+            )
+        })
+        .map_err(context("progress", "sessions"))
 }
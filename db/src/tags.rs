@@ -0,0 +1,56 @@
+use sqlx::{postgres::PgRow, PgExecutor, Row};
+use tracing::instrument;
+
+use crate::{
+    images,
+    result::{code_to_error, context, Context, DbResult, FOREIGN_KEYS_HANDLER},
+};
+
+/// A tag, with the number of images it is on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub name: String,
+    pub images: i64,
+}
+
+/// Replaces the tags of the image, creating the missing ones.
+///
+/// Names are case-insensitive, a tag keeps the case it was first created with.
+#[instrument(skip_all)]
+pub async fn set<'a, E>(image: images::Id, names: &[String], db: E) -> DbResult<()>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "with n as (select distinct on (lower(name)) name from unnest($2::text[]) name),c as (insert into tags(name) select name from n on conflict (lower(name)) do nothing returning id),t as (select id from c union select id from tags where lower(name) in (select lower(name) from n)),d as (delete from images_tags where image_id=$1 and tag_id not in (select id from t)) insert into images_tags(image_id,tag_id) select $1,id from t on conflict do nothing";
+
+    sqlx::query(QUERY)
+        .bind(image.0)
+        .bind(names)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(code_to_error(
+            Context::new("set", "images_tags"),
+            &[FOREIGN_KEYS_HANDLER],
+        ))
+}
+
+/// Every tag on at least one image, sorted case-insensitively.
+#[instrument(skip_all)]
+pub async fn list<'a, E>(db: E) -> DbResult<Vec<Tag>>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "select t.name,count(*) from tags t join images_tags it on it.tag_id=t.id group by t.id order by lower(t.name)";
+
+    sqlx::query(QUERY)
+        .try_map(|row: PgRow| {
+            Ok(Tag {
+                name: row.try_get(0)?,
+                images: row.try_get(1)?,
+            })
+        })
+        .fetch_all(db)
+        .await
+        .map_err(context("list", "tags"))
+}
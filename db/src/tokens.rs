@@ -2,15 +2,17 @@ use sqlx::{
     types::{time::OffsetDateTime, Uuid},
     PgExecutor,
 };
+use tracing::instrument;
 
 use crate::{
-    result::{at_least_one, DbResult, Error},
+    result::{at_least_one, context, DbResult, Error},
     users,
 };
 
 #[derive(Clone, Copy)]
 pub struct Token(pub Uuid);
 
+#[instrument(skip_all)]
 pub async fn create<'a, E>(id: users::Id, db: E) -> DbResult<(Token, OffsetDateTime)>
 where
     E: PgExecutor<'a>,
@@ -22,9 +24,10 @@ where
         .fetch_one(db)
         .await
         .map(|(uuid, expiration)| (Token(uuid), expiration))
-        .map_err(Error::Sqlx)
+        .map_err(context("create", "tokens"))
 }
 
+#[instrument(skip_all)]
 pub async fn auth<'a, E>(token: Token, db: E) -> DbResult<users::Id>
 where
     E: PgExecutor<'a>,
@@ -39,28 +42,32 @@ where
         .map(|(id,)| users::Id(id))
         .map_err(|e| match e {
             sqlx::Error::RowNotFound => Error::InvalidToken,
-            e => Error::Sqlx(e),
+            e => context("auth", "tokens")(e),
         })
 }
 
-pub async fn auth_admin<'a, E>(token: Token, db: E) -> DbResult<users::Id>
+/// Authenticates an admin, `require_totp` refuses admins without an enabled second factor.
+#[instrument(skip_all)]
+pub async fn auth_admin<'a, E>(token: Token, require_totp: bool, db: E) -> DbResult<users::Id>
 where
     E: PgExecutor<'a>,
 {
     const QUERY: &str =
-        "select id from tokens inner join users on user_id = id where token = $1 and admin = true";
+        "select id from tokens inner join users on user_id = id where token = $1 and admin = true and (totp_enabled or not $2)";
 
     sqlx::query_as(QUERY)
         .bind(token.0)
+        .bind(require_totp)
         .fetch_one(db)
         .await
         .map(|(id,)| users::Id(id))
         .map_err(|e| match e {
             sqlx::Error::RowNotFound => Error::InvalidToken,
-            e => Error::Sqlx(e),
+            e => context("auth_admin", "tokens")(e),
         })
 }
 
+#[instrument(skip_all)]
 pub async fn delete<'a, E>(token: Token, db: E) -> DbResult<()>
 where
     E: PgExecutor<'a>,
@@ -71,10 +78,11 @@ where
         .bind(token.0)
         .execute(db)
         .await
-        .map_err(Error::Sqlx)
+        .map_err(context("delete", "tokens"))
         .and_then(at_least_one(Error::InvalidToken))
 }
 
+#[instrument(skip_all)]
 pub async fn logout_user<'a, E>(id: users::Id, db: E) -> DbResult<()>
 where
     E: PgExecutor<'a>,
@@ -86,5 +94,20 @@ where
         .execute(db)
         .await
         .map(|_| ())
-        .map_err(Error::Sqlx)
+        .map_err(context("logout_user", "tokens"))
+}
+
+/// Deletes expired tokens, returning how many there were.
+#[instrument(skip_all)]
+pub async fn purge_expired<'a, E>(db: E) -> DbResult<u64>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "delete from tokens where expiration <= CURRENT_TIMESTAMP";
+
+    sqlx::query(QUERY)
+        .execute(db)
+        .await
+        .map(|result| result.rows_affected())
+        .map_err(context("purge_expired", "tokens"))
 }
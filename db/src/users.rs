@@ -1,10 +1,18 @@
 use sqlx::{postgres::PgRow, PgExecutor, Row};
+use tracing::instrument;
 
-use crate::result::{at_least_one, code_to_error, DbResult, Error};
+use crate::result::{at_least_one, code_to_error, context, Context, DbResult, Error};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Id(pub i32);
 
+/// Second authentication factor of a user.
+#[derive(Debug)]
+pub struct Totp {
+    pub secret: Option<String>,
+    pub enabled: bool,
+}
+
 #[derive(Debug)]
 pub struct Summary {
     pub id: Id,
@@ -22,6 +30,7 @@ impl<'r> sqlx::FromRow<'r, PgRow> for Summary {
     }
 }
 
+#[instrument(skip_all)]
 pub async fn create<'a, E>(email: &str, password: &str, db: E) -> DbResult<Id>
 where
     E: PgExecutor<'a>,
@@ -35,9 +44,34 @@ where
         .fetch_one(db)
         .await
         .map(|(id,)| Id(id))
-        .map_err(code_to_error(&[("23505", |_| Error::DuplicateEmail)]))
+        .map_err(code_to_error(
+            Context::new("create", "users"),
+            &[("23505", |_| Error::DuplicateEmail)],
+        ))
 }
 
+/// Finds or creates the confirmed user owning an email verified by a trusted third party.
+///
+/// Created users get a random password, so they can only log in through that third party. So do
+/// existing users who never confirmed their account, whose second factor is also reset, since
+/// whoever registered it may not own the email.
+#[instrument(skip_all)]
+pub async fn upsert_verified<'a, E>(email: &str, db: E) -> DbResult<(Id, bool)>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str =
+        "insert into users(email,password,confirm_limit)values($1,crypt(gen_random_uuid()::text,gen_salt('bf')),null) on conflict((lower(email))) do update set password=case when users.confirm_limit is null then users.password else excluded.password end,totp_secret=case when users.confirm_limit is null then users.totp_secret end,totp_enabled=users.totp_enabled and users.confirm_limit is null,totp_last_step=case when users.confirm_limit is null then users.totp_last_step end,confirm_limit=null returning id,admin";
+
+    sqlx::query_as(QUERY)
+        .bind(email)
+        .fetch_one(db)
+        .await
+        .map(|(id, admin)| (Id(id), admin))
+        .map_err(context("upsert_verified", "users"))
+}
+
+#[instrument(skip_all)]
 pub async fn confirm<'a, E>(id: Id, db: E) -> DbResult<()>
 where
     E: PgExecutor<'a>,
@@ -48,10 +82,11 @@ where
         .bind(id.0)
         .execute(db)
         .await
-        .map_err(Error::Sqlx)
+        .map_err(context("confirm", "users"))
         .and_then(at_least_one(Error::InvalidUserId))
 }
 
+#[instrument(skip_all)]
 pub async fn list_candidates<'a, E>(db: E) -> DbResult<Vec<Summary>>
 where
     E: PgExecutor<'a>,
@@ -59,28 +94,83 @@ where
     sqlx::query_as(LIST_CANDIDATES_QUERY)
         .fetch_all(db)
         .await
-        .map_err(Error::Sqlx)
+        .map_err(context("list_candidates", "users"))
 }
 
+/// Finds a confirmed user by its credentials. Locked accounts are refused whatever the password,
+/// so that the lock tells nothing about it.
+#[instrument(skip_all)]
 pub async fn find_by_credentials<'a, E>(email: &str, password: &str, db: E) -> DbResult<(Id, bool)>
 where
     E: PgExecutor<'a>,
 {
     const QUERY: &str =
-        "select id,admin from users where confirm_limit is null and email=$1 and password=crypt($2,password)";
+        "select id,admin,coalesce(locked_until > CURRENT_TIMESTAMP, false),password=crypt($2,password) from users where confirm_limit is null and lower(email)=lower($1)";
 
     sqlx::query_as(QUERY)
         .bind(email)
         .bind(password)
         .fetch_optional(db)
         .await
-        .map_err(Error::Sqlx)
-        .and_then(|opt| {
-            opt.map(|(id, admin)| (Id(id), admin))
-                .ok_or(Error::InvalidCredentials)
+        .map_err(context("find_by_credentials", "users"))
+        .and_then(|opt| match opt {
+            Some((_, _, true, _)) => Err(Error::AccountLocked),
+            Some((id, admin, false, true)) => Ok((Id(id), admin)),
+            _ => Err(Error::InvalidCredentials),
         })
 }
 
+#[instrument(skip_all)]
+pub async fn lock<'a, E>(email: &str, duration: f64, db: E) -> DbResult<()>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str =
+        "update users set locked_until=CURRENT_TIMESTAMP + make_interval(secs => $2) where lower(email)=lower($1)";
+
+    sqlx::query(QUERY)
+        .bind(email)
+        .bind(duration)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(context("lock", "users"))
+}
+
+/// Lifts the lock on an account and returns its email.
+#[instrument(skip_all)]
+pub async fn unlock<'a, E>(id: Id, db: E) -> DbResult<String>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "update users set locked_until=null where id=$1 returning email";
+
+    sqlx::query_as(QUERY)
+        .bind(id.0)
+        .fetch_optional(db)
+        .await
+        .map_err(context("unlock", "users"))
+        .and_then(|opt| opt.map(|(email,)| email).ok_or(Error::InvalidUserId))
+}
+
+/// Whether the account is locked after too many failed logins.
+#[instrument(skip_all)]
+pub async fn locked<'a, E>(id: Id, db: E) -> DbResult<bool>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str =
+        "select coalesce(locked_until > CURRENT_TIMESTAMP, false) from users where id=$1";
+
+    sqlx::query_as(QUERY)
+        .bind(id.0)
+        .fetch_optional(db)
+        .await
+        .map_err(context("locked", "users"))
+        .and_then(|opt| opt.map(|(locked,)| locked).ok_or(Error::InvalidUserId))
+}
+
+#[instrument(skip_all)]
 pub async fn set_admin<'a, E>(id: Id, admin: bool, db: E) -> DbResult<()>
 where
     E: PgExecutor<'a>,
@@ -92,6 +182,106 @@ where
         .bind(id.0)
         .execute(db)
         .await
-        .map_err(Error::Sqlx)
+        .map_err(context("set_admin", "users"))
+        .and_then(at_least_one(Error::InvalidUserId))
+}
+
+#[instrument(skip_all)]
+pub async fn email<'a, E>(id: Id, db: E) -> DbResult<String>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "select email from users where id=$1";
+
+    sqlx::query_as(QUERY)
+        .bind(id.0)
+        .fetch_optional(db)
+        .await
+        .map_err(context("email", "users"))
+        .and_then(|opt| opt.map(|(email,)| email).ok_or(Error::InvalidUserId))
+}
+
+#[instrument(skip_all)]
+pub async fn totp<'a, E>(id: Id, db: E) -> DbResult<Totp>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "select totp_secret,totp_enabled from users where id=$1";
+
+    sqlx::query_as(QUERY)
+        .bind(id.0)
+        .fetch_optional(db)
+        .await
+        .map_err(context("totp", "users"))
+        .and_then(|opt| {
+            opt.map(|(secret, enabled)| Totp { secret, enabled })
+                .ok_or(Error::InvalidUserId)
+        })
+}
+
+/// Stores a pending secret, the second factor is only required once [`enable_totp`] is called.
+#[instrument(skip_all)]
+pub async fn set_totp_secret<'a, E>(id: Id, secret: &str, db: E) -> DbResult<()>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "update users set totp_secret=$1 where id=$2 and not totp_enabled";
+
+    sqlx::query(QUERY)
+        .bind(secret)
+        .bind(id.0)
+        .execute(db)
+        .await
+        .map_err(context("set_totp_secret", "users"))
+        .and_then(at_least_one(Error::SecondFactorAlreadyEnabled))
+}
+
+#[instrument(skip_all)]
+pub async fn enable_totp<'a, E>(id: Id, db: E) -> DbResult<()>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str =
+        "update users set totp_enabled=true where id=$1 and totp_secret is not null";
+
+    sqlx::query(QUERY)
+        .bind(id.0)
+        .execute(db)
+        .await
+        .map_err(context("enable_totp", "users"))
+        .and_then(at_least_one(Error::InvalidUserId))
+}
+
+/// Records the time step of an accepted TOTP code, failing unless it is newer than the last one so
+/// that a code cannot be used twice.
+#[instrument(skip_all)]
+pub async fn use_totp_step<'a, E>(id: Id, step: i64, db: E) -> DbResult<()>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str =
+        "update users set totp_last_step=$2 where id=$1 and (totp_last_step is null or totp_last_step < $2)";
+
+    sqlx::query(QUERY)
+        .bind(id.0)
+        .bind(step)
+        .execute(db)
+        .await
+        .map_err(context("use_totp_step", "users"))
+        .and_then(at_least_one(Error::InvalidSecondFactor))
+}
+
+#[instrument(skip_all)]
+pub async fn disable_totp<'a, E>(id: Id, db: E) -> DbResult<()>
+where
+    E: PgExecutor<'a>,
+{
+    const QUERY: &str = "update users set totp_secret=null,totp_enabled=false where id=$1";
+
+    sqlx::query(QUERY)
+        .bind(id.0)
+        .execute(db)
+        .await
+        .map_err(context("disable_totp", "users"))
         .and_then(at_least_one(Error::InvalidUserId))
 }
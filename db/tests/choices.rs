@@ -0,0 +1,91 @@
+mod common;
+
+mod create {
+    use crate::common::{connect_db, data::*};
+
+    use db::{choices, images, result::Error, sessions, users};
+    use sqlx::Acquire;
+
+    #[tokio::test]
+    async fn replaces() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let user = users::create(USERS[0].0, USERS[0].1, &mut trans)
+            .await
+            .unwrap();
+        let session = sessions::create("Session", DATES[0](), DATES[1](), DATES[2](), &mut trans)
+            .await
+            .unwrap();
+        let first = images::create(&mut trans).await.unwrap();
+        let second = images::create(&mut trans).await.unwrap();
+
+        let id = choices::create(user, session, first, &mut trans)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            choices::create(user, session, second, &mut trans)
+                .await
+                .unwrap(),
+            id
+        );
+        assert_eq!(
+            choices::by_session(session, &mut trans).await.unwrap(),
+            [(user, second)]
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_image() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let user = users::create(USERS[0].0, USERS[0].1, &mut trans)
+            .await
+            .unwrap();
+        let session = sessions::create("Session", DATES[0](), DATES[1](), DATES[2](), &mut trans)
+            .await
+            .unwrap();
+
+        let error = choices::create(user, session, images::Id(9182734), &mut trans)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, Error::InvalidImage));
+    }
+}
+
+mod by_session {
+    use crate::common::{connect_db, data::*};
+
+    use db::{choices, images, sessions, users};
+    use sqlx::Acquire;
+
+    #[tokio::test]
+    async fn ordered_by_image() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let session = sessions::create("Session", DATES[0](), DATES[1](), DATES[2](), &mut trans)
+            .await
+            .unwrap();
+        let first = images::create(&mut trans).await.unwrap();
+        let second = images::create(&mut trans).await.unwrap();
+        let mut users = Vec::new();
+
+        for ((email, password), image) in USERS.iter().zip([second, first, second]) {
+            let user = users::create(email, password, &mut trans).await.unwrap();
+
+            choices::create(user, session, image, &mut trans)
+                .await
+                .unwrap();
+            users.push(user);
+        }
+
+        assert_eq!(
+            choices::by_session(session, &mut trans).await.unwrap(),
+            [(users[1], first), (users[0], second), (users[2], second)]
+        );
+    }
+}
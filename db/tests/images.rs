@@ -14,3 +14,604 @@ mod create {
         images::create(&mut trans).await.unwrap();
     }
 }
+
+mod existing {
+    use crate::common::connect_db;
+
+    use db::images;
+    use sqlx::Acquire;
+
+    #[tokio::test]
+    async fn basic() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let image = images::create(&mut trans).await.unwrap();
+
+        assert_eq!(
+            images::existing(&[image, images::Id(-1)], &mut trans)
+                .await
+                .unwrap(),
+            [image]
+        );
+    }
+}
+
+mod update {
+    use crate::common::connect_db;
+
+    use db::{
+        images::{self, Metadata},
+        result::Error,
+    };
+    use sqlx::Acquire;
+
+    #[tokio::test]
+    async fn basic() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let image = images::create(&mut trans).await.unwrap();
+
+        images::update(
+            image,
+            &Metadata {
+                title: Some(String::from("Title")),
+                ..Default::default()
+            },
+            &mut trans,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn invalid_image() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        assert!(matches!(
+            images::update(images::Id(-1), &Default::default(), &mut trans)
+                .await
+                .unwrap_err(),
+            Error::InvalidImage
+        ));
+    }
+}
+
+mod access {
+    use crate::common::{connect_db, data::*};
+
+    use db::{
+        images::{self, Access},
+        images_associations, registrations,
+        result::Error,
+        sessions, users,
+    };
+    use sqlx::{types::time::OffsetDateTime, Acquire};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn public() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let image = images::create(&mut trans).await.unwrap();
+
+        assert_eq!(
+            images::access(image, None, false, &mut trans)
+                .await
+                .unwrap(),
+            Access::Denied
+        );
+
+        images::set_public(image, true, &mut trans).await.unwrap();
+
+        assert_eq!(
+            images::access(image, None, false, &mut trans)
+                .await
+                .unwrap(),
+            Access::Public
+        );
+        assert!(matches!(
+            images::set_public(images::Id(-1), true, &mut trans)
+                .await
+                .unwrap_err(),
+            Error::InvalidImage
+        ));
+    }
+
+    #[tokio::test]
+    async fn admin() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let image = images::create(&mut trans).await.unwrap();
+        let admin = users::create(USERS[0].0, USERS[0].1, &mut trans)
+            .await
+            .unwrap();
+
+        users::set_admin(admin, true, &mut trans).await.unwrap();
+
+        assert_eq!(
+            images::access(image, Some(admin), false, &mut trans)
+                .await
+                .unwrap(),
+            Access::Granted
+        );
+        assert_eq!(
+            images::access(image, Some(admin), true, &mut trans)
+                .await
+                .unwrap(),
+            Access::Denied
+        );
+        assert_eq!(
+            images::access(images::Id(-1), Some(admin), false, &mut trans)
+                .await
+                .unwrap(),
+            Access::Denied
+        );
+    }
+
+    #[tokio::test]
+    async fn participant() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let started = OffsetDateTime::now_utc() - Duration::from_secs(3600);
+        let started = sessions::create("Started", started, DATES[0](), DATES[1](), &mut trans)
+            .await
+            .unwrap();
+        let pending = sessions::create("Pending", DATES[0](), DATES[1](), DATES[2](), &mut trans)
+            .await
+            .unwrap();
+        let image = images::create(&mut trans).await.unwrap();
+        let user = users::create(USERS[0].0, USERS[0].1, &mut trans)
+            .await
+            .unwrap();
+        let other = users::create(USERS[1].0, USERS[1].1, &mut trans)
+            .await
+            .unwrap();
+
+        images_associations::add(pending, &[image], &mut trans)
+            .await
+            .unwrap();
+        registrations::create(user, pending, &mut trans)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            images::access(image, Some(user), false, &mut trans)
+                .await
+                .unwrap(),
+            Access::Denied
+        );
+
+        images_associations::add(started, &[image], &mut trans)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            images::access(image, Some(user), false, &mut trans)
+                .await
+                .unwrap(),
+            Access::Denied
+        );
+
+        registrations::create(user, started, &mut trans)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            images::access(image, Some(user), false, &mut trans)
+                .await
+                .unwrap(),
+            Access::Granted
+        );
+        assert_eq!(
+            images::access(image, Some(other), false, &mut trans)
+                .await
+                .unwrap(),
+            Access::Denied
+        );
+        assert_eq!(
+            images::access(image, None, false, &mut trans)
+                .await
+                .unwrap(),
+            Access::Denied
+        );
+    }
+}
+
+mod session_access {
+    use crate::common::{connect_db, data::*};
+
+    use db::{images, registrations, sessions, users};
+    use sqlx::{types::time::OffsetDateTime, Acquire};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn admin() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let session = sessions::create("Pending", DATES[0](), DATES[1](), DATES[2](), &mut trans)
+            .await
+            .unwrap();
+        let admin = users::create(USERS[0].0, USERS[0].1, &mut trans)
+            .await
+            .unwrap();
+
+        assert!(!images::session_access(session, admin, false, &mut trans)
+            .await
+            .unwrap());
+
+        users::set_admin(admin, true, &mut trans).await.unwrap();
+
+        assert!(images::session_access(session, admin, false, &mut trans)
+            .await
+            .unwrap());
+        assert!(!images::session_access(session, admin, true, &mut trans)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn participant() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let started = OffsetDateTime::now_utc() - Duration::from_secs(3600);
+        let started = sessions::create("Started", started, DATES[0](), DATES[1](), &mut trans)
+            .await
+            .unwrap();
+        let pending = sessions::create("Pending", DATES[0](), DATES[1](), DATES[2](), &mut trans)
+            .await
+            .unwrap();
+        let user = users::create(USERS[0].0, USERS[0].1, &mut trans)
+            .await
+            .unwrap();
+
+        registrations::create(user, pending, &mut trans)
+            .await
+            .unwrap();
+
+        assert!(!images::session_access(pending, user, false, &mut trans)
+            .await
+            .unwrap());
+        assert!(!images::session_access(started, user, false, &mut trans)
+            .await
+            .unwrap());
+
+        registrations::create(user, started, &mut trans)
+            .await
+            .unwrap();
+
+        assert!(images::session_access(started, user, false, &mut trans)
+            .await
+            .unwrap());
+    }
+}
+
+mod by_session {
+    use crate::common::{connect_db, data::*};
+
+    use db::{
+        images::{self, Metadata},
+        images_associations, sessions, tags,
+    };
+    use sqlx::Acquire;
+
+    #[tokio::test]
+    async fn with_metadata() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let image_1 = images::create(&mut trans).await.unwrap();
+        let image_2 = images::create(&mut trans).await.unwrap();
+        let other = images::create(&mut trans).await.unwrap();
+        let session = sessions::create("Session", DATES[0](), DATES[1](), DATES[2](), &mut trans)
+            .await
+            .unwrap();
+        let metadata = Metadata {
+            title: Some(String::from("Harbour")),
+            description: Some(String::from("Boats at dawn")),
+            alt_text: Some(String::from("Three boats moored in a harbour")),
+        };
+
+        images::update(image_1, &metadata, &mut trans)
+            .await
+            .unwrap();
+        tags::set(
+            image_1,
+            &[String::from("sea"), String::from("Boats")],
+            &mut trans,
+        )
+        .await
+        .unwrap();
+        images_associations::add(session, &[image_1, image_2], &mut trans)
+            .await
+            .unwrap();
+
+        let images = images::by_session(session, &mut trans).await.unwrap();
+
+        assert_eq!(
+            images.iter().map(|image| image.id).collect::<Vec<_>>(),
+            [image_1, image_2]
+        );
+        assert!(!images.iter().any(|image| image.id == other));
+        assert_eq!(images[0].metadata, metadata);
+        assert_eq!(images[0].tags, ["Boats", "sea"]);
+        assert_eq!(images[1].metadata, Metadata::default());
+        assert!(images[1].tags.is_empty());
+    }
+}
+
+mod library {
+    use crate::common::{connect_db, data::*};
+
+    use db::{
+        images::{self, Cursor, Filter, Metadata, Sort},
+        images_associations, sessions, tags, users,
+    };
+    use sqlx::{Acquire, PgConnection};
+
+    /// Images uploaded by a new user, so that the filter ignores the other images of the database.
+    async fn upload(count: usize, db: &mut PgConnection) -> (Filter, Vec<images::Id>) {
+        let user = users::create(USERS[1].0, USERS[1].1, &mut *db)
+            .await
+            .unwrap();
+        let mut ids = Vec::new();
+
+        for _ in 0..count {
+            ids.push(images::create_by(Some(user), &mut *db).await.unwrap());
+        }
+
+        (
+            Filter {
+                uploader: Some(user),
+                ..Default::default()
+            },
+            ids,
+        )
+    }
+
+    fn ids(entries: &[images::Entry]) -> Vec<images::Id> {
+        entries.iter().map(|entry| entry.image.id).collect()
+    }
+
+    #[tokio::test]
+    async fn pages() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let (filter, images) = upload(3, &mut trans).await;
+
+        let first = images::library(&filter, Sort::Newest, None, 2, &mut trans)
+            .await
+            .unwrap();
+        let cursor = first.last().unwrap().cursor(Sort::Newest);
+        let second = images::library(&filter, Sort::Newest, Some(&cursor), 2, &mut trans)
+            .await
+            .unwrap();
+
+        assert_eq!(ids(&first), [images[2], images[1]]);
+        assert_eq!(ids(&second), [images[0]]);
+        assert_eq!(first[0].uploader, filter.uploader);
+    }
+
+    #[tokio::test]
+    async fn oldest() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let (filter, images) = upload(3, &mut trans).await;
+        let cursor = Cursor::Uploaded(
+            images::library(&filter, Sort::Oldest, None, 1, &mut trans)
+                .await
+                .unwrap()[0]
+                .uploaded_at,
+            images[0],
+        );
+
+        let entries = images::library(&filter, Sort::Oldest, Some(&cursor), 10, &mut trans)
+            .await
+            .unwrap();
+
+        assert_eq!(ids(&entries), [images[1], images[2]]);
+    }
+
+    #[tokio::test]
+    async fn title() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let (filter, images) = upload(3, &mut trans).await;
+
+        for (image, title) in [(images[0], "beach"), (images[2], "Alps")] {
+            let metadata = Metadata {
+                title: Some(String::from(title)),
+                ..Default::default()
+            };
+
+            images::update(image, &metadata, &mut trans).await.unwrap();
+        }
+
+        let first = images::library(&filter, Sort::Title, None, 2, &mut trans)
+            .await
+            .unwrap();
+        let cursor = first.last().unwrap().cursor(Sort::Title);
+        let second = images::library(&filter, Sort::Title, Some(&cursor), 2, &mut trans)
+            .await
+            .unwrap();
+
+        assert_eq!(ids(&first), [images[2], images[0]]);
+        assert_eq!(ids(&second), [images[1]]);
+    }
+
+    #[tokio::test]
+    async fn filters() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let (filter, images) = upload(3, &mut trans).await;
+        let session = sessions::create("Session", DATES[0](), DATES[1](), DATES[2](), &mut trans)
+            .await
+            .unwrap();
+
+        tags::set(
+            images[0],
+            &[String::from("Library_a"), String::from("library_b")],
+            &mut trans,
+        )
+        .await
+        .unwrap();
+        tags::set(images[1], &[String::from("library_a")], &mut trans)
+            .await
+            .unwrap();
+        images_associations::add(session, &[images[0]], &mut trans)
+            .await
+            .unwrap();
+
+        let tagged = Filter {
+            tags: vec![String::from("LIBRARY_A"), String::from("library_b")],
+            ..filter.clone()
+        };
+        let unused = Filter {
+            unused: true,
+            ..filter.clone()
+        };
+        let later = Filter {
+            uploaded_after: Some(DATES[0]()),
+            ..filter.clone()
+        };
+
+        let entries = images::library(&tagged, Sort::Newest, None, 10, &mut trans)
+            .await
+            .unwrap();
+
+        assert_eq!(ids(&entries), [images[0]]);
+        assert_eq!(entries[0].sessions, [session]);
+        assert_eq!(
+            ids(
+                &images::library(&unused, Sort::Newest, None, 10, &mut trans)
+                    .await
+                    .unwrap()
+            ),
+            [images[2], images[1]]
+        );
+        assert!(images::library(&later, Sort::Newest, None, 10, &mut trans)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
+
+mod near_duplicates {
+    use crate::common::{connect_db, data::*};
+
+    use db::{
+        images::{self, NearDuplicate},
+        images_associations, sessions,
+    };
+    use sqlx::Acquire;
+
+    #[tokio::test]
+    async fn session() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let session = sessions::create("Session", DATES[0](), DATES[1](), DATES[2](), &mut trans)
+            .await
+            .unwrap();
+        let mut ids = Vec::new();
+
+        // The first two differ by 3 bits, the third by 16 and the last one has no hash.
+        for phash in [
+            Some(0xF0F0_0000_0000_0007),
+            Some(0xF0F0_0000_0000_0000),
+            Some(0xFFFF_0000_0000_00FF),
+            None,
+        ] {
+            let id = images::create(&mut trans).await.unwrap();
+
+            if let Some(phash) = phash {
+                images::set_phash(id, phash, &mut trans).await.unwrap();
+            }
+            ids.push(id);
+        }
+        images_associations::add(session, &ids, &mut trans)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            images::near_duplicates(Some(session), None, 10, &mut trans)
+                .await
+                .unwrap(),
+            [NearDuplicate {
+                images: (ids[0], ids[1]),
+                distance: 3
+            }]
+        );
+        assert_eq!(
+            images::near_duplicates(Some(session), Some(&ids[2..]), 20, &mut trans)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(
+            images::near_duplicates(Some(session), Some(&ids[3..]), 64, &mut trans)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn library() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let mut ids = Vec::new();
+
+        // The first two share their top byte, the last one only differs from them there.
+        for phash in [
+            0xF0F0_0000_0000_0007,
+            0xF0F0_0000_0000_0000,
+            0x0FF0_0000_0000_0000,
+        ] {
+            let id = images::create(&mut trans).await.unwrap();
+
+            images::set_phash(id, phash, &mut trans).await.unwrap();
+            ids.push(id);
+        }
+
+        assert_eq!(
+            images::near_duplicates(None, Some(&ids), 64, &mut trans)
+                .await
+                .unwrap(),
+            [NearDuplicate {
+                images: (ids[0], ids[1]),
+                distance: 3
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn without_phash() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let hashed = images::create(&mut trans).await.unwrap();
+        let missing = images::create(&mut trans).await.unwrap();
+
+        images::set_phash(hashed, u64::MAX, &mut trans)
+            .await
+            .unwrap();
+
+        let ids = images::without_phash(&mut trans).await.unwrap();
+
+        assert!(ids.contains(&missing));
+        assert!(!ids.contains(&hashed));
+    }
+}
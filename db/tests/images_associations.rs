@@ -210,6 +210,89 @@ mod delete_by_session {
     }
 }
 
+mod add {
+    use crate::common::{connect_db, data::*};
+
+    use db::{images, images_associations, result::Error, sessions};
+    use sqlx::Acquire;
+
+    #[tokio::test]
+    async fn skips_associated() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let image_1 = images::create(&mut trans).await.unwrap();
+        let image_2 = images::create(&mut trans).await.unwrap();
+        let session = sessions::create("Session", DATES[0](), DATES[1](), DATES[2](), &mut trans)
+            .await
+            .unwrap();
+
+        images_associations::create(image_1, session, &mut trans)
+            .await
+            .unwrap();
+
+        let added = images_associations::add(session, &[image_1, image_2, image_2], &mut trans)
+            .await
+            .unwrap();
+
+        assert_eq!(added, [image_2]);
+    }
+
+    #[tokio::test]
+    async fn invalid_image() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let image = images::create(&mut trans).await.unwrap();
+        let session = sessions::create("Session", DATES[0](), DATES[1](), DATES[2](), &mut trans)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            images_associations::add(session, &[image, images::Id(-1)], &mut trans)
+                .await
+                .unwrap_err(),
+            Error::InvalidImage
+        ));
+    }
+}
+
+mod remove {
+    use crate::common::{connect_db, data::*};
+
+    use db::{images, images_associations, sessions};
+    use sqlx::Acquire;
+
+    #[tokio::test]
+    async fn only_associated() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let image_1 = images::create(&mut trans).await.unwrap();
+        let image_2 = images::create(&mut trans).await.unwrap();
+        let image_3 = images::create(&mut trans).await.unwrap();
+        let session = sessions::create("Session", DATES[0](), DATES[1](), DATES[2](), &mut trans)
+            .await
+            .unwrap();
+
+        images_associations::add(session, &[image_1, image_2], &mut trans)
+            .await
+            .unwrap();
+
+        let removed = images_associations::remove(session, &[image_1, image_3], &mut trans)
+            .await
+            .unwrap();
+
+        assert_eq!(removed, [image_1]);
+        assert_eq!(
+            images_associations::by_session(session, &mut trans)
+                .await
+                .unwrap(),
+            [image_2]
+        );
+    }
+}
+
 mod by_session {
     use crate::common::{connect_db, data::*};
 
@@ -239,7 +322,7 @@ mod by_session {
             .unwrap();
 
         assert_eq!(images.len(), 2);
-        assert!(images.iter().any(|&i| i == image_1));
-        assert!(images.iter().any(|&i| i == image_2));
+        assert!(images.contains(&image_1));
+        assert!(images.contains(&image_2));
     }
 }
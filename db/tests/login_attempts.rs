@@ -0,0 +1,124 @@
+mod common;
+
+const BACKOFF: db::login_attempts::Backoff = db::login_attempts::Backoff {
+    threshold: 2,
+    base_delay: 60.,
+    max_delay: 3600.,
+    reset_after: 86400.,
+};
+
+mod check {
+    use crate::{common::connect_db, BACKOFF};
+
+    use db::{
+        login_attempts::{self, Scope},
+        result::Error,
+    };
+    use sqlx::Acquire;
+
+    #[tokio::test]
+    async fn no_failures() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        login_attempts::check(Scope::Ip, "127.0.0.1", BACKOFF, &mut trans)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn under_threshold() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        login_attempts::record_failure(Scope::Ip, "127.0.0.1", BACKOFF, &mut trans)
+            .await
+            .unwrap();
+        login_attempts::check(Scope::Ip, "127.0.0.1", BACKOFF, &mut trans)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn over_threshold() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        for _ in 0..BACKOFF.threshold {
+            login_attempts::record_failure(Scope::Ip, "127.0.0.1", BACKOFF, &mut trans)
+                .await
+                .unwrap();
+        }
+
+        assert!(matches!(
+            login_attempts::check(Scope::Ip, "127.0.0.1", BACKOFF, &mut trans)
+                .await
+                .unwrap_err(),
+            Error::TooManyAttempts
+        ));
+    }
+
+    #[tokio::test]
+    async fn other_scope() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        for _ in 0..BACKOFF.threshold {
+            login_attempts::record_failure(Scope::Ip, "127.0.0.1", BACKOFF, &mut trans)
+                .await
+                .unwrap();
+        }
+
+        login_attempts::check(Scope::Email, "127.0.0.1", BACKOFF, &mut trans)
+            .await
+            .unwrap();
+    }
+}
+
+mod record_failure {
+    use crate::{common::connect_db, BACKOFF};
+
+    use db::login_attempts::{self, Scope};
+    use sqlx::Acquire;
+
+    #[tokio::test]
+    async fn counts() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        for expected in 1..=3 {
+            let failures =
+                login_attempts::record_failure(Scope::Email, "a@b.c", BACKOFF, &mut trans)
+                    .await
+                    .unwrap();
+
+            assert_eq!(failures, expected);
+        }
+    }
+}
+
+mod clear {
+    use crate::{common::connect_db, BACKOFF};
+
+    use db::login_attempts::{self, Scope};
+    use sqlx::Acquire;
+
+    #[tokio::test]
+    async fn resets() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        for _ in 0..BACKOFF.threshold {
+            login_attempts::record_failure(Scope::Email, "a@b.c", BACKOFF, &mut trans)
+                .await
+                .unwrap();
+        }
+        login_attempts::clear(Scope::Email, "a@b.c", &mut trans)
+            .await
+            .unwrap();
+
+        login_attempts::check(Scope::Email, "a@b.c", BACKOFF, &mut trans)
+            .await
+            .unwrap();
+    }
+}
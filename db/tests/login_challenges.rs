@@ -0,0 +1,72 @@
+mod common;
+
+mod find {
+    use crate::common::{connect_db, data::*};
+
+    use db::{
+        login_challenges::{self, Challenge},
+        result::Error,
+        users,
+    };
+    use sqlx::{types::Uuid, Acquire};
+
+    #[tokio::test]
+    async fn valid() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let (email, pass) = USERS[0];
+
+        let id = users::create(email, pass, &mut trans).await.unwrap();
+        let challenge = login_challenges::create(id, &mut trans).await.unwrap();
+        let found = login_challenges::find(challenge, &mut trans).await.unwrap();
+
+        assert_eq!(found, (id, email.to_string(), false));
+    }
+
+    #[tokio::test]
+    async fn doesnt_exist() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let challenge = Challenge(Uuid::from_u128(81726354));
+
+        assert!(matches!(
+            login_challenges::find(challenge, &mut trans)
+                .await
+                .unwrap_err(),
+            Error::InvalidLoginChallenge
+        ));
+    }
+}
+
+mod consume {
+    use crate::common::{connect_db, data::*};
+
+    use db::{login_challenges, result::Error, users};
+    use sqlx::Acquire;
+
+    #[tokio::test]
+    async fn only_once() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let (email, pass) = USERS[0];
+
+        let id = users::create(email, pass, &mut trans).await.unwrap();
+        let challenge = login_challenges::create(id, &mut trans).await.unwrap();
+        login_challenges::consume(challenge, &mut trans)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            login_challenges::consume(challenge, &mut trans)
+                .await
+                .unwrap_err(),
+            Error::InvalidLoginChallenge
+        ));
+        assert!(matches!(
+            login_challenges::find(challenge, &mut trans)
+                .await
+                .unwrap_err(),
+            Error::InvalidLoginChallenge
+        ));
+    }
+}
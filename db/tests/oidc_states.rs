@@ -0,0 +1,53 @@
+mod common;
+
+mod consume {
+    use crate::common::connect_db;
+
+    use db::{
+        oidc_states::{self, State},
+        result::Error,
+    };
+    use sqlx::{types::Uuid, Acquire};
+
+    #[tokio::test]
+    async fn valid() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let state = oidc_states::create("nonce", "verifier", &mut trans)
+            .await
+            .unwrap();
+        let (nonce, verifier) = oidc_states::consume(state, &mut trans).await.unwrap();
+
+        assert_eq!(nonce, "nonce");
+        assert_eq!(verifier, "verifier");
+    }
+
+    #[tokio::test]
+    async fn only_once() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let state = oidc_states::create("nonce", "verifier", &mut trans)
+            .await
+            .unwrap();
+        oidc_states::consume(state, &mut trans).await.unwrap();
+
+        assert!(matches!(
+            oidc_states::consume(state, &mut trans).await.unwrap_err(),
+            Error::InvalidOidcState
+        ));
+    }
+
+    #[tokio::test]
+    async fn doesnt_exist() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let state = State(Uuid::from_u128(81726354));
+
+        assert!(matches!(
+            oidc_states::consume(state, &mut trans).await.unwrap_err(),
+            Error::InvalidOidcState
+        ));
+    }
+}
@@ -0,0 +1,94 @@
+mod common;
+
+mod consume {
+    use crate::common::{connect_db, data::*};
+
+    use db::{recovery_codes, result::Error, users};
+    use sqlx::Acquire;
+
+    #[tokio::test]
+    async fn valid() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let (email, pass) = USERS[0];
+        let codes = [String::from("abcdef"), String::from("ghijkl")];
+
+        let id = users::create(email, pass, &mut trans).await.unwrap();
+        recovery_codes::replace(id, &codes, &mut trans)
+            .await
+            .unwrap();
+
+        recovery_codes::consume(id, &codes[1], &mut trans)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_once() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let (email, pass) = USERS[0];
+        let codes = [String::from("abcdef")];
+
+        let id = users::create(email, pass, &mut trans).await.unwrap();
+        recovery_codes::replace(id, &codes, &mut trans)
+            .await
+            .unwrap();
+        recovery_codes::consume(id, &codes[0], &mut trans)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            recovery_codes::consume(id, &codes[0], &mut trans)
+                .await
+                .unwrap_err(),
+            Error::InvalidSecondFactor
+        ));
+    }
+
+    #[tokio::test]
+    async fn replaced() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let (email, pass) = USERS[0];
+
+        let id = users::create(email, pass, &mut trans).await.unwrap();
+        recovery_codes::replace(id, &[String::from("abcdef")], &mut trans)
+            .await
+            .unwrap();
+        recovery_codes::replace(id, &[String::from("ghijkl")], &mut trans)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            recovery_codes::consume(id, "abcdef", &mut trans)
+                .await
+                .unwrap_err(),
+            Error::InvalidSecondFactor
+        ));
+    }
+
+    #[tokio::test]
+    async fn other_user() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let codes = [String::from("abcdef")];
+
+        let id_1 = users::create(USERS[0].0, USERS[0].1, &mut trans)
+            .await
+            .unwrap();
+        let id_2 = users::create(USERS[1].0, USERS[1].1, &mut trans)
+            .await
+            .unwrap();
+        recovery_codes::replace(id_1, &codes, &mut trans)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            recovery_codes::consume(id_2, &codes[0], &mut trans)
+                .await
+                .unwrap_err(),
+            Error::InvalidSecondFactor
+        ));
+    }
+}
@@ -139,3 +139,69 @@ mod by_session {
             .any(|user| user.id == user_3 && user.email == USERS[2].0));
     }
 }
+
+mod count_by_state {
+    use crate::common::{connect_db, data::*};
+
+    use db::{
+        registrations,
+        sessions::{self, State},
+        users,
+    };
+    use sqlx::{types::time::OffsetDateTime, Acquire};
+    use std::time::Duration;
+
+    fn count(counts: &[(State, i64)], state: State) -> i64 {
+        counts
+            .iter()
+            .find(|(s, _)| *s == state)
+            .map_or(0, |(_, count)| *count)
+    }
+
+    #[tokio::test]
+    async fn valid() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let now = OffsetDateTime::now_utc();
+        let day = Duration::from_secs(86400);
+
+        let before = registrations::count_by_state(&mut trans).await.unwrap();
+
+        let user_1 = users::create(USERS[0].0, USERS[0].1, &mut trans)
+            .await
+            .unwrap();
+        let user_2 = users::create(USERS[1].0, USERS[1].1, &mut trans)
+            .await
+            .unwrap();
+        let session_1 = sessions::create("Session", DATES[0](), DATES[1](), DATES[2](), &mut trans)
+            .await
+            .unwrap();
+        let session_2 =
+            sessions::create("Phase 2", now - day, now - day / 2, now + day, &mut trans)
+                .await
+                .unwrap();
+
+        registrations::create(user_1, session_1, &mut trans)
+            .await
+            .unwrap();
+        registrations::create(user_2, session_1, &mut trans)
+            .await
+            .unwrap();
+        registrations::create(user_1, session_2, &mut trans)
+            .await
+            .unwrap();
+
+        let after = registrations::count_by_state(&mut trans).await.unwrap();
+
+        assert_eq!(
+            count(&after, State::Pending),
+            count(&before, State::Pending) + 2
+        );
+        assert_eq!(count(&after, State::Phase1), count(&before, State::Phase1));
+        assert_eq!(
+            count(&after, State::Phase2),
+            count(&before, State::Phase2) + 1
+        );
+        assert_eq!(count(&after, State::Phase3), count(&before, State::Phase3));
+    }
+}
@@ -0,0 +1,70 @@
+mod common;
+
+mod replace {
+    use crate::common::{connect_db, data::*};
+
+    use db::{
+        images,
+        results::{self, Match},
+        sessions, users,
+    };
+    use sqlx::Acquire;
+
+    #[tokio::test]
+    async fn replaces() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let session = sessions::create("Session", DATES[0](), DATES[1](), DATES[2](), &mut trans)
+            .await
+            .unwrap();
+        let image = images::create(&mut trans).await.unwrap();
+        let mut ids = Vec::new();
+
+        for (email, password) in USERS {
+            ids.push(users::create(email, password, &mut trans).await.unwrap());
+        }
+
+        let pair = Match {
+            image,
+            users: (ids[0], ids[1], None),
+        };
+        let triple = Match {
+            image,
+            users: (ids[0], ids[1], Some(ids[2])),
+        };
+
+        results::replace(session, &[pair.clone(), pair], &mut trans)
+            .await
+            .unwrap();
+        results::replace(session, std::slice::from_ref(&triple), &mut trans)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            results::by_session(session, &mut trans).await.unwrap(),
+            [triple]
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_image() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let session = sessions::create("Session", DATES[0](), DATES[1](), DATES[2](), &mut trans)
+            .await
+            .unwrap();
+        let user = users::create(USERS[0].0, USERS[0].1, &mut trans)
+            .await
+            .unwrap();
+        let matches = [Match {
+            image: images::Id(9182734),
+            users: (user, user, None),
+        }];
+
+        results::replace(session, &matches, &mut trans)
+            .await
+            .unwrap_err();
+    }
+}
@@ -55,3 +55,163 @@ mod create {
         ));
     }
 }
+
+mod count_by_state {
+    use crate::common::{connect_db, data::*};
+
+    use db::sessions::{self, State};
+    use sqlx::{types::time::OffsetDateTime, Acquire};
+    use std::time::Duration;
+
+    fn count(counts: &[(State, i64)], state: State) -> i64 {
+        counts
+            .iter()
+            .find(|(s, _)| *s == state)
+            .map_or(0, |(_, count)| *count)
+    }
+
+    #[tokio::test]
+    async fn valid() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let now = OffsetDateTime::now_utc();
+        let day = Duration::from_secs(86400);
+
+        let before = sessions::count_by_state(&mut trans).await.unwrap();
+
+        sessions::create("Pending", DATES[0](), DATES[1](), DATES[2](), &mut trans)
+            .await
+            .unwrap();
+        sessions::create("Phase 2", now - day, now - day / 2, now + day, &mut trans)
+            .await
+            .unwrap();
+        sessions::create(
+            "Phase 3",
+            now - 3 * day,
+            now - 2 * day,
+            now - day,
+            &mut trans,
+        )
+        .await
+        .unwrap();
+
+        let after = sessions::count_by_state(&mut trans).await.unwrap();
+
+        assert_eq!(
+            count(&after, State::Pending),
+            count(&before, State::Pending) + 1
+        );
+        assert_eq!(count(&after, State::Phase1), count(&before, State::Phase1));
+        assert_eq!(
+            count(&after, State::Phase2),
+            count(&before, State::Phase2) + 1
+        );
+        assert_eq!(
+            count(&after, State::Phase3),
+            count(&before, State::Phase3) + 1
+        );
+    }
+}
+
+mod lock {
+    use crate::common::{connect_db, data::*};
+
+    use db::{result::Error, sessions};
+    use sqlx::Acquire;
+
+    #[tokio::test]
+    async fn valid() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let id = sessions::create("Session", DATES[0](), DATES[1](), DATES[2](), &mut trans)
+            .await
+            .unwrap();
+        let session = sessions::lock(id, &mut trans).await.unwrap();
+
+        assert_eq!(session.id, id);
+        assert_eq!(session.phase1, DATES[0]());
+    }
+
+    #[tokio::test]
+    async fn unknown() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let error = sessions::lock(sessions::Id(9182735), &mut trans)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, Error::InvalidSession));
+    }
+}
+
+mod progress {
+    use crate::common::{connect_db, data::*};
+
+    use db::{registrations, sessions, users};
+    use sqlx::Acquire;
+
+    #[tokio::test]
+    async fn counts() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let session = sessions::create("Session", DATES[0](), DATES[1](), DATES[2](), &mut trans)
+            .await
+            .unwrap();
+        for (email, password) in &USERS[..2] {
+            let user = users::create(email, password, &mut trans).await.unwrap();
+
+            registrations::create(user, session, &mut trans)
+                .await
+                .unwrap();
+        }
+
+        let progress = sessions::progress(session, &mut trans)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(progress.session.id, session);
+        assert_eq!(progress.session.phase2, DATES[1]());
+        assert_eq!(progress.registrations, 2);
+        assert_eq!(progress.choices, 0);
+        assert_eq!(progress.results, 0);
+    }
+
+    #[tokio::test]
+    async fn unknown() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let progress = sessions::progress(sessions::Id(9182735), &mut trans)
+            .await
+            .unwrap();
+
+        assert!(progress.is_none());
+    }
+}
+
+mod state {
+    use crate::common::data::*;
+
+    use db::sessions::{Id, Session, State};
+
+    #[test]
+    fn phases() {
+        let session = Session {
+            id: Id(1),
+            phase1: DATES[1](),
+            phase2: DATES[2](),
+            phase3: DATES[3](),
+        };
+
+        assert_eq!(session.state(DATES[0]()), State::Pending);
+        assert_eq!(session.next_phase(DATES[0]()), Some(DATES[1]()));
+        assert_eq!(session.state(DATES[2]()), State::Phase2);
+        assert_eq!(session.next_phase(DATES[2]()), Some(DATES[3]()));
+        assert_eq!(session.state(DATES[4]()), State::Phase3);
+        assert_eq!(session.next_phase(DATES[4]()), None);
+    }
+}
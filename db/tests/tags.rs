@@ -0,0 +1,69 @@
+mod common;
+
+mod set {
+    use crate::common::connect_db;
+
+    use db::{images, result::Error, tags};
+    use sqlx::Acquire;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn replace() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let image = images::create(&mut trans).await.unwrap();
+
+        tags::set(image, &names(&["set_a", "set_b"]), &mut trans)
+            .await
+            .unwrap();
+        tags::set(image, &names(&["set_b", "set_c"]), &mut trans)
+            .await
+            .unwrap();
+
+        let tags = tags::list(&mut trans).await.unwrap();
+
+        assert_eq!(
+            tags.iter().map(|tag| tag.name.as_str()).collect::<Vec<_>>(),
+            ["set_b", "set_c"]
+        );
+    }
+
+    #[tokio::test]
+    async fn case_insensitive() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let image_1 = images::create(&mut trans).await.unwrap();
+        let image_2 = images::create(&mut trans).await.unwrap();
+
+        tags::set(image_1, &names(&["Case", "CASE"]), &mut trans)
+            .await
+            .unwrap();
+        tags::set(image_2, &names(&["case"]), &mut trans)
+            .await
+            .unwrap();
+
+        let tags = tags::list(&mut trans).await.unwrap();
+
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].name, "Case");
+        assert_eq!(tags[0].images, 2);
+    }
+
+    #[tokio::test]
+    async fn invalid_image() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        assert!(matches!(
+            tags::set(images::Id(-1), &names(&["invalid"]), &mut trans)
+                .await
+                .unwrap_err(),
+            Error::InvalidImage
+        ));
+    }
+}
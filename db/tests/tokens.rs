@@ -70,7 +70,43 @@ mod auth_admin {
 
         users::set_admin(id, true, &mut trans).await.unwrap();
 
-        let auth = tokens::auth_admin(token, &mut trans).await.unwrap();
+        let auth = tokens::auth_admin(token, false, &mut trans).await.unwrap();
+
+        assert_eq!(id, auth);
+    }
+
+    #[tokio::test]
+    async fn admin_without_totp() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let (email, pass) = USERS[0];
+        let id = users::create(email, pass, &mut trans).await.unwrap();
+        let (token, _) = tokens::create(id, &mut trans).await.unwrap();
+
+        users::set_admin(id, true, &mut trans).await.unwrap();
+
+        let error = tokens::auth_admin(token, true, &mut trans)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, Error::InvalidToken));
+    }
+
+    #[tokio::test]
+    async fn admin_with_totp() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let (email, pass) = USERS[0];
+        let id = users::create(email, pass, &mut trans).await.unwrap();
+        let (token, _) = tokens::create(id, &mut trans).await.unwrap();
+
+        users::set_admin(id, true, &mut trans).await.unwrap();
+        users::set_totp_secret(id, "SECRET", &mut trans)
+            .await
+            .unwrap();
+        users::enable_totp(id, &mut trans).await.unwrap();
+
+        let auth = tokens::auth_admin(token, true, &mut trans).await.unwrap();
 
         assert_eq!(id, auth);
     }
@@ -83,7 +119,9 @@ mod auth_admin {
         let id = users::create(email, pass, &mut trans).await.unwrap();
         let (token, _) = tokens::create(id, &mut trans).await.unwrap();
 
-        let error = tokens::auth_admin(token, &mut trans).await.unwrap_err();
+        let error = tokens::auth_admin(token, false, &mut trans)
+            .await
+            .unwrap_err();
 
         assert!(matches!(error, Error::InvalidToken));
     }
@@ -93,7 +131,7 @@ mod auth_admin {
         let mut db = connect_db().await;
         let mut trans = db.begin().await.unwrap();
         let uuid = Uuid::from_u128(91823561239);
-        let error = tokens::auth_admin(Token(uuid), &mut trans)
+        let error = tokens::auth_admin(Token(uuid), false, &mut trans)
             .await
             .unwrap_err();
 
@@ -161,3 +199,39 @@ mod logout_user {
         tokens::auth(token_2, &mut trans).await.unwrap_err();
     }
 }
+
+mod purge_expired {
+    use crate::common::{connect_db, data::USERS};
+
+    use db::{tokens, users};
+    use sqlx::Acquire;
+
+    #[tokio::test]
+    async fn keeps_valid() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let (email, pass) = USERS[0];
+        let id = users::create(email, pass, &mut trans).await.unwrap();
+        let (expired, _) = tokens::create(id, &mut trans).await.unwrap();
+        let (valid, _) = tokens::create(id, &mut trans).await.unwrap();
+
+        sqlx::query(
+            "update tokens set expiration=CURRENT_TIMESTAMP - interval '1 day' where token=$1",
+        )
+        .bind(expired.0)
+        .execute(&mut trans)
+        .await
+        .unwrap();
+
+        let deleted = tokens::purge_expired(&mut trans).await.unwrap();
+        let (remaining,): (i64,) = sqlx::query_as("select count(*) from tokens where user_id=$1")
+            .bind(id.0)
+            .fetch_one(&mut trans)
+            .await
+            .unwrap();
+
+        assert!(deleted >= 1);
+        assert_eq!(remaining, 1);
+        assert_eq!(tokens::auth(valid, &mut trans).await.unwrap(), id);
+    }
+}
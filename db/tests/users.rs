@@ -37,6 +37,81 @@ mod create {
             Error::DuplicateEmail
         ));
     }
+
+    #[tokio::test]
+    async fn same_email_different_case() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let [(email1, pass1), (_, pass2), ..] = USERS;
+
+        users::create(email1, pass1, &mut trans).await.unwrap();
+        assert!(matches!(
+            users::create(&email1.to_uppercase(), pass2, &mut trans)
+                .await
+                .unwrap_err(),
+            Error::DuplicateEmail
+        ));
+    }
+}
+
+mod upsert_verified {
+    use crate::common::{connect_db, data::*};
+
+    use db::{result::Error, users};
+    use sqlx::Acquire;
+
+    #[tokio::test]
+    async fn new_user_is_confirmed() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let (email, _) = USERS[0];
+
+        users::upsert_verified(email, &mut trans).await.unwrap();
+
+        assert!(users::list_candidates(&mut trans).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn existing_user() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let (email, pass) = USERS[0];
+
+        let expected = users::create(email, pass, &mut trans).await.unwrap();
+        users::confirm(expected, &mut trans).await.unwrap();
+        users::set_admin(expected, true, &mut trans).await.unwrap();
+        let (id, admin) = users::upsert_verified(email, &mut trans).await.unwrap();
+        let (logged_in, _) = users::find_by_credentials(email, pass, &mut trans)
+            .await
+            .unwrap();
+
+        assert_eq!(id, expected);
+        assert_eq!(logged_in, expected);
+        assert!(admin);
+    }
+
+    #[tokio::test]
+    async fn unconfirmed_user_loses_credentials() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let (email, pass) = USERS[0];
+
+        let expected = users::create(email, pass, &mut trans).await.unwrap();
+        users::set_totp_secret(expected, "SECRET", &mut trans)
+            .await
+            .unwrap();
+        users::enable_totp(expected, &mut trans).await.unwrap();
+        let (id, _) = users::upsert_verified(email, &mut trans).await.unwrap();
+        let e = users::find_by_credentials(email, pass, &mut trans)
+            .await
+            .unwrap_err();
+        let totp = users::totp(id, &mut trans).await.unwrap();
+
+        assert_eq!(id, expected);
+        assert!(matches!(e, Error::InvalidCredentials));
+        assert_eq!(totp.secret, None);
+        assert!(!totp.enabled);
+    }
 }
 
 mod confirm {
@@ -121,6 +196,21 @@ mod find_by_credentials {
         assert!(!admin);
     }
 
+    #[tokio::test]
+    async fn email_case_insensitive() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let (email, pass) = USERS[0];
+
+        let expected = users::create(email, pass, &mut trans).await.unwrap();
+        users::confirm(expected, &mut trans).await.unwrap();
+        let (id, _) = users::find_by_credentials(&email.to_uppercase(), pass, &mut trans)
+            .await
+            .unwrap();
+
+        assert_eq!(id, expected);
+    }
+
     #[tokio::test]
     async fn admin() {
         let mut db = connect_db().await;
@@ -197,3 +287,186 @@ mod set_admin {
         assert!(matches!(error, Error::InvalidUserId,));
     }
 }
+
+mod lock {
+    use crate::common::{connect_db, data::*};
+
+    use db::{result::Error, users};
+    use sqlx::Acquire;
+
+    #[tokio::test]
+    async fn refuses_login() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let (email, pass) = USERS[0];
+
+        let id = users::create(email, pass, &mut trans).await.unwrap();
+        users::confirm(id, &mut trans).await.unwrap();
+        users::lock(email, 3600., &mut trans).await.unwrap();
+        let e = users::find_by_credentials(email, pass, &mut trans)
+            .await
+            .unwrap_err();
+        let wrong = users::find_by_credentials(email, "wrong password", &mut trans)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(e, Error::AccountLocked));
+        assert!(matches!(wrong, Error::AccountLocked));
+    }
+
+    #[tokio::test]
+    async fn unlock() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let (email, pass) = USERS[0];
+
+        let expected = users::create(email, pass, &mut trans).await.unwrap();
+        users::confirm(expected, &mut trans).await.unwrap();
+        users::lock(email, 3600., &mut trans).await.unwrap();
+        let unlocked = users::unlock(expected, &mut trans).await.unwrap();
+        let (id, _) = users::find_by_credentials(email, pass, &mut trans)
+            .await
+            .unwrap();
+
+        assert_eq!(unlocked, email);
+        assert_eq!(id, expected);
+    }
+
+    #[tokio::test]
+    async fn locked() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let (email, pass) = USERS[0];
+
+        let id = users::create(email, pass, &mut trans).await.unwrap();
+        let before = users::locked(id, &mut trans).await.unwrap();
+        users::lock(email, 3600., &mut trans).await.unwrap();
+        let after = users::locked(id, &mut trans).await.unwrap();
+        users::unlock(id, &mut trans).await.unwrap();
+
+        assert!(!before);
+        assert!(after);
+        assert!(!users::locked(id, &mut trans).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn unlock_invalid_id() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+
+        let error = users::unlock(users::Id(94886529), &mut trans)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, Error::InvalidUserId));
+    }
+}
+
+mod totp {
+    use crate::common::{connect_db, data::*};
+
+    use db::{result::Error, users};
+    use sqlx::Acquire;
+
+    #[tokio::test]
+    async fn disabled_by_default() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let (email, pass) = USERS[0];
+
+        let id = users::create(email, pass, &mut trans).await.unwrap();
+        let totp = users::totp(id, &mut trans).await.unwrap();
+
+        assert!(totp.secret.is_none());
+        assert!(!totp.enabled);
+    }
+
+    #[tokio::test]
+    async fn enable() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let (email, pass) = USERS[0];
+
+        let id = users::create(email, pass, &mut trans).await.unwrap();
+        users::set_totp_secret(id, "SECRET", &mut trans)
+            .await
+            .unwrap();
+        users::enable_totp(id, &mut trans).await.unwrap();
+        let totp = users::totp(id, &mut trans).await.unwrap();
+
+        assert_eq!(totp.secret.as_deref(), Some("SECRET"));
+        assert!(totp.enabled);
+    }
+
+    #[tokio::test]
+    async fn enable_without_secret() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let (email, pass) = USERS[0];
+
+        let id = users::create(email, pass, &mut trans).await.unwrap();
+
+        assert!(matches!(
+            users::enable_totp(id, &mut trans).await.unwrap_err(),
+            Error::InvalidUserId
+        ));
+    }
+
+    #[tokio::test]
+    async fn set_secret_when_enabled() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let (email, pass) = USERS[0];
+
+        let id = users::create(email, pass, &mut trans).await.unwrap();
+        users::set_totp_secret(id, "SECRET", &mut trans)
+            .await
+            .unwrap();
+        users::enable_totp(id, &mut trans).await.unwrap();
+
+        assert!(matches!(
+            users::set_totp_secret(id, "OTHER", &mut trans)
+                .await
+                .unwrap_err(),
+            Error::SecondFactorAlreadyEnabled
+        ));
+    }
+
+    #[tokio::test]
+    async fn use_step_once() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let (email, pass) = USERS[0];
+
+        let id = users::create(email, pass, &mut trans).await.unwrap();
+        users::use_totp_step(id, 100, &mut trans).await.unwrap();
+
+        for step in [100, 99] {
+            assert!(matches!(
+                users::use_totp_step(id, step, &mut trans)
+                    .await
+                    .unwrap_err(),
+                Error::InvalidSecondFactor
+            ));
+        }
+        users::use_totp_step(id, 101, &mut trans).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn disable() {
+        let mut db = connect_db().await;
+        let mut trans = db.begin().await.unwrap();
+        let (email, pass) = USERS[0];
+
+        let id = users::create(email, pass, &mut trans).await.unwrap();
+        users::set_totp_secret(id, "SECRET", &mut trans)
+            .await
+            .unwrap();
+        users::enable_totp(id, &mut trans).await.unwrap();
+        users::disable_totp(id, &mut trans).await.unwrap();
+        let totp = users::totp(id, &mut trans).await.unwrap();
+
+        assert!(totp.secret.is_none());
+        assert!(!totp.enabled);
+    }
+}
@@ -1,73 +1,627 @@
 use std::{
+    io,
     net::SocketAddr,
     path::{Path, PathBuf},
-    str::FromStr,
+    time::{Duration, SystemTime},
 };
 
-use db::{images, Pool};
-use futures::{StreamExt, TryStreamExt};
-use log::error;
-use serde::Serialize;
+use config::{cors, database, image_urls, Loader};
+use db::{images, result, sessions, tokens, users, ConnectOptions, Pool};
+use futures::{future::BoxFuture, FutureExt, StreamExt, TryStreamExt};
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter, register_int_counter_vec, IntCounter, IntCounterVec};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use server::{
+    files::{self, Files},
+    health::{self, Checks},
+    metrics::{self, Hooks},
+    shutdown::{self, Trigger},
+    signing::Keys,
+};
+use storage::{encoding::Encoding, format::Format, import, phash, Storage};
 use tokio::io::AsyncWriteExt;
+use tracing::{error, instrument};
 use warp::{
-    http::StatusCode,
+    http::{header, HeaderValue, StatusCode},
     multipart::{FormData, Part},
-    Buf, Filter, Rejection,
+    Buf, Filter, Rejection, Reply,
 };
 
+static UPLOAD_BYTES: Lazy<IntCounter> =
+    Lazy::new(|| register_int_counter!("upload_bytes_total", "Bytes of images received").unwrap());
+
+static UPLOAD_PARTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "upload_parts_total",
+        "Images received, by result",
+        &["result"]
+    )
+    .unwrap()
+});
+
 #[tokio::main]
 async fn main() {
-    env_logger::init();
+    server::logging::init();
 
     let config = config();
 
+    if config.healthcheck {
+        std::process::exit(if health::probe(config.addr).await {
+            0
+        } else {
+            1
+        });
+    }
+
     std::fs::DirBuilder::new()
         .recursive(true)
         .create(&config.storage_path)
         .expect("Could not create storage directory");
 
-    let pool = db::connect(
-        &config.db_user,
-        &config.db_pass,
-        &config.db_host,
-        config.db_port,
-        &config.db_name,
+    let pool = match db::connect(&config.db).await {
+        Ok(pool) => pool,
+        Err(err) => {
+            error!("Failed to connect to database: {err}");
+            std::process::exit(1);
+        }
+    };
+    let checks = Checks::new()
+        .database(pool.clone())
+        .with("storage", storage_check(config.storage_path.clone()));
+    let routes = health::routes(checks)
+        .or(metrics::routes(Hooks::new().pool(pool.clone())))
+        .or(get_image(Serving::new(
+            Storage::new(config.storage_path.clone()),
+            config.cache_max_age,
+            pool.clone(),
+            config.require_admin_totp,
+            config.image_url_keys,
+        )))
+        .or(import_route(
+            Importing {
+                storage: Storage::new(config.storage_path.clone()),
+                db: pool.clone(),
+                require_admin_totp: config.require_admin_totp,
+                limits: config.import_limits,
+            },
+            config.import_max_request_size,
+        ))
+        .or(add_images_route(config.storage_path, pool.clone()))
+        .with(config.cors.filter())
+        .with(metrics::http(["/{id}", "/sessions/{id}/import", "/"]));
+
+    let deadline = server::serve(
+        warp::service(routes),
+        config.addr,
+        config.shutdown_timeout,
+        Trigger::new(),
     )
-    .await
-    .expect("Failed to connect to database");
-    let routes =
-        get_image(config.storage_path.clone()).or(add_images_route(config.storage_path, pool));
+    .await;
+
+    shutdown::until(deadline, "closing the database pool", pool.close()).await;
+}
 
-    println!("Starting server on {}", config.addr);
+/// Checks that images can be written to `path`.
+fn storage_check(
+    path: PathBuf,
+) -> impl Fn() -> BoxFuture<'static, Result<(), String>> + Send + Sync + 'static {
+    move || {
+        let probe = path.join(".readyz");
 
-    warp::serve(routes).run(config.addr).await;
+        async move {
+            tokio::fs::write(&probe, b"")
+                .await
+                .and(tokio::fs::remove_file(&probe).await)
+                .map_err(|e| format!("{} is not writable: {e}", probe.display()))
+        }
+        .boxed()
+    }
 }
 
-fn get_image(path: PathBuf) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
-    warp::get().and(warp::fs::dir(path))
+/// `GET /{id}`, images never change once stored under their id so clients may keep them.
+///
+/// JPEG and PNG images are sent in the lightest encoding listed by the `Accept` header, see
+/// [`storage::encoding`]. Images that are not public are only sent to the users allowed to see
+/// them, see [`images::access`], or to anyone with a URL signed by the backend, see
+/// [`server::signing`].
+fn get_image(
+    serving: Serving,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    warp::get()
+        .or(warp::head())
+        .unify()
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::header::optional("authorization"))
+        .and(warp::header::optional("accept"))
+        .and(warp::query())
+        .and(files::conditions())
+        .then(move |id, authorization, accept, signed, conditions| {
+            serve_image(
+                images::Id(id),
+                authorization,
+                signed,
+                accept,
+                conditions,
+                serving.clone(),
+            )
+        })
+}
+
+/// Stored images and who they may be served to.
+#[derive(Clone)]
+struct Serving {
+    storage: Storage,
+    files: Files,
+    db: Pool,
+    require_admin_totp: bool,
+    keys: Option<Keys>,
+}
+
+impl Serving {
+    fn new(
+        storage: Storage,
+        max_age: Duration,
+        db: Pool,
+        require_admin_totp: bool,
+        keys: Option<Keys>,
+    ) -> Self {
+        let files = Files::new(max_age, |data| {
+            Format::sniff(data)
+                .map(Format::mime)
+                .or_else(|| Encoding::sniff(data).map(Encoding::mime))
+        });
+
+        Self {
+            storage,
+            files,
+            db,
+            require_admin_totp,
+            keys,
+        }
+    }
+}
+
+/// Query parameters of a signed URL.
+#[derive(Deserialize)]
+struct Signed {
+    exp: Option<String>,
+    sig: Option<String>,
+}
+
+#[instrument(skip_all, fields(image = id.0))]
+async fn serve_image(
+    id: images::Id,
+    authorization: Option<String>,
+    signed: Signed,
+    accept: Option<String>,
+    conditions: files::Conditions,
+    serving: Serving,
+) -> warp::reply::Response {
+    let mut response = match access(id, authorization, signed, &serving).await {
+        Ok(public) => {
+            let path = match encoded(id, accept, &serving.storage).await {
+                Some(path) => path,
+                None => serving.storage.path(id),
+            };
+
+            match serving.files.reply(&path, public, &conditions).await {
+                Ok(Some(response)) => response,
+                Ok(None) => reply_error(
+                    StatusCode::NOT_FOUND,
+                    result::Error::InvalidImage.code(),
+                    "No image with this id",
+                ),
+                Err(err) => {
+                    error!("Failed to read {}: {err}", path.display());
+                    reply_error(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        INTERNAL,
+                        "Failed to read image",
+                    )
+                }
+            }
+        }
+        Err(response) => response,
+    };
+
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("accept"));
+    response
 }
 
+/// Whether the image is public when it may be served to the user sending the request.
+///
+/// Anonymous requests for other images are asked to authenticate, while users who may not see
+/// an image are told that it does not exist. Signed URLs are checked without the database and are
+/// refused once expired, whoever requests them.
+async fn access(
+    id: images::Id,
+    authorization: Option<String>,
+    signed: Signed,
+    serving: &Serving,
+) -> Result<bool, warp::reply::Response> {
+    if let (Some(expires), Some(signature)) = (signed.exp, signed.sig) {
+        let path = format!("/{}", id.0);
+
+        return match &serving.keys {
+            Some(keys) if keys.verify(&path, &expires, &signature, SystemTime::now()) => Ok(false),
+            _ => Err(reply_error(
+                StatusCode::FORBIDDEN,
+                "invalid_signature",
+                "Invalid or expired signature",
+            )),
+        };
+    }
+
+    let user = authenticate(authorization, &serving.db).await?;
+
+    match images::access(id, user, serving.require_admin_totp, &serving.db).await {
+        Ok(images::Access::Public) => Ok(true),
+        Ok(images::Access::Granted) => Ok(false),
+        Ok(images::Access::Denied) if user.is_none() => {
+            let mut response = reply_error(
+                StatusCode::UNAUTHORIZED,
+                result::Error::InvalidToken.code(),
+                "Authentication required",
+            );
+
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            Err(response)
+        }
+        Ok(images::Access::Denied) => Err(reply_error(
+            StatusCode::NOT_FOUND,
+            result::Error::InvalidImage.code(),
+            "No image with this id",
+        )),
+        Err(err) => {
+            error!("Failed to check access to image: {err}");
+            Err(reply_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                INTERNAL,
+                "Failed to check access",
+            ))
+        }
+    }
+}
+
+/// Path of the smallest encoding of the image accepted by a client sending `accept`, if any.
+///
+/// Only the encoding the client prefers is generated, in the background, the original or another
+/// encoding already stored is served meanwhile.
+async fn encoded(id: images::Id, accept: Option<String>, storage: &Storage) -> Option<PathBuf> {
+    let encodings = Encoding::negotiate(&accept?);
+    let mut smallest: Option<(PathBuf, u64)> = None;
+
+    for (index, &encoding) in encodings.iter().enumerate() {
+        match storage.stored_encoding(id, encoding).await {
+            Ok(Some((path, len))) if smallest.as_ref().is_none_or(|(_, min)| len < *min) => {
+                smallest = Some((path, len));
+            }
+            Ok(Some(_)) => {}
+            Ok(None) if index == 0 => {
+                let storage = storage.clone();
+
+                tokio::spawn(async move {
+                    if let Err(err) = storage.encoded(id, encoding).await {
+                        error!("Failed to encode image to {}: {err}", encoding.mime());
+                    }
+                });
+            }
+            Ok(None) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return None,
+            Err(err) => error!("Failed to read image encoded to {}: {err}", encoding.mime()),
+        }
+    }
+
+    smallest.map(|(path, _)| path)
+}
+
+/// `POST /`, stores each part as an image.
 fn add_images_route(
     path: PathBuf,
     db: Pool,
 ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
     warp::post()
+        .and(warp::path::end())
+        .and(warp::header::optional("authorization"))
         .and(warp::filters::multipart::form().max_length(128_000_000))
-        .map(move |m| (m, path.clone(), db.clone()))
+        .map(move |authorization, m| (authorization, m, path.clone(), db.clone()))
         .untuple_one()
         .then(add_images)
 }
 
+/// `POST /sessions/{id}/import`, stores every image of the uploaded files and ZIP archives and
+/// associates them with the session in a single transaction. Only admins may import.
+///
+/// The files are written to a temporary directory as they are received, then read within the
+/// limits of the import, see [`import::Reader`].
+fn import_route(
+    importing: Importing,
+    max_request_size: u64,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("sessions" / i32 / "import"))
+        .and(warp::header::optional("authorization"))
+        .and(warp::filters::multipart::form().max_length(max_request_size))
+        .then(move |session, authorization, data| {
+            import_images(
+                sessions::Id(session),
+                authorization,
+                data,
+                importing.clone(),
+            )
+        })
+}
+
+/// Where images are imported, and who may import them.
+#[derive(Clone)]
+struct Importing {
+    storage: Storage,
+    db: Pool,
+    require_admin_totp: bool,
+    limits: import::Limits,
+}
+
+#[instrument(skip_all, fields(session = session.0))]
+async fn import_images(
+    session: sessions::Id,
+    authorization: Option<String>,
+    mut data: FormData,
+    importing: Importing,
+) -> warp::reply::Response {
+    let uploader = match authenticate_admin(
+        authorization,
+        importing.require_admin_totp,
+        &importing.db,
+    )
+    .await
+    {
+        Ok(uploader) => uploader,
+        Err(response) => return response,
+    };
+    let dir = match tempfile::Builder::new()
+        .prefix(".import-")
+        .tempdir_in(importing.storage.root())
+    {
+        Ok(dir) => dir,
+        Err(err) => {
+            error!("Failed to create import directory: {err}");
+            return reply_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                INTERNAL,
+                "Failed to import images",
+            );
+        }
+    };
+    let mut files = Vec::new();
+
+    while let Some(part) = data.next().await {
+        let path = dir.path().join(files.len().to_string());
+
+        match receive_part(part, &path).await {
+            Ok(name) => files.push((name, path)),
+            Err(response) => return response,
+        }
+    }
+
+    let limits = importing.limits;
+    let entries = tokio::task::spawn_blocking(move || {
+        let mut reader = import::Reader::new(limits);
+
+        for (name, path) in &files {
+            reader.add_file(name, path);
+        }
+        reader.finish()
+    })
+    .await;
+    let entries = match entries {
+        Ok(Ok(entries)) => entries,
+        Ok(Err(exceeded)) => {
+            return reply_error(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "payload_too_large",
+                &format!("The import holds {exceeded}"),
+            )
+        }
+        Err(err) => {
+            error!("Failed to read imported files: {err}");
+            return reply_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                INTERNAL,
+                "Failed to import images",
+            );
+        }
+    };
+
+    drop(dir);
+    match import::import(
+        session,
+        Some(uploader),
+        entries,
+        &importing.storage,
+        &importing.db,
+    )
+    .await
+    {
+        Ok(report) => {
+            UPLOAD_PARTS
+                .with_label_values(&["ok"])
+                .inc_by(report.imported as u64);
+            UPLOAD_PARTS
+                .with_label_values(&["error"])
+                .inc_by(report.rejected as u64);
+            warp::reply::with_status(warp::reply::json(&report), StatusCode::OK).into_response()
+        }
+        Err(import::Error::Db(result::Error::InvalidSession)) => reply_error(
+            StatusCode::NOT_FOUND,
+            result::Error::InvalidSession.code(),
+            "No session with this id",
+        ),
+        Err(import::Error::Started(state)) => reply_error(
+            StatusCode::CONFLICT,
+            "session_started",
+            &format!(
+                "Images can only be imported before the first phase, the session is in {}",
+                state.as_str()
+            ),
+        ),
+        Err(err) => {
+            error!("Failed to import images: {err}");
+            reply_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                INTERNAL,
+                "Failed to import images",
+            )
+        }
+    }
+}
+
+/// Writes a part to `path`, returns the name of the file it was sent as.
+async fn receive_part(
+    part: Result<Part, warp::Error>,
+    path: &Path,
+) -> Result<String, warp::reply::Response> {
+    let invalid = |err: warp::Error| {
+        error!("Error while receiving images: {err}");
+        reply_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_body",
+            "Invalid multipart body",
+        )
+    };
+    let part = part.map_err(invalid)?;
+    let name = part.filename().unwrap_or_else(|| part.name()).to_string();
+    let mut stream = part.stream();
+    let mut file = tokio::fs::File::create(path).await.map_err(|err| {
+        error!("Failed to create {}: {err}", path.display());
+        reply_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            INTERNAL,
+            "Failed to import images",
+        )
+    })?;
+
+    while let Some(buf) = stream.try_next().await.map_err(invalid)? {
+        write_buf(buf, &mut file).await.map_err(|err| {
+            error!("{err}");
+            reply_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                INTERNAL,
+                "Failed to import images",
+            )
+        })?;
+    }
+
+    Ok(name)
+}
+
+/// The user sending the request, anonymous requests are allowed but a token must be valid.
+async fn authenticate(
+    authorization: Option<String>,
+    db: &Pool,
+) -> Result<Option<users::Id>, warp::reply::Response> {
+    let token = match authorization {
+        Some(authorization) => bearer(&authorization).ok_or_else(invalid_token)?,
+        None => return Ok(None),
+    };
+
+    tokens::auth(token, db).await.map(Some).map_err(auth_error)
+}
+
+/// The admin sending the request, `require_totp` refuses admins without a second factor.
+async fn authenticate_admin(
+    authorization: Option<String>,
+    require_totp: bool,
+    db: &Pool,
+) -> Result<users::Id, warp::reply::Response> {
+    let token = match authorization {
+        Some(authorization) => bearer(&authorization).ok_or_else(invalid_token)?,
+        None => {
+            let mut response = reply_error(
+                StatusCode::UNAUTHORIZED,
+                result::Error::InvalidToken.code(),
+                "Authentication required",
+            );
+
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            return Err(response);
+        }
+    };
+
+    tokens::auth_admin(token, require_totp, db)
+        .await
+        .map_err(auth_error)
+}
+
+fn bearer(authorization: &str) -> Option<tokens::Token> {
+    authorization
+        .trim()
+        .strip_prefix("Bearer ")
+        .and_then(|token| token.trim().parse().ok())
+        .map(tokens::Token)
+}
+
+fn invalid_token() -> warp::reply::Response {
+    reply_error(
+        StatusCode::FORBIDDEN,
+        result::Error::InvalidToken.code(),
+        "Invalid token",
+    )
+}
+
+fn auth_error(err: result::Error) -> warp::reply::Response {
+    match err {
+        result::Error::InvalidToken => invalid_token(),
+        err => {
+            error!("Failed to authenticate user: {err}");
+            reply_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                INTERNAL,
+                "Failed to authenticate",
+            )
+        }
+    }
+}
+
+/// Code of unexpected failures, whose details are only logged.
+const INTERNAL: &str = "internal";
+
+/// Body of every error, `code` is a stable identifier clients can branch on, unlike `error`.
+fn reply_error(status: StatusCode, code: &str, message: &str) -> warp::reply::Response {
+    warp::reply::with_status(
+        warp::reply::json(&json!({ "error": message, "code": code })),
+        status,
+    )
+    .into_response()
+}
+
 #[derive(Serialize)]
 struct AddResponse {
     ok: Vec<(usize, i32)>,
     errors: Vec<usize>,
 }
 
-async fn add_images(data: FormData, path: PathBuf, db: Pool) -> impl warp::Reply {
+#[instrument(skip_all)]
+async fn add_images(
+    authorization: Option<String>,
+    data: FormData,
+    path: PathBuf,
+    db: Pool,
+) -> warp::reply::Response {
+    let uploader = match authenticate(authorization, &db).await {
+        Ok(uploader) => uploader,
+        Err(response) => return response,
+    };
     let results: Vec<_> = data
         .map_err(|err| format!("add_images: FormData content error: {err}"))
-        .and_then(|part| add_image(part, path.clone(), db.clone()))
+        .and_then(|part| add_image(part, uploader, path.clone(), db.clone()))
         .map_err(|e| error!("Error while receiving image: {e}"))
         .enumerate()
         .collect()
@@ -80,8 +634,14 @@ async fn add_images(data: FormData, path: PathBuf, db: Pool) -> impl warp::Reply
         },
         |mut acc, (index, result)| {
             match result {
-                Ok(id) => acc.ok.push((index, id.0)),
-                Err(()) => acc.errors.push(index),
+                Ok(id) => {
+                    UPLOAD_PARTS.with_label_values(&["ok"]).inc();
+                    acc.ok.push((index, id.0))
+                }
+                Err(()) => {
+                    UPLOAD_PARTS.with_label_values(&["error"]).inc();
+                    acc.errors.push(index)
+                }
             };
             acc
         },
@@ -95,41 +655,81 @@ async fn add_images(data: FormData, path: PathBuf, db: Pool) -> impl warp::Reply
             StatusCode::INTERNAL_SERVER_ERROR
         },
     )
+    .into_response()
 }
 
-async fn add_image(part: Part, mut path: PathBuf, db: Pool) -> Result<images::Id, String> {
+#[instrument(skip_all)]
+async fn add_image(
+    part: Part,
+    uploader: Option<users::Id>,
+    mut path: PathBuf,
+    db: Pool,
+) -> Result<images::Id, String> {
     let mut trans = db
         .begin()
         .await
-        .map_err(|e| format!("Failed to create transaction on database: {e:?}"))?;
-    let id = images::create(&mut trans)
+        .map_err(|e| format!("Failed to create transaction on database: {e}"))?;
+    let id = images::create_by(uploader, &mut trans)
         .await
-        .map_err(|e| format!("Failed to create image in database: {e:?}"))?;
+        .map_err(|e| format!("Failed to create image in database: {e}"))?;
 
     path.push(id.0.to_string());
     write_image(part, &path).await?;
 
+    if let Some(phash) = phash(&path).await {
+        images::set_phash(id, phash, &mut trans)
+            .await
+            .map_err(|e| format!("Failed to store perceptual hash in database: {e}"))?;
+    }
+
     trans
         .commit()
         .await
         .map(|()| id)
-        .map_err(|e| format!("Failed to commit transaction on database: {e:?}"))
+        .map_err(|e| format!("Failed to commit transaction on database: {e}"))
+}
+
+/// Perceptual hash of the image written at `path`, `None` when it cannot be decoded.
+async fn phash(path: &Path) -> Option<u64> {
+    let data = tokio::fs::read(path).await.ok()?;
+
+    tokio::task::spawn_blocking(move || phash::dhash(&data))
+        .await
+        .ok()
+        .flatten()
 }
 
+/// Writes to a temporary file renamed once complete, so that an interrupted upload never leaves a
+/// truncated image behind.
 async fn write_image(part: Part, path: &Path) -> Result<(), String> {
-    let mut stream = part.stream().map_err(|e| format!("Part stream error: {e}"));
-    let mut file = tokio::fs::File::create(path).await.map_err(|e| {
-        format!(
-            "Failed to open {} with write permissions: {e}",
-            path.display()
-        )
-    })?;
+    let partial = path.with_extension("part");
+    let result = async {
+        let mut stream = part.stream().map_err(|e| format!("Part stream error: {e}"));
+        let mut file = tokio::fs::File::create(&partial).await.map_err(|e| {
+            format!(
+                "Failed to open {} with write permissions: {e}",
+                partial.display()
+            )
+        })?;
+
+        while let Some(buf) = stream.next().await {
+            write_buf(buf?, &mut file).await?;
+        }
+
+        file.sync_all()
+            .await
+            .map_err(|e| format!("Failed to sync {}: {e}", partial.display()))?;
+        tokio::fs::rename(&partial, path)
+            .await
+            .map_err(|e| format!("Failed to move image to {}: {e}", path.display()))
+    }
+    .await;
 
-    while let Some(buf) = stream.next().await {
-        write_buf(buf?, &mut file).await?;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&partial).await;
     }
 
-    Ok(())
+    result
 }
 
 async fn write_buf(mut buf: impl Buf, file: &mut tokio::fs::File) -> Result<(), String> {
@@ -137,45 +737,52 @@ async fn write_buf(mut buf: impl Buf, file: &mut tokio::fs::File) -> Result<(),
     let mut vec = vec![0; size];
 
     buf.copy_to_slice(&mut vec);
+    UPLOAD_BYTES.inc_by(size as u64);
     file.write_all(&vec)
         .await
         .map_err(|e| format!("Failed to write Part buffer to file: {e}"))
 }
 
 fn config() -> Config {
-    Config {
-        addr: var_with_default("HOST", || SocketAddr::from(([0, 0, 0, 0], 3030))),
-        db_port: var_with_default("DB_PORT", || 6300),
-        db_host: var_with_default("DB_HOST", || String::from("0.0.0.0")),
-        db_name: var_with_default("DB_NAME", || String::from("db")),
-        db_user: var_with_default("DB_USER", || String::from("postgre")),
-        db_pass: var_with_default("DB_PASS", || String::from("postgre")),
-        storage_path: var_with_default("STORAGE_PATH", || PathBuf::from("./images")),
-    }
+    let mut loader = Loader::load(database::SECRETS.into_iter().chain(image_urls::SECRETS));
+    let config = Config {
+        addr: loader.get("host", || SocketAddr::from(([0, 0, 0, 0], 3030))),
+        shutdown_timeout: loader.duration("shutdown_timeout", Duration::from_secs(30)),
+        db: database::connect_options(&mut loader, "image-host"),
+        cors: cors::policy(&mut loader),
+        storage_path: loader.get("storage_path", || PathBuf::from("./images")),
+        cache_max_age: Duration::from_secs(loader.get("cache_max_age", || 365 * 24 * 3600)),
+        require_admin_totp: loader.get("require_admin_totp", || false),
+        import_max_request_size: loader.get("import.max_request_size", || 128 * 1024 * 1024),
+        import_limits: import::Limits {
+            max_files: loader.get("import.max_files", || import::Limits::default().max_files),
+            max_total_size: loader.get("import.max_total_size", || {
+                import::Limits::default().max_total_size
+            }),
+        },
+        image_url_keys: image_urls::keys(&mut loader),
+        healthcheck: loader.get("healthcheck", || false),
+    };
+
+    loader.finish_or_exit();
+    config
 }
 
 struct Config {
     addr: SocketAddr,
-    db_port: i16,
-    db_host: String,
-    db_name: String,
-    db_user: String,
-    db_pass: String,
+    shutdown_timeout: Duration,
+    db: ConnectOptions,
+    cors: server::cors::Policy,
     storage_path: PathBuf,
-}
-
-fn var_with_default<T, F>(var: &str, default: F) -> T
-where
-    T: FromStr,
-    F: FnOnce() -> T,
-{
-    std::env::var(var)
-        .map(|var| {
-            var.parse()
-                .unwrap_or_else(|_| panic!("Invalid value for {var}"))
-        })
-        .unwrap_or_else(|e| match e {
-            std::env::VarError::NotPresent => default(),
-            std::env::VarError::NotUnicode(_) => panic!("Invalid unicode in {var}"),
-        })
+    /// How long clients may keep an image without revalidating it.
+    cache_max_age: Duration,
+    /// Only serve images to admins with TOTP enabled, as the backend does for admin routes.
+    require_admin_totp: bool,
+    /// Largest body of a request importing images, in bytes.
+    import_max_request_size: u64,
+    import_limits: import::Limits,
+    /// Keys verifying the image URLs signed by the backend.
+    image_url_keys: Option<Keys>,
+    /// Probe the readiness of a running instance then exit, for container health checks.
+    healthcheck: bool,
 }
@@ -0,0 +1,93 @@
+//! Cross-origin requests from the browser frontend.
+//!
+//! The policy is applied around every route, after rejections were recovered, so that error
+//! responses carry the CORS headers too and can be read by the frontend. Requests from an origin
+//! that is not allowed are refused with `403 Forbidden`, preflight requests are answered without
+//! reaching the routes.
+
+use std::{str::FromStr, time::Duration};
+
+use warp::{
+    cors::Cors,
+    http::{header::HeaderName, Method, Uri},
+};
+
+use crate::request;
+
+/// Origin allowed to call the services.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    /// `*`, any origin.
+    Any,
+    /// `scheme://host[:port]`, as sent by browsers in the `Origin` header.
+    Exact(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Policy {
+    /// Nothing is allowed when empty, which only refuses requests sent with an `Origin` header.
+    pub origins: Vec<Origin>,
+    pub methods: Vec<Method>,
+    /// Request headers the frontend may send.
+    pub headers: Vec<HeaderName>,
+    /// How long browsers may cache the answer to a preflight request.
+    pub max_age: Duration,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            origins: Vec::new(),
+            methods: vec![Method::GET, Method::POST, Method::PUT, Method::DELETE],
+            headers: vec![
+                HeaderName::from_static("authorization"),
+                HeaderName::from_static("content-type"),
+            ],
+            max_age: Duration::from_secs(3600),
+        }
+    }
+}
+
+impl Policy {
+    /// Builds the wrapper to apply with `Filter::with`.
+    ///
+    /// The request id header is exposed so that the frontend can report it.
+    pub fn filter(&self) -> Cors {
+        let builder = warp::cors()
+            .allow_methods(self.methods.iter().cloned())
+            .allow_headers(self.headers.iter().cloned())
+            .expose_header(request::HEADER)
+            .max_age(self.max_age);
+
+        if self.origins.contains(&Origin::Any) {
+            builder.allow_any_origin()
+        } else {
+            builder.allow_origins(self.origins.iter().filter_map(|origin| match origin {
+                Origin::Any => None,
+                Origin::Exact(origin) => Some(origin.as_str()),
+            }))
+        }
+        .build()
+    }
+}
+
+impl FromStr for Origin {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "*" {
+            return Ok(Self::Any);
+        }
+
+        let uri: Uri = s.parse().map_err(|e| format!("{e}"))?;
+
+        match (uri.scheme(), uri.authority(), uri.path_and_query()) {
+            (Some(scheme), Some(authority), path)
+                if matches!(path.map(|path| path.as_str()), None | Some("/")) =>
+            {
+                Ok(Self::Exact(format!("{scheme}://{authority}")))
+            }
+            _ => Err(String::from("expected scheme://host[:port]")),
+        }
+    }
+}
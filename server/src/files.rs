@@ -0,0 +1,379 @@
+//! Files that never change once written, such as images stored under their id.
+//!
+//! They are served with a strong `ETag`, the SHA-256 digest of their content, and may be kept by
+//! clients for as long as [`Files`] allows without being revalidated, by shared caches too unless
+//! they are private. Conditional requests with `If-None-Match` are answered with
+//! `304 Not Modified`, and a single byte range with `206 Partial Content` unless `If-Range` names
+//! another version of the file.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    io::{self, SeekFrom},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+use tokio_util::io::ReaderStream;
+use warp::{
+    http::{header, HeaderMap, HeaderValue, Response, StatusCode},
+    hyper::Body,
+    Filter,
+};
+
+/// Number of bytes at the start of a file its `Content-Type` is recognized from.
+const SNIFF_LEN: u64 = 512;
+
+/// Number of files whose digest is kept, the least recently served are forgotten first.
+const ETAGS_CAPACITY: usize = 4096;
+
+/// Request headers deciding whether and which part of a file is sent.
+#[derive(Debug, Clone, Default)]
+pub struct Conditions {
+    if_none_match: Option<String>,
+    range: Option<String>,
+    if_range: Option<String>,
+}
+
+/// Extracts the [`Conditions`] of a request, headers that are not valid strings are ignored.
+pub fn conditions() -> impl Filter<Extract = (Conditions,), Error = Infallible> + Clone {
+    warp::header::headers_cloned().map(|headers: HeaderMap| {
+        let get = |name| {
+            headers
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(String::from)
+        };
+
+        Conditions {
+            if_none_match: get(header::IF_NONE_MATCH),
+            range: get(header::RANGE),
+            if_range: get(header::IF_RANGE),
+        }
+    })
+}
+
+#[derive(Clone)]
+pub struct Files {
+    public: HeaderValue,
+    private: HeaderValue,
+    content_type: fn(&[u8]) -> Option<&'static str>,
+    /// Digests are only computed once per version of a file, recognized by its size and
+    /// modification time.
+    etags: Arc<Mutex<Etags>>,
+}
+
+impl Files {
+    /// Files that clients may keep for `max_age`, their `Content-Type` is recognized from the
+    /// first bytes of their content by `content_type` and omitted when it returns `None`.
+    pub fn new(max_age: Duration, content_type: fn(&[u8]) -> Option<&'static str>) -> Self {
+        let cache_control = |scope| {
+            HeaderValue::try_from(format!("{scope}, max-age={}, immutable", max_age.as_secs()))
+                .expect("Cache-Control is a valid header value")
+        };
+
+        Self {
+            public: cache_control("public"),
+            private: cache_control("private"),
+            content_type,
+            etags: Arc::new(Mutex::new(Etags::new(ETAGS_CAPACITY))),
+        }
+    }
+
+    /// Answers a `GET` or `HEAD` request for the file at `path`, `None` when there is no such file.
+    ///
+    /// Files that are not `public` may only be kept by the client, as they were checked to be
+    /// allowed for it. The body is streamed from the file, only the requested range is read.
+    pub async fn reply(
+        &self,
+        path: &Path,
+        public: bool,
+        conditions: &Conditions,
+    ) -> io::Result<Option<Response<Body>>> {
+        let mut file = match File::open(path).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let metadata = file.metadata().await?;
+
+        if !metadata.is_file() {
+            return Ok(None);
+        }
+
+        let etag = match self.cached(path, &metadata) {
+            Some(etag) => etag,
+            None => self.compute(path, &metadata, &file).await?,
+        };
+
+        let mut response = Response::new(Body::empty());
+        let headers = response.headers_mut();
+
+        headers.insert(header::ETAG, etag.clone());
+        headers.insert(
+            header::CACHE_CONTROL,
+            if public { &self.public } else { &self.private }.clone(),
+        );
+        headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+        if matches!(&conditions.if_none_match, Some(tags) if matches_any(tags, &etag)) {
+            *response.status_mut() = StatusCode::NOT_MODIFIED;
+            return Ok(Some(response));
+        }
+
+        let mut head = Vec::new();
+
+        file.seek(SeekFrom::Start(0)).await?;
+        (&mut file).take(SNIFF_LEN).read_to_end(&mut head).await?;
+
+        if let Some(content_type) = (self.content_type)(&head) {
+            headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+        }
+
+        let len = metadata.len();
+        // A range is only valid for the version of the file named by `If-Range`, otherwise the
+        // whole file is sent.
+        let range = match (&conditions.range, &conditions.if_range) {
+            (Some(range), None) => byte_range(range, len),
+            (Some(range), Some(tag)) if tag.trim().as_bytes() == etag.as_bytes() => {
+                byte_range(range, len)
+            }
+            _ => ByteRange::Full,
+        };
+        let range = match range {
+            ByteRange::Full => 0..len,
+            ByteRange::Partial(range) => {
+                let content_range = format!("bytes {}-{}/{len}", range.start, range.end - 1);
+
+                headers.insert(
+                    header::CONTENT_RANGE,
+                    HeaderValue::try_from(content_range)
+                        .expect("Content-Range is a valid header value"),
+                );
+                *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+                range
+            }
+            ByteRange::Unsatisfiable => {
+                headers.insert(
+                    header::CONTENT_RANGE,
+                    HeaderValue::try_from(format!("bytes */{len}"))
+                        .expect("Content-Range is a valid header value"),
+                );
+                *response.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                return Ok(Some(response));
+            }
+        };
+
+        response.headers_mut().insert(
+            header::CONTENT_LENGTH,
+            HeaderValue::from(range.end - range.start),
+        );
+        file.seek(SeekFrom::Start(range.start)).await?;
+        *response.body_mut() =
+            Body::wrap_stream(ReaderStream::new(file.take(range.end - range.start)));
+
+        Ok(Some(response))
+    }
+
+    fn cached(&self, path: &Path, metadata: &std::fs::Metadata) -> Option<HeaderValue> {
+        self.etags
+            .lock()
+            .unwrap()
+            .get(path, metadata.len(), metadata.modified().ok())
+    }
+
+    /// Digests the content of `file` without loading it in memory.
+    async fn compute(
+        &self,
+        path: &Path,
+        metadata: &std::fs::Metadata,
+        file: &File,
+    ) -> io::Result<HeaderValue> {
+        let mut file = file.try_clone().await?.into_std().await;
+        let digest = tokio::task::spawn_blocking(move || {
+            let mut hasher = Sha256::new();
+
+            io::Seek::seek(&mut file, SeekFrom::Start(0))?;
+            io::copy(&mut file, &mut hasher)?;
+            Ok::<_, io::Error>(hasher.finalize())
+        })
+        .await
+        .map_err(io::Error::other)??;
+        let etag = HeaderValue::try_from(format!("\"{}\"", hex::encode(digest)))
+            .expect("hex digests are valid header values");
+
+        self.etags.lock().unwrap().insert(
+            path.to_path_buf(),
+            Version {
+                len: metadata.len(),
+                modified: metadata.modified().ok(),
+                etag: etag.clone(),
+                used: 0,
+            },
+        );
+
+        Ok(etag)
+    }
+}
+
+/// Digests of the files served most recently.
+struct Etags {
+    versions: HashMap<PathBuf, Version>,
+    capacity: usize,
+    /// Incremented on every use, to find the least recently used version.
+    clock: u64,
+}
+
+struct Version {
+    len: u64,
+    modified: Option<SystemTime>,
+    etag: HeaderValue,
+    used: u64,
+}
+
+impl Etags {
+    fn new(capacity: usize) -> Self {
+        Self {
+            versions: HashMap::new(),
+            capacity,
+            clock: 0,
+        }
+    }
+
+    /// Digest of the file at `path`, if it was computed for the same version of the file.
+    fn get(&mut self, path: &Path, len: u64, modified: Option<SystemTime>) -> Option<HeaderValue> {
+        self.clock += 1;
+
+        let version = self
+            .versions
+            .get_mut(path)
+            .filter(|version| version.len == len && version.modified == modified)?;
+
+        version.used = self.clock;
+        Some(version.etag.clone())
+    }
+
+    /// Records the digest of a file, forgetting the least recently used one when full.
+    fn insert(&mut self, path: PathBuf, mut version: Version) {
+        self.clock += 1;
+        version.used = self.clock;
+
+        if self.versions.len() >= self.capacity && !self.versions.contains_key(&path) {
+            // Scanning every entry is fine at `ETAGS_CAPACITY`, next to the hashing of the file
+            // that precedes each insertion.
+            let oldest = self
+                .versions
+                .iter()
+                .min_by_key(|(_, version)| version.used)
+                .map(|(path, _)| path.clone());
+
+            if let Some(oldest) = oldest {
+                self.versions.remove(&oldest);
+            }
+        }
+        self.versions.insert(path, version);
+    }
+}
+
+/// Whether the `If-None-Match` list `tags` names `etag`, with the weak comparison it requires.
+fn matches_any(tags: &str, etag: &HeaderValue) -> bool {
+    tags.split(',').map(str::trim).any(|tag| {
+        tag == "*" || tag.strip_prefix("W/").unwrap_or(tag).as_bytes() == etag.as_bytes()
+    })
+}
+
+enum ByteRange {
+    Full,
+    Partial(Range<u64>),
+    Unsatisfiable,
+}
+
+/// Parses a `Range` header for a file of `len` bytes.
+///
+/// Only a single range is supported, the whole file is sent for several ranges or an invalid
+/// header, as allowed by RFC 9110.
+fn byte_range(header: &str, len: u64) -> ByteRange {
+    let spec = match header.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec,
+        _ => return ByteRange::Full,
+    };
+    let (start, end) = match spec.split_once('-') {
+        Some((start, end)) => (start.trim(), end.trim()),
+        None => return ByteRange::Full,
+    };
+
+    match (start.parse::<u64>(), end.parse::<u64>()) {
+        // The last `suffix` bytes.
+        (Err(_), Ok(suffix)) if start.is_empty() => {
+            if suffix == 0 || len == 0 {
+                ByteRange::Unsatisfiable
+            } else {
+                ByteRange::Partial(len.saturating_sub(suffix)..len)
+            }
+        }
+        (Ok(start), Err(_)) if end.is_empty() => {
+            if start >= len {
+                ByteRange::Unsatisfiable
+            } else {
+                ByteRange::Partial(start..len)
+            }
+        }
+        (Ok(start), Ok(end)) if start <= end => {
+            if start >= len {
+                ByteRange::Unsatisfiable
+            } else {
+                ByteRange::Partial(start..len.min(end + 1))
+            }
+        }
+        _ => ByteRange::Full,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use warp::http::HeaderValue;
+
+    use super::{Etags, Version};
+
+    fn version(etag: &'static str) -> Version {
+        Version {
+            len: 1,
+            modified: None,
+            etag: HeaderValue::from_static(etag),
+            used: 0,
+        }
+    }
+
+    #[test]
+    fn etags_forget_least_recently_used() {
+        let mut etags = Etags::new(2);
+
+        etags.insert("a".into(), version("\"a\""));
+        etags.insert("b".into(), version("\"b\""));
+        etags.get(Path::new("a"), 1, None);
+        etags.insert("c".into(), version("\"c\""));
+
+        assert_eq!(etags.versions.len(), 2);
+        assert_eq!(etags.get(Path::new("a"), 1, None).unwrap(), "\"a\"");
+        assert_eq!(etags.get(Path::new("b"), 1, None), None);
+        assert_eq!(etags.get(Path::new("c"), 1, None).unwrap(), "\"c\"");
+    }
+
+    #[test]
+    fn etags_of_other_versions() {
+        let mut etags = Etags::new(2);
+
+        etags.insert("a".into(), version("\"a\""));
+
+        assert_eq!(etags.get(Path::new("a"), 2, None), None);
+    }
+}
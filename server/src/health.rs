@@ -0,0 +1,122 @@
+//! Liveness and readiness endpoints.
+//!
+//! `/healthz` answers as long as the process serves requests, `/readyz` runs every [`Checks`] and
+//! answers 503 when one of them fails.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use futures::{future::BoxFuture, FutureExt};
+use serde_json::{json, Map, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::warn;
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+use db::Pool;
+
+type Check = Arc<dyn Fn() -> BoxFuture<'static, Result<(), String>> + Send + Sync>;
+
+/// Named readiness checks.
+#[derive(Clone, Default)]
+pub struct Checks(Vec<(&'static str, Check)>);
+
+impl Checks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with<F, Fut>(mut self, name: &'static str, check: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<(), String>> + Send + 'static,
+    {
+        self.0.push((name, Arc::new(move || check().boxed())));
+        self
+    }
+
+    /// Checks that the database answers.
+    pub fn database(self, pool: Pool) -> Self {
+        self.with("database", move || {
+            let pool = pool.clone();
+
+            async move { db::pool::ping(&pool).await.map_err(|e| e.to_string()) }
+        })
+    }
+
+    async fn run(self) -> impl Reply {
+        let mut ready = true;
+        let mut results = Map::new();
+
+        for (name, check) in self.0 {
+            let result = match check().await {
+                Ok(()) => String::from("ok"),
+                Err(err) => {
+                    warn!("Readiness check {name} failed: {err}");
+                    ready = false;
+                    err
+                }
+            };
+
+            results.insert(name.to_string(), Value::String(result));
+        }
+
+        let (status, code) = if ready {
+            ("ok", StatusCode::OK)
+        } else {
+            ("unavailable", StatusCode::SERVICE_UNAVAILABLE)
+        };
+
+        warp::reply::with_status(
+            warp::reply::json(&json!({ "status": status, "checks": results })),
+            code,
+        )
+    }
+}
+
+/// Paths of [`routes`].
+pub(crate) const PATHS: [&str; 2] = ["/healthz", "/readyz"];
+
+/// `GET /healthz` and `GET /readyz`.
+pub fn routes(checks: Checks) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let healthz = warp::path("healthz")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(|| warp::reply::json(&json!({ "status": "ok" })));
+    let readyz = warp::path("readyz")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || checks.clone())
+        .then(Checks::run);
+
+    healthz.or(readyz)
+}
+
+/// Requests `/readyz` from a server listening on `addr`, for container health checks where no
+/// HTTP client is available. Returns whether the server is ready.
+pub async fn probe(mut addr: SocketAddr) -> bool {
+    if addr.ip().is_unspecified() {
+        addr.set_ip([127, 0, 0, 1].into());
+    }
+
+    let response = async {
+        let mut stream = tokio::net::TcpStream::connect(addr).await?;
+        let mut response = Vec::new();
+
+        stream
+            .write_all(b"GET /readyz HTTP/1.0\r\nHost: localhost\r\n\r\n")
+            .await?;
+        stream.read_to_end(&mut response).await?;
+
+        Ok::<_, std::io::Error>(response)
+    }
+    .await;
+
+    match response {
+        Ok(response) => {
+            response.starts_with(b"HTTP/1.1 200") || response.starts_with(b"HTTP/1.0 200")
+        }
+        Err(err) => {
+            eprintln!("Failed to reach {addr}: {err}");
+            false
+        }
+    }
+}
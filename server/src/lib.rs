@@ -0,0 +1,81 @@
+//! Plumbing shared by the HTTP binaries.
+
+pub mod cors;
+pub mod files;
+pub mod health;
+pub mod logging;
+pub mod metrics;
+pub mod request;
+pub mod shutdown;
+pub mod signing;
+
+use std::{convert::Infallible, net::SocketAddr, time::Duration};
+
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+use warp::hyper::{
+    server::conn::AddrStream,
+    service::{make_service_fn, service_fn, Service},
+    Body, Request, Response, Server,
+};
+
+use shutdown::Trigger;
+
+/// Serves `service`, usually made with `warp::service`, on `addr` until SIGINT or SIGTERM.
+///
+/// Each request is handled in its own span with a request id, see [`request`]. On shutdown the
+/// listener is closed and in-flight requests get `drain_timeout` to complete before being dropped,
+/// while background jobs subscribed to `trigger` are told to stop. Returns the end of the drain, to
+/// bound the remaining cleanup with [`shutdown::until`].
+pub async fn serve<S>(
+    service: S,
+    addr: SocketAddr,
+    drain_timeout: Duration,
+    trigger: Trigger,
+) -> Instant
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+{
+    let make_service = make_service_fn(move |conn: &AddrStream| {
+        let service = service.clone();
+        let remote = conn.remote_addr();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                request::handle(service.clone(), remote, req)
+            }))
+        }
+    });
+    let server = match Server::try_bind(&addr) {
+        Ok(builder) => builder.serve(make_service),
+        Err(err) => {
+            error!("Failed to bind {addr}: {err}");
+            std::process::exit(1);
+        }
+    };
+    let addr = server.local_addr();
+    let shutdown = trigger.subscribe();
+    let mut server = tokio::spawn(server.with_graceful_shutdown(shutdown.wait()));
+
+    info!("Starting server on {addr}");
+
+    shutdown::signal().await;
+    info!("Shutting down, draining requests for up to {drain_timeout:?}");
+    trigger.trigger();
+
+    let deadline = Instant::now() + drain_timeout;
+
+    match tokio::time::timeout_at(deadline, &mut server).await {
+        Ok(_) => info!("All requests drained"),
+        Err(_) => {
+            warn!("Drain timeout reached, dropping in-flight requests");
+            server.abort();
+        }
+    }
+
+    deadline
+}
@@ -0,0 +1,20 @@
+//! JSON logs on stdout, filtered with `RUST_LOG`.
+//!
+//! Every event carries the spans it happened in, including the `request` span and its id, and
+//! closed spans are logged with their duration.
+
+use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
+
+const DEFAULT_FILTER: &str = "info,sqlx=warn";
+
+/// Installs the subscriber, `log` records of dependencies are forwarded to it.
+pub fn init() {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+
+    tracing_subscriber::fmt()
+        .json()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .init();
+}
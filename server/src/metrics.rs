@@ -0,0 +1,219 @@
+//! Prometheus metrics, served at `/metrics` from the default registry.
+//!
+//! Binaries register their own metrics in the default registry, gauges that need a query are
+//! updated by [`Hooks`] right before each scrape.
+
+use std::sync::Arc;
+
+use futures::{future::BoxFuture, FutureExt};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, Encoder, HistogramVec,
+    IntCounterVec, IntGauge, TextEncoder,
+};
+use tracing::warn;
+use warp::{
+    http::{header::CONTENT_TYPE, StatusCode},
+    log::Info,
+    Filter, Rejection, Reply,
+};
+
+use db::Pool;
+
+use crate::health;
+
+static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "http_requests_total",
+        "HTTP requests handled, by route and status",
+        &["method", "route", "status"]
+    )
+    .unwrap()
+});
+
+static HTTP_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "http_request_duration_seconds",
+        "Time taken to answer HTTP requests, by route and status",
+        &["method", "route", "status"]
+    )
+    .unwrap()
+});
+
+static POOL_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!("db_pool_connections", "Database connections currently open").unwrap()
+});
+
+static POOL_IDLE: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "db_pool_idle_connections",
+        "Database connections open but not in use"
+    )
+    .unwrap()
+});
+
+type Hook = Arc<dyn Fn() -> BoxFuture<'static, Result<(), String>> + Send + Sync>;
+
+/// Named updates run before each scrape.
+#[derive(Clone, Default)]
+pub struct Hooks(Vec<(&'static str, Hook)>);
+
+impl Hooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with<F, Fut>(mut self, name: &'static str, hook: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<(), String>> + Send + 'static,
+    {
+        self.0.push((name, Arc::new(move || hook().boxed())));
+        self
+    }
+
+    /// Exports the usage of the connection pool.
+    pub fn pool(self, pool: Pool) -> Self {
+        self.with("pool", move || {
+            POOL_CONNECTIONS.set(pool.size().into());
+            POOL_IDLE.set(pool.num_idle() as i64);
+
+            async { Ok(()) }
+        })
+    }
+
+    async fn scrape(self) -> impl Reply {
+        for (name, hook) in self.0 {
+            if let Err(err) = hook().await {
+                warn!("Metrics hook {name} failed: {err}");
+            }
+        }
+
+        let encoder = TextEncoder::new();
+        let mut body = Vec::new();
+
+        match encoder.encode(&prometheus::gather(), &mut body) {
+            Ok(()) => warp::reply::with_header(
+                warp::reply::with_status(body, StatusCode::OK),
+                CONTENT_TYPE,
+                encoder.format_type(),
+            ),
+            Err(err) => {
+                warn!("Failed to encode metrics: {err}");
+
+                warp::reply::with_header(
+                    warp::reply::with_status(Vec::new(), StatusCode::INTERNAL_SERVER_ERROR),
+                    CONTENT_TYPE,
+                    encoder.format_type(),
+                )
+            }
+        }
+    }
+}
+
+/// `GET /metrics`.
+pub fn routes(hooks: Hooks) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("metrics")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || hooks.clone())
+        .then(Hooks::scrape)
+}
+
+/// Counts requests and records their latency, to wrap around every route with `Filter::with`.
+///
+/// Requests are labelled with the first of `routes` their path matches, where `{…}` segments
+/// match any segment, such as `/users/confirm/{id}`. `/metrics` and the health routes are always
+/// known. Other paths are grouped, as any client could otherwise create new labels.
+pub fn http<I>(routes: I) -> warp::log::Log<impl Fn(Info<'_>) + Clone>
+where
+    I: IntoIterator,
+    I::Item: Into<String>,
+{
+    let routes: Arc<[String]> = routes
+        .into_iter()
+        .map(Into::into)
+        .chain(
+            ["/metrics"]
+                .into_iter()
+                .chain(health::PATHS)
+                .map(String::from),
+        )
+        .collect();
+
+    warp::log::custom(move |info| {
+        let status = info.status();
+        let labels = [
+            info.method().as_str(),
+            route(&routes, info.path()),
+            status.as_str(),
+        ];
+
+        HTTP_REQUESTS.with_label_values(&labels).inc();
+        HTTP_DURATION
+            .with_label_values(&labels)
+            .observe(info.elapsed().as_secs_f64());
+    })
+}
+
+/// The first of `routes` matching `path`, `unmatched` when there is none.
+fn route<'a>(routes: &'a [String], path: &str) -> &'a str {
+    routes
+        .iter()
+        .find(|route| matches(route, path))
+        .map_or("unmatched", String::as_str)
+}
+
+fn matches(route: &str, path: &str) -> bool {
+    let mut route = route.split('/');
+    let mut path = path.split('/');
+
+    loop {
+        match (route.next(), path.next()) {
+            (None, None) => return true,
+            (Some(expected), Some(segment))
+                if expected == segment
+                    || (expected.starts_with('{')
+                        && expected.ends_with('}')
+                        && !segment.is_empty()) => {}
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::route;
+
+    fn routes() -> Vec<String> {
+        [
+            "/users/login",
+            "/users/confirm/{id}",
+            "/sessions/{id}/images",
+        ]
+        .map(String::from)
+        .to_vec()
+    }
+
+    #[test]
+    fn labelled_with_route() {
+        assert_eq!(route(&routes(), "/users/confirm/12"), "/users/confirm/{id}");
+        assert_eq!(
+            route(&routes(), "/sessions/anything/images"),
+            "/sessions/{id}/images"
+        );
+        assert_eq!(route(&routes(), "/users/login"), "/users/login");
+    }
+
+    #[test]
+    fn unmatched_paths_are_grouped() {
+        for path in [
+            "/wp-admin",
+            "/users/confirm/",
+            "/users/login/more",
+            "/users",
+        ] {
+            assert_eq!(route(&routes(), path), "unmatched", "{path}");
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! Request ids and the span every request is handled in.
+//!
+//! The id is taken from the `x-request-id` header when the client or a proxy sent a valid one,
+//! generated otherwise, and sent back in the same header so that the logs of every service a
+//! request went through can be followed with it.
+
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    time::Instant,
+};
+
+use tracing::{info, info_span, Instrument};
+use uuid::Uuid;
+use warp::{
+    http::HeaderMap,
+    hyper::{
+        header::{HeaderName, HeaderValue},
+        service::Service,
+        Body, Request, Response,
+    },
+    Filter,
+};
+
+pub const HEADER: &str = "x-request-id";
+
+const MAX_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Address of the client, available to filters through `warp::ext::optional`.
+#[derive(Debug, Clone, Copy)]
+pub struct RemoteAddr(pub SocketAddr);
+
+/// IP of the client. Behind a reverse proxy, `header` names the header it appends the address of
+/// the client to, such as `x-forwarded-for`: the last address of that header is used. Without a
+/// header, or when it is missing, the address of the connection is used. Only set `header` when
+/// every request goes through such a proxy, since clients can send the header themselves.
+pub fn client_ip(
+    header: Option<HeaderName>,
+) -> impl Filter<Extract = (Option<IpAddr>,), Error = Infallible> + Clone {
+    warp::ext::optional()
+        .and(warp::header::headers_cloned())
+        .map(move |remote: Option<RemoteAddr>, headers: HeaderMap| {
+            header
+                .as_ref()
+                .and_then(|header| forwarded(&headers, header))
+                .or(remote.map(|remote| remote.0.ip()))
+        })
+}
+
+fn forwarded(headers: &HeaderMap, header: &HeaderName) -> Option<IpAddr> {
+    headers
+        .get_all(header)
+        .iter()
+        .next_back()?
+        .to_str()
+        .ok()?
+        .rsplit(',')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Id of the request being handled by the current task.
+pub fn id() -> Option<String> {
+    REQUEST_ID.try_with(String::clone).ok()
+}
+
+pub(crate) async fn handle<S>(
+    mut service: S,
+    remote: SocketAddr,
+    mut request: Request<Body>,
+) -> Result<Response<Body>, Infallible>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible>,
+{
+    let id = request
+        .headers()
+        .get(HEADER)
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map(String::from)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let span = info_span!(
+        "request",
+        request_id = %id,
+        method = %request.method(),
+        path = %request.uri().path(),
+    );
+    let started = Instant::now();
+
+    request.extensions_mut().insert(RemoteAddr(remote));
+    futures::future::poll_fn(|cx| service.poll_ready(cx)).await?;
+
+    let mut response = REQUEST_ID
+        .scope(id.clone(), service.call(request))
+        .instrument(span.clone())
+        .await?;
+
+    span.in_scope(|| {
+        info!(
+            status = response.status().as_u16(),
+            elapsed_ms = started.elapsed().as_secs_f64() * 1000.,
+            "Request completed"
+        )
+    });
+    if let Ok(id) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(HEADER, id);
+    }
+
+    Ok(response)
+}
+
+/// Rejects ids that could be used to forge log lines.
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_LENGTH && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use warp::{
+        hyper::{header::HeaderName, Request},
+        Filter,
+    };
+
+    use super::{client_ip, handle, id, RemoteAddr, HEADER};
+
+    async fn reply(request: Request<warp::hyper::Body>) -> (String, String) {
+        let routes = warp::ext::optional()
+            .map(|addr: Option<RemoteAddr>| format!("{} {}", id().unwrap(), addr.unwrap().0));
+        let response = handle(
+            warp::service(routes),
+            ([127, 0, 0, 1], 4242).into(),
+            request,
+        )
+        .await
+        .unwrap();
+        let header = response.headers()[HEADER].to_str().unwrap().to_string();
+        let body = warp::hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+
+        (header, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn propagated() {
+        let request = Request::builder()
+            .header(HEADER, "abc-123")
+            .body(Default::default())
+            .unwrap();
+
+        let (header, body) = reply(request).await;
+
+        assert_eq!(header, "abc-123");
+        assert_eq!(body, "abc-123 127.0.0.1:4242");
+    }
+
+    #[tokio::test]
+    async fn generated() {
+        let request = Request::builder()
+            .header(HEADER, "forged line")
+            .body(Default::default())
+            .unwrap();
+
+        let (header, body) = reply(request).await;
+
+        assert_eq!(header.len(), 36);
+        assert!(body.starts_with(&header));
+    }
+
+    async fn ip(header: Option<&'static str>, forwarded: &[&str]) -> String {
+        let routes = client_ip(header.map(HeaderName::from_static))
+            .map(|ip: Option<IpAddr>| ip.unwrap().to_string());
+        let request = forwarded
+            .iter()
+            .fold(Request::builder(), |request, value| {
+                request.header("x-forwarded-for", *value)
+            })
+            .body(Default::default())
+            .unwrap();
+        let response = handle(
+            warp::service(routes),
+            ([127, 0, 0, 1], 4242).into(),
+            request,
+        )
+        .await
+        .unwrap();
+        let body = warp::hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn client_ip_from_connection() {
+        assert_eq!(ip(None, &["10.0.0.1"]).await, "127.0.0.1");
+        assert_eq!(ip(Some("x-forwarded-for"), &[]).await, "127.0.0.1");
+        assert_eq!(ip(Some("x-forwarded-for"), &["invalid"]).await, "127.0.0.1");
+    }
+
+    #[tokio::test]
+    async fn client_ip_from_proxy() {
+        assert_eq!(ip(Some("x-forwarded-for"), &["10.0.0.1"]).await, "10.0.0.1");
+        assert_eq!(
+            ip(Some("x-forwarded-for"), &["10.0.0.1, 10.0.0.2"]).await,
+            "10.0.0.2"
+        );
+        assert_eq!(
+            ip(Some("x-forwarded-for"), &["10.0.0.1", "::1"]).await,
+            "::1"
+        );
+    }
+
+    #[test]
+    fn outside_of_requests() {
+        assert_eq!(id(), None);
+    }
+}
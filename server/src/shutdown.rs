@@ -0,0 +1,95 @@
+//! Shutdown notifications for the server and background jobs.
+
+use std::future::Future;
+
+use tokio::{sync::watch, time::Instant};
+use tracing::warn;
+
+/// Sends the shutdown notification, once.
+pub struct Trigger(watch::Sender<bool>);
+
+/// Resolves [`Shutdown::wait`] once the [`Trigger`] fired or was dropped.
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    pub async fn wait(mut self) {
+        while !*self.0.borrow() {
+            if self.0.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Trigger {
+    pub fn new() -> Self {
+        Self(watch::channel(false).0)
+    }
+
+    pub fn subscribe(&self) -> Shutdown {
+        Shutdown(self.0.subscribe())
+    }
+
+    pub fn trigger(&self) {
+        self.0.send_replace(true);
+    }
+}
+
+impl Default for Trigger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Resolves on the first SIGINT, or SIGTERM on Unix.
+pub async fn signal() {
+    let interrupt = tokio::signal::ctrl_c();
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+
+        tokio::select! {
+            _ = interrupt => (),
+            _ = terminate.recv() => (),
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = interrupt.await;
+}
+
+/// Runs a cleanup step of the shutdown until `deadline`, giving up on it past that point.
+pub async fn until<F: Future>(deadline: Instant, step: &str, future: F) -> Option<F::Output> {
+    let output = tokio::time::timeout_at(deadline, future).await.ok();
+
+    if output.is_none() {
+        warn!("Shutdown deadline reached, abandoning {step}");
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::{sleep, Instant};
+
+    use super::until;
+
+    #[tokio::test]
+    async fn until_deadline() {
+        let deadline = Instant::now() + Duration::from_millis(50);
+
+        assert_eq!(until(deadline, "quick", async { 1 }).await, Some(1));
+        assert_eq!(
+            until(deadline, "slow", sleep(Duration::from_secs(5))).await,
+            None
+        );
+        assert!(Instant::now() < deadline + Duration::from_secs(1));
+    }
+}
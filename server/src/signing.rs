@@ -0,0 +1,106 @@
+//! Expiring URLs signed with HMAC-SHA256, so that a service can check that another one handed them
+//! out without asking it.
+//!
+//! The signature covers the path and the expiration, sent as `exp=<unix time>&sig=<base64url>`.
+//! Several keys may be configured to rotate them: URLs are signed with the first key and accepted
+//! when signed with any of them. A new key is first added last to every service then moved first,
+//! and the previous key is removed once the URLs it signed have expired.
+
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Shortest key accepted, the length of the digest.
+pub const KEY_MIN_LENGTH: usize = 32;
+
+/// Secret shared by the services signing and verifying URLs.
+#[derive(Clone, PartialEq, Eq)]
+struct Key(Vec<u8>);
+
+/// Keys signing URLs with the first one and verifying them with every one.
+#[derive(Clone)]
+pub struct Keys(Vec<Key>);
+
+/// Comma separated keys, errors never include them as they are secret.
+impl FromStr for Keys {
+    type Err = String;
+
+    fn from_str(keys: &str) -> Result<Self, Self::Err> {
+        let keys: Vec<_> = keys
+            .split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .collect();
+
+        if keys.is_empty() {
+            return Err(String::from("at least one key is required"));
+        }
+
+        keys.into_iter()
+            .enumerate()
+            .map(|(index, key)| {
+                if key.len() < KEY_MIN_LENGTH {
+                    Err(format!(
+                        "key {} must be at least {KEY_MIN_LENGTH} characters long",
+                        index + 1
+                    ))
+                } else {
+                    Ok(Key(key.as_bytes().to_vec()))
+                }
+            })
+            .collect::<Result<_, _>>()
+            .map(Keys)
+    }
+}
+
+impl fmt::Debug for Keys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Keys(<{} redacted>)", self.0.len())
+    }
+}
+
+impl Keys {
+    /// Query string letting `path` be requested until `expires`, rounded down to the second.
+    pub fn sign(&self, path: &str, expires: SystemTime) -> String {
+        let expires = unix(expires);
+        let signature = mac(&self.0[0], path, expires).finalize().into_bytes();
+
+        format!("exp={expires}&sig={}", URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    /// Whether `signature`, the `sig` parameter, was computed by [`Keys::sign`] for `path` and
+    /// `expires`, the `exp` parameter, with one of the keys, and `expires` is after `now`.
+    pub fn verify(&self, path: &str, expires: &str, signature: &str, now: SystemTime) -> bool {
+        let (expires, signature) = match (expires.parse(), URL_SAFE_NO_PAD.decode(signature)) {
+            (Ok(expires), Ok(signature)) => (expires, signature),
+            _ => return false,
+        };
+
+        expires > unix(now)
+            && self
+                .0
+                .iter()
+                .any(|key| mac(key, path, expires).verify_slice(&signature).is_ok())
+    }
+}
+
+fn mac(key: &Key, path: &str, expires: u64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key.0).expect("HMAC accepts keys of any length");
+
+    mac.update(path.as_bytes());
+    mac.update(b"\n");
+    mac.update(expires.to_string().as_bytes());
+    mac
+}
+
+fn unix(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
@@ -0,0 +1,102 @@
+mod filter {
+    use server::cors::{Origin, Policy};
+    use warp::{http::StatusCode, Filter};
+
+    fn policy(origins: &[&str]) -> Policy {
+        Policy {
+            origins: origins
+                .iter()
+                .map(|origin| origin.parse().unwrap())
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn routes(policy: &Policy) -> impl Filter<Extract = (impl warp::Reply,)> + Clone {
+        warp::path("ping").map(|| "pong").with(policy.filter())
+    }
+
+    #[tokio::test]
+    async fn preflight() {
+        let response = warp::test::request()
+            .method("OPTIONS")
+            .path("/ping")
+            .header("origin", "http://localhost:8000")
+            .header("access-control-request-method", "POST")
+            .header("access-control-request-headers", "Authorization")
+            .reply(&routes(&policy(&["http://localhost:8000"])))
+            .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["access-control-allow-origin"],
+            "http://localhost:8000"
+        );
+        assert!(response.body().is_empty());
+    }
+
+    #[tokio::test]
+    async fn allowed() {
+        let response = warp::test::request()
+            .path("/ping")
+            .header("origin", "https://example.com")
+            .reply(&routes(&policy(&["*"])))
+            .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["access-control-allow-origin"],
+            "https://example.com"
+        );
+        assert_eq!(
+            response.headers()["access-control-expose-headers"],
+            "x-request-id"
+        );
+    }
+
+    #[tokio::test]
+    async fn forbidden() {
+        let routes = routes(&policy(&["http://localhost:8000"]));
+
+        let origin = warp::test::request()
+            .path("/ping")
+            .header("origin", "http://evil.com")
+            .reply(&routes)
+            .await;
+        let header = warp::test::request()
+            .method("OPTIONS")
+            .path("/ping")
+            .header("origin", "http://localhost:8000")
+            .header("access-control-request-method", "POST")
+            .header("access-control-request-headers", "x-custom")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(origin.status(), StatusCode::FORBIDDEN);
+        assert_eq!(header.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn not_cors() {
+        let response = warp::test::request()
+            .path("/ping")
+            .reply(&routes(&policy(&[])))
+            .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response
+            .headers()
+            .contains_key("access-control-allow-origin"));
+    }
+
+    #[test]
+    fn origins() {
+        assert_eq!(
+            "http://localhost:8000/".parse(),
+            Ok(Origin::Exact(String::from("http://localhost:8000")))
+        );
+        assert_eq!("*".parse(), Ok(Origin::Any));
+        assert!("localhost:8000".parse::<Origin>().is_err());
+        assert!("https://example.com/app".parse::<Origin>().is_err());
+    }
+}
@@ -0,0 +1,250 @@
+mod reply {
+    use std::{path::Path, time::Duration};
+
+    use server::files::{self, Files};
+    use tempfile::TempDir;
+    use warp::{http::StatusCode, Filter};
+
+    const DATA: &[u8] = b"0123456789";
+
+    fn routes(
+        dir: &Path,
+    ) -> impl Filter<Extract = (warp::http::Response<warp::hyper::Body>,)> + Clone {
+        let files = Files::new(Duration::from_secs(3600), |data| {
+            data.starts_with(b"0").then_some("text/plain")
+        });
+        let dir = dir.to_path_buf();
+
+        warp::path::param::<String>().and(files::conditions()).then(
+            move |name: String, conditions| {
+                let files = files.clone();
+                let path = dir.join(&name);
+
+                async move {
+                    files
+                        .reply(&path, name != "private", &conditions)
+                        .await
+                        .unwrap()
+                        .unwrap()
+                }
+            },
+        )
+    }
+
+    fn dir() -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+
+        std::fs::write(dir.path().join("file"), DATA).unwrap();
+        std::fs::write(dir.path().join("private"), DATA).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn full() {
+        let dir = dir();
+        let response = warp::test::request()
+            .path("/file")
+            .reply(&routes(dir.path()))
+            .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body().as_ref(), DATA);
+        assert_eq!(response.headers()["content-type"], "text/plain");
+        assert_eq!(
+            response.headers()["cache-control"],
+            "public, max-age=3600, immutable"
+        );
+        assert_eq!(response.headers()["accept-ranges"], "bytes");
+        assert_eq!(
+            response.headers()["etag"],
+            "\"84d89877f0d4041efb6bf91a16f0248f2fd573e6af05c19f96bedb9f882f7882\""
+        );
+    }
+
+    #[tokio::test]
+    async fn private() {
+        let dir = dir();
+        let response = warp::test::request()
+            .path("/private")
+            .reply(&routes(dir.path()))
+            .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["cache-control"],
+            "private, max-age=3600, immutable"
+        );
+    }
+
+    #[tokio::test]
+    async fn not_modified() {
+        let dir = dir();
+        let routes = routes(dir.path());
+        let etag = warp::test::request()
+            .path("/file")
+            .reply(&routes)
+            .await
+            .headers()["etag"]
+            .clone();
+
+        let response = warp::test::request()
+            .path("/file")
+            .header(
+                "if-none-match",
+                format!("\"other\", W/{}", etag.to_str().unwrap()),
+            )
+            .reply(&routes)
+            .await;
+        let other = warp::test::request()
+            .path("/file")
+            .header("if-none-match", "\"other\"")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()["etag"], etag);
+        assert!(response.body().is_empty());
+        assert_eq!(other.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn changed() {
+        let dir = dir();
+        let routes = routes(dir.path());
+        let reply = || warp::test::request().path("/file").reply(&routes);
+
+        let before = reply().await.headers()["etag"].clone();
+        std::fs::write(dir.path().join("file"), b"changed").unwrap();
+        let after = reply().await;
+
+        assert_ne!(after.headers()["etag"], before);
+        assert_eq!(after.body().as_ref(), b"changed");
+    }
+
+    #[tokio::test]
+    async fn range() {
+        let dir = dir();
+        let routes = routes(dir.path());
+
+        for (range, status, body, content_range) in [
+            (
+                "bytes=2-4",
+                StatusCode::PARTIAL_CONTENT,
+                "234",
+                Some("bytes 2-4/10"),
+            ),
+            (
+                "bytes=7-",
+                StatusCode::PARTIAL_CONTENT,
+                "789",
+                Some("bytes 7-9/10"),
+            ),
+            (
+                "bytes=-2",
+                StatusCode::PARTIAL_CONTENT,
+                "89",
+                Some("bytes 8-9/10"),
+            ),
+            (
+                "bytes=8-100",
+                StatusCode::PARTIAL_CONTENT,
+                "89",
+                Some("bytes 8-9/10"),
+            ),
+            (
+                "bytes=10-",
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                "",
+                Some("bytes */10"),
+            ),
+            ("bytes=0-1,4-5", StatusCode::OK, "0123456789", None),
+            ("bytes=5-2", StatusCode::OK, "0123456789", None),
+            ("items=0-1", StatusCode::OK, "0123456789", None),
+        ] {
+            let response = warp::test::request()
+                .path("/file")
+                .header("range", range)
+                .reply(&routes)
+                .await;
+
+            assert_eq!(response.status(), status, "{range}");
+            assert_eq!(response.body().as_ref(), body.as_bytes(), "{range}");
+            assert_eq!(
+                response
+                    .headers()
+                    .get("content-range")
+                    .map(|value| value.to_str().unwrap()),
+                content_range,
+                "{range}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn large() {
+        let dir = dir();
+        let routes = routes(dir.path());
+        let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+
+        std::fs::write(dir.path().join("large"), &data).unwrap();
+        let full = warp::test::request().path("/large").reply(&routes).await;
+        let partial = warp::test::request()
+            .path("/large")
+            .header("range", "bytes=5000-70000")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(full.headers()["content-length"], "100000");
+        assert_eq!(full.body().as_ref(), data);
+        assert_eq!(partial.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(partial.headers()["content-length"], "65001");
+        assert_eq!(partial.body().as_ref(), &data[5000..=70000]);
+    }
+
+    #[tokio::test]
+    async fn if_range() {
+        let dir = dir();
+        let routes = routes(dir.path());
+        let etag = warp::test::request()
+            .path("/file")
+            .reply(&routes)
+            .await
+            .headers()["etag"]
+            .clone();
+
+        let current = warp::test::request()
+            .path("/file")
+            .header("range", "bytes=0-1")
+            .header("if-range", etag)
+            .reply(&routes)
+            .await;
+        let outdated = warp::test::request()
+            .path("/file")
+            .header("range", "bytes=0-1")
+            .header("if-range", "\"outdated\"")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(current.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(current.body().as_ref(), b"01");
+        assert_eq!(outdated.status(), StatusCode::OK);
+        assert_eq!(outdated.body().as_ref(), DATA);
+    }
+
+    #[tokio::test]
+    async fn missing() {
+        let dir = dir();
+        let files = Files::new(Duration::from_secs(60), |_| None);
+
+        assert!(files
+            .reply(&dir.path().join("missing"), true, &Default::default())
+            .await
+            .unwrap()
+            .is_none());
+        assert!(files
+            .reply(dir.path(), true, &Default::default())
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,48 @@
+mod routes {
+    use server::health::{self, Checks};
+    use warp::http::StatusCode;
+
+    async fn get(checks: Checks, path: &str) -> (StatusCode, serde_json::Value) {
+        let response = warp::test::request()
+            .path(path)
+            .reply(&health::routes(checks))
+            .await;
+
+        (
+            response.status(),
+            serde_json::from_slice(response.body()).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn healthz() {
+        let checks = Checks::new().with("failing", || async { Err(String::from("down")) });
+
+        let (status, _) = get(checks, "/healthz").await;
+
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn ready() {
+        let checks = Checks::new().with("passing", || async { Ok(()) });
+
+        let (status, body) = get(checks, "/readyz").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["checks"]["passing"], "ok");
+    }
+
+    #[tokio::test]
+    async fn not_ready() {
+        let checks = Checks::new()
+            .with("passing", || async { Ok(()) })
+            .with("failing", || async { Err(String::from("down")) });
+
+        let (status, body) = get(checks, "/readyz").await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "unavailable");
+        assert_eq!(body["checks"]["failing"], "down");
+    }
+}
@@ -0,0 +1,74 @@
+use std::time::{Duration, SystemTime};
+
+use server::signing::Keys;
+
+const OLD: &str = "an old key that is at least 32 characters long";
+const NEW: &str = "a new key that is at least 32 characters long too";
+
+fn keys(keys: &[&str]) -> Keys {
+    keys.join(",").parse().unwrap()
+}
+
+/// The `exp` and `sig` parameters of a query string.
+fn params(query: &str) -> (String, String) {
+    let (expires, signature) = query.split_once('&').unwrap();
+
+    (
+        expires.strip_prefix("exp=").unwrap().to_string(),
+        signature.strip_prefix("sig=").unwrap().to_string(),
+    )
+}
+
+#[test]
+fn valid() {
+    let keys = keys(&[NEW]);
+    let now = SystemTime::now();
+    let (expires, signature) = params(&keys.sign("/42", now + Duration::from_secs(60)));
+
+    assert!(keys.verify("/42", &expires, &signature, now));
+}
+
+#[test]
+fn expired() {
+    let keys = keys(&[NEW]);
+    let now = SystemTime::now();
+    let (expires, signature) = params(&keys.sign("/42", now + Duration::from_secs(60)));
+
+    assert!(!keys.verify("/42", &expires, &signature, now + Duration::from_secs(61)));
+}
+
+#[test]
+fn tampered() {
+    let keys = keys(&[NEW]);
+    let now = SystemTime::now();
+    let (expires, signature) = params(&keys.sign("/42", now + Duration::from_secs(60)));
+    let later = expires.parse::<u64>().unwrap() + 3600;
+
+    assert!(!keys.verify("/43", &expires, &signature, now));
+    assert!(!keys.verify("/42", &later.to_string(), &signature, now));
+    assert!(!keys.verify("/42", &expires, "c2lnbmF0dXJl", now));
+    assert!(!keys.verify("/42", &expires, "not base64!", now));
+    assert!(!keys.verify("/42", "soon", &signature, now));
+}
+
+#[test]
+fn rotation() {
+    let now = SystemTime::now();
+    let expires = now + Duration::from_secs(60);
+    let (old_expires, old_signature) = params(&keys(&[OLD]).sign("/42", expires));
+    let (new_expires, new_signature) = params(&keys(&[NEW, OLD]).sign("/42", expires));
+
+    // Both keys are accepted while rotating, whichever signs.
+    assert!(keys(&[NEW, OLD]).verify("/42", &old_expires, &old_signature, now));
+    assert!(keys(&[OLD, NEW]).verify("/42", &new_expires, &new_signature, now));
+    // URLs signed with a removed key are refused.
+    assert!(!keys(&[NEW]).verify("/42", &old_expires, &old_signature, now));
+}
+
+#[test]
+fn invalid() {
+    let error = format!("{NEW}, too short").parse::<Keys>().unwrap_err();
+
+    assert_eq!(error, "key 2 must be at least 32 characters long");
+    assert!(" , ".parse::<Keys>().is_err());
+}
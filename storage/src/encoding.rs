@@ -0,0 +1,140 @@
+//! Encodings of the stored images in formats lighter than the originals, generated the first time
+//! a client accepting them requests an image and stored next to it, see [`Storage::encoded`].
+//!
+//! Only JPEG and PNG images are encoded, and only PNG images to WebP: it is encoded losslessly,
+//! which is almost never smaller than a JPEG photo. An encoding is only served when it is smaller
+//! than the original.
+//!
+//! [`Storage::encoded`]: crate::Storage::encoded
+
+use image::{codecs::webp::WebPEncoder, ColorType, ImageEncoder, ImageFormat};
+use ravif::{Img, RGB8, RGBA8};
+
+use crate::format::Format;
+
+const AVIF_QUALITY: f32 = 75.;
+/// From 1 to 10, the encoder is built without its assembly so slower speeds take seconds.
+const AVIF_SPEED: u8 = 8;
+
+/// Variants are declared from the most to the least preferred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Encoding {
+    Avif,
+    Webp,
+}
+
+impl Encoding {
+    /// Encodings accepted by a client sending the `Accept` header `accept`, preferred first.
+    ///
+    /// They must be listed explicitly, browsers send `image/*` and `*/*` whatever they support.
+    pub fn negotiate(accept: &str) -> Vec<Self> {
+        let mut accepted: Vec<_> = accept
+            .split(',')
+            .filter_map(|range| {
+                let mut params = range.split(';').map(str::trim);
+                let encoding = match params.next()?.to_ascii_lowercase().as_str() {
+                    "image/avif" => Encoding::Avif,
+                    "image/webp" => Encoding::Webp,
+                    _ => return None,
+                };
+                let quality = match params.find_map(|param| param.strip_prefix("q=")) {
+                    Some(quality) => quality.parse().ok()?,
+                    None => 1.,
+                };
+
+                (quality > 0.).then_some((encoding, quality))
+            })
+            .collect();
+
+        accepted.sort_by(|(a, a_quality): &(_, f32), (b, b_quality)| {
+            b_quality.total_cmp(a_quality).then(a.cmp(b))
+        });
+        accepted
+            .into_iter()
+            .fold(Vec::new(), |mut encodings, (encoding, _)| {
+                if !encodings.contains(&encoding) {
+                    encodings.push(encoding);
+                }
+                encodings
+            })
+    }
+
+    /// Recognizes an encoding from the first bytes of a file.
+    pub fn sniff(data: &[u8]) -> Option<Self> {
+        match data {
+            [_, _, _, _, b'f', b't', b'y', b'p', b'a', b'v', b'i', b'f' | b's', ..] => {
+                Some(Encoding::Avif)
+            }
+            _ if Format::sniff(data) == Some(Format::Webp) => Some(Encoding::Webp),
+            _ => None,
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            Encoding::Avif => "image/avif",
+            Encoding::Webp => "image/webp",
+        }
+    }
+
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Encoding::Avif => "avif",
+            Encoding::Webp => "webp",
+        }
+    }
+
+    /// Encodes a JPEG or PNG image, `None` for other formats, JPEG images to WebP or when it cannot
+    /// be decoded.
+    ///
+    /// This takes up to a few seconds for large images.
+    pub fn encode(self, data: &[u8]) -> Option<Vec<u8>> {
+        let format = match (Format::sniff(data)?, self) {
+            (Format::Jpeg, Encoding::Avif) => ImageFormat::Jpeg,
+            (Format::Png, _) => ImageFormat::Png,
+            (Format::Jpeg, Encoding::Webp) | (Format::Gif | Format::Webp, _) => return None,
+        };
+        let image = image::load_from_memory_with_format(data, format).ok()?;
+        let (width, height) = (image.width(), image.height());
+        let alpha = image.color().has_alpha();
+
+        match self {
+            Encoding::Avif => {
+                let encoder = ravif::Encoder::new()
+                    .with_quality(AVIF_QUALITY)
+                    .with_speed(AVIF_SPEED);
+                let (width, height) = (width as usize, height as usize);
+                let encoded = if alpha {
+                    let pixels: Vec<_> = image
+                        .to_rgba8()
+                        .pixels()
+                        .map(|pixel| RGBA8::from(pixel.0))
+                        .collect();
+
+                    encoder.encode_rgba(Img::new(&pixels[..], width, height))
+                } else {
+                    let pixels: Vec<_> = image
+                        .to_rgb8()
+                        .pixels()
+                        .map(|pixel| RGB8::from(pixel.0))
+                        .collect();
+
+                    encoder.encode_rgb(Img::new(&pixels[..], width, height))
+                };
+
+                encoded.ok().map(|encoded| encoded.avif_file)
+            }
+            Encoding::Webp => {
+                let mut encoded = Vec::new();
+                let encoder = WebPEncoder::new_lossless(&mut encoded);
+                let result = if alpha {
+                    encoder.write_image(&image.to_rgba8(), width, height, ColorType::Rgba8)
+                } else {
+                    encoder.write_image(&image.to_rgb8(), width, height, ColorType::Rgb8)
+                };
+
+                result.ok().map(|()| encoded)
+            }
+        }
+    }
+}
@@ -0,0 +1,499 @@
+//! Bulk import of images into a session, from uploaded files, directories or ZIP archives.
+//!
+//! Archives are expanded and every file is validated, then the valid ones are stored, created in
+//! the `images` table and associated with the session in a single transaction, so that either all
+//! of them are imported or none is. Each file gets a line in the [`Report`].
+//!
+//! Files are kept in memory until they are stored, so a [`Reader`] stops reading them once they
+//! exceed its [`Limits`].
+
+use std::{
+    fmt::{self, Display},
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use serde::Serialize;
+use time::OffsetDateTime;
+use tracing::{instrument, warn};
+
+use db::{
+    images, images_associations,
+    result::{self, context},
+    sessions::{self, State},
+    users, Pool,
+};
+
+use crate::{format::Format, phash, Storage};
+
+/// Largest image accepted, larger files are not even read.
+pub const MAX_IMAGE_SIZE: u64 = 32 * 1024 * 1024;
+
+/// A file to import, with the reason it could not be read instead of its content on failure.
+pub struct Entry {
+    pub name: String,
+    pub data: Result<Vec<u8>, String>,
+}
+
+/// Bounds on the files of a single import, checked while reading them since they are all kept in
+/// memory until they are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Files read, each entry of an archive counting as one.
+    pub max_files: usize,
+    /// Sum of the sizes of the files read, once decompressed.
+    pub max_total_size: u64,
+}
+
+/// Which of the [`Limits`] the files of an import exceed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exceeded {
+    Files(usize),
+    TotalSize(u64),
+}
+
+/// Reads the files to import, expanding ZIP archives, until they exceed the limits.
+pub struct Reader {
+    limits: Limits,
+    size: u64,
+    entries: Vec<Entry>,
+    exceeded: Option<Exceeded>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub session: i32,
+    pub imported: usize,
+    pub rejected: usize,
+    pub files: Vec<FileReport>,
+    /// Images of the session that look alike, including at least one imported image.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<NearDuplicate>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NearDuplicate {
+    pub images: [i32; 2],
+    /// Number of bits by which their perceptual hashes differ.
+    pub distance: i32,
+}
+
+/// Outcome of a file, either the id of the image created or the reason it was rejected.
+#[derive(Debug, Serialize)]
+pub struct FileReport {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Failure of the whole import, nothing was imported.
+#[derive(Debug)]
+pub enum Error {
+    Db(result::Error),
+    /// Images can only be imported before the first phase of the session.
+    Started(State),
+    Storage(io::Error),
+    /// Validation panicked.
+    Validation,
+}
+
+/// Small enough for the files of an import to be held in memory along with the image being
+/// decoded, larger imports can be split.
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_files: 200,
+            max_total_size: 128 * 1024 * 1024,
+        }
+    }
+}
+
+impl Reader {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            size: 0,
+            entries: Vec::new(),
+            exceeded: None,
+        }
+    }
+
+    /// Adds `data`, or the files it contains when it is a ZIP archive, their names prefixed with
+    /// `name`.
+    pub fn add(&mut self, name: &str, data: Vec<u8>) {
+        if is_archive(&data) {
+            self.add_archive(name, Cursor::new(data));
+        } else {
+            let size = data.len() as u64;
+
+            self.push(name.to_string(), Cursor::new(data), size);
+        }
+    }
+
+    /// Adds the file at `path` like [`Reader::add`], reading archives one entry at a time.
+    pub fn add_file(&mut self, name: &str, path: &Path) {
+        let file = std::fs::File::open(path).and_then(|mut file| {
+            let mut magic = [0; 4];
+            let read = file.read(&mut magic)?;
+
+            file.seek(SeekFrom::Start(0))?;
+            Ok((is_archive(&magic[..read]), file.metadata()?.len(), file))
+        });
+
+        match file {
+            Ok((true, _, file)) => self.add_archive(name, file),
+            Ok((false, size, file)) => self.push(name.to_string(), file, size),
+            Err(err) => self.push_error(name.to_string(), format!("Unreadable file: {err}")),
+        }
+    }
+
+    /// Adds `path`, or every file under it when it is a directory.
+    ///
+    /// Fails only when `path` itself cannot be read, files that cannot be are reported as entries.
+    pub fn add_path(&mut self, path: &Path) -> io::Result<()> {
+        if std::fs::metadata(path)?.is_dir() {
+            self.add_dir(path, path)
+        } else {
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+
+            self.add_file(&name, path);
+            Ok(())
+        }
+    }
+
+    /// The files read, unless they exceed the limits.
+    pub fn finish(self) -> Result<Vec<Entry>, Exceeded> {
+        match self.exceeded {
+            Some(exceeded) => Err(exceeded),
+            None => Ok(self.entries),
+        }
+    }
+
+    fn add_dir(&mut self, root: &Path, dir: &Path) -> io::Result<()> {
+        let mut paths = std::fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        paths.sort();
+
+        for path in paths {
+            let name = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .into_owned();
+
+            if self.exceeded.is_some() {
+                break;
+            }
+            if is_hidden(&name) {
+                continue;
+            }
+            if path.is_dir() {
+                self.add_dir(root, &path)?;
+            } else {
+                self.add_file(&name, &path);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_archive(&mut self, name: &str, reader: impl Read + Seek) {
+        let mut archive = match zip::ZipArchive::new(reader) {
+            Ok(archive) => archive,
+            Err(err) => {
+                return self.push_error(name.to_string(), format!("Invalid ZIP archive: {err}"));
+            }
+        };
+
+        for index in 0..archive.len() {
+            if self.exceeded.is_some() {
+                break;
+            }
+
+            let file = match archive.by_index(index) {
+                Ok(file) => file,
+                Err(err) => {
+                    self.push_error(
+                        format!("{name}#{index}"),
+                        format!("Unreadable archive entry: {err}"),
+                    );
+                    continue;
+                }
+            };
+
+            if file.is_dir() || is_hidden(file.name()) {
+                continue;
+            }
+
+            let name = format!("{name}/{}", file.name());
+            let size = file.size();
+
+            self.push(name, file, size);
+        }
+    }
+
+    /// Reads `size` bytes at most from `reader`, the size it claims to have.
+    fn push(&mut self, name: String, reader: impl Read, size: u64) {
+        if !self.count() {
+            return;
+        }
+
+        let data = read_limited(reader, size);
+
+        if let Ok(data) = &data {
+            self.size += data.len() as u64;
+            if self.size > self.limits.max_total_size {
+                self.exceeded = Some(Exceeded::TotalSize(self.limits.max_total_size));
+                return;
+            }
+        }
+        self.entries.push(Entry { name, data });
+    }
+
+    fn push_error(&mut self, name: String, error: String) {
+        if self.count() {
+            self.entries.push(Entry {
+                name,
+                data: Err(error),
+            });
+        }
+    }
+
+    /// Whether one more file may be read.
+    fn count(&mut self) -> bool {
+        if self.exceeded.is_none() && self.entries.len() >= self.limits.max_files {
+            self.exceeded = Some(Exceeded::Files(self.limits.max_files));
+        }
+        self.exceeded.is_none()
+    }
+}
+
+fn is_archive(data: &[u8]) -> bool {
+    data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06")
+}
+
+fn read_limited(reader: impl Read, size: u64) -> Result<Vec<u8>, String> {
+    if size > MAX_IMAGE_SIZE {
+        return Err(too_large());
+    }
+
+    let mut data = Vec::with_capacity(size as usize);
+
+    reader
+        .take(MAX_IMAGE_SIZE + 1)
+        .read_to_end(&mut data)
+        .map_err(|err| format!("Unreadable file: {err}"))?;
+
+    if data.len() as u64 > MAX_IMAGE_SIZE {
+        return Err(too_large());
+    }
+    Ok(data)
+}
+
+fn too_large() -> String {
+    format!("Larger than {} MiB", MAX_IMAGE_SIZE / 1024 / 1024)
+}
+
+/// Dot files and the metadata added by macOS to archives.
+fn is_hidden(name: &str) -> bool {
+    name.split(['/', '\\'])
+        .any(|component| component.starts_with('.') || component == "__MACOSX")
+}
+
+/// Imports the valid entries into `session`, reporting the invalid ones.
+#[instrument(skip_all)]
+pub async fn import(
+    session: sessions::Id,
+    uploader: Option<users::Id>,
+    entries: Vec<Entry>,
+    storage: &Storage,
+    db: &Pool,
+) -> Result<Report, Error> {
+    // Decoding images to validate them is too slow to run on the async executor.
+    let (mut files, valid) = tokio::task::spawn_blocking(|| validate_all(entries))
+        .await
+        .map_err(|_| Error::Validation)?;
+    let mut written = Vec::new();
+    let warnings = match store(
+        session,
+        uploader,
+        &valid,
+        &mut files,
+        &mut written,
+        storage,
+        db,
+    )
+    .await
+    {
+        Ok(warnings) => warnings,
+        Err(err) => {
+            for id in written {
+                let _ = storage.remove(id).await;
+            }
+            return Err(err);
+        }
+    };
+
+    Ok(Report {
+        session: session.0,
+        imported: valid.len(),
+        rejected: files.len() - valid.len(),
+        files,
+        warnings,
+    })
+}
+
+/// A valid file, with the index of its report.
+struct Valid {
+    index: usize,
+    data: Vec<u8>,
+    phash: u64,
+}
+
+fn validate_all(entries: Vec<Entry>) -> (Vec<FileReport>, Vec<Valid>) {
+    let mut files = Vec::new();
+    let mut valid = Vec::new();
+
+    for entry in entries {
+        let error = match entry
+            .data
+            .and_then(|data| validate(&data).map(|phash| (data, phash)))
+        {
+            Ok((data, phash)) => {
+                valid.push(Valid {
+                    index: files.len(),
+                    data,
+                    phash,
+                });
+                None
+            }
+            Err(err) => {
+                warn!(file = %entry.name, "Rejected image: {err}");
+                Some(err)
+            }
+        };
+
+        files.push(FileReport {
+            name: entry.name,
+            image: None,
+            error,
+        });
+    }
+
+    (files, valid)
+}
+
+/// Checks that `data` is a whole image in a supported format by decoding it, and returns its
+/// perceptual hash.
+pub fn validate(data: &[u8]) -> Result<u64, String> {
+    if data.len() as u64 > MAX_IMAGE_SIZE {
+        return Err(too_large());
+    }
+    if Format::sniff(data).is_none() {
+        return Err(String::from("Not a JPEG, PNG, GIF or WebP image"));
+    }
+
+    let image = image::load_from_memory(data)
+        .map_err(|err| format!("Corrupt or truncated image: {err}"))?;
+
+    if image.width() == 0 || image.height() == 0 {
+        return Err(String::from("Empty image"));
+    }
+    Ok(phash::dhash_image(&image))
+}
+
+/// Stores the images in a transaction, `written` keeps track of the files to remove on failure.
+async fn store(
+    session: sessions::Id,
+    uploader: Option<users::Id>,
+    valid: &[Valid],
+    files: &mut [FileReport],
+    written: &mut Vec<images::Id>,
+    storage: &Storage,
+    db: &Pool,
+) -> Result<Vec<NearDuplicate>, Error> {
+    let mut trans = db.begin().await.map_err(context("begin", "transaction"))?;
+    let mut ids = Vec::new();
+
+    // Concurrent changes to the session wait for the images to be stored.
+    let state = sessions::lock(session, &mut trans)
+        .await?
+        .state(OffsetDateTime::now_utc());
+
+    if state != State::Pending {
+        return Err(Error::Started(state));
+    }
+
+    for file in valid {
+        let id = images::create_by(uploader, &mut trans).await?;
+
+        storage
+            .write(id, &file.data)
+            .await
+            .map_err(Error::Storage)?;
+        written.push(id);
+        images_associations::create(id, session, &mut trans).await?;
+        images::set_phash(id, file.phash, &mut trans).await?;
+        files[file.index].image = Some(id.0);
+        ids.push(id);
+    }
+
+    let warnings = images::near_duplicates(
+        Some(session),
+        Some(&ids),
+        images::NEAR_DUPLICATE_DISTANCE,
+        &mut trans,
+    )
+    .await?;
+
+    trans
+        .commit()
+        .await
+        .map_err(context("commit", "transaction"))?;
+    Ok(warnings
+        .into_iter()
+        .map(|duplicate| NearDuplicate {
+            images: [duplicate.images.0 .0, duplicate.images.1 .0],
+            distance: duplicate.distance,
+        })
+        .collect())
+}
+
+impl From<result::Error> for Error {
+    fn from(err: result::Error) -> Self {
+        Error::Db(err)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Db(err) => write!(f, "{err}"),
+            Error::Started(state) => write!(
+                f,
+                "images can only be imported before the first phase, the session is in {}",
+                state.as_str()
+            ),
+            Error::Storage(err) => write!(f, "failed to store image: {err}"),
+            Error::Validation => write!(f, "failed to validate images"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Display for Exceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exceeded::Files(max) => write!(f, "more than {max} files"),
+            Exceeded::TotalSize(max) => write!(f, "more than {max} bytes once decompressed"),
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! Image files, stored by `image-host` and the binaries importing them, served by `image-host`.
+//!
+//! Each image is stored in a file named after its id in the `images` table, its encodings in files
+//! named after the id with the extension of their format.
+
+pub mod encoding;
+pub mod format;
+pub mod import;
+pub mod phash;
+
+use std::{
+    collections::HashMap,
+    io,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use tokio::{io::AsyncWriteExt, sync::Semaphore};
+
+use db::images;
+
+use encoding::Encoding;
+
+/// Encodings generated at the same time, each one keeps a core busy for up to a few seconds.
+static ENCODERS: LazyLock<Semaphore> = LazyLock::new(|| {
+    Semaphore::new(std::thread::available_parallelism().map_or(1, NonZeroUsize::get))
+});
+
+/// Encodings being generated, by path, so that requests for the same one wait for it instead of
+/// generating it again.
+type InFlight = Mutex<HashMap<PathBuf, Arc<tokio::sync::Mutex<()>>>>;
+
+static IN_FLIGHT: LazyLock<InFlight> = LazyLock::new(Default::default);
+
+#[derive(Debug, Clone)]
+pub struct Storage(PathBuf);
+
+impl Storage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self(root.into())
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn path(&self, id: images::Id) -> PathBuf {
+        self.0.join(id.0.to_string())
+    }
+
+    /// Path written to before the image is complete, see [`Storage::write`].
+    pub fn partial_path(&self, id: images::Id) -> PathBuf {
+        self.path(id).with_extension("part")
+    }
+
+    /// Writes to a temporary file renamed once complete, so that an interrupted write never leaves
+    /// a truncated image behind.
+    pub async fn write(&self, id: images::Id, data: &[u8]) -> io::Result<()> {
+        write(&self.path(id), &self.partial_path(id), data).await
+    }
+
+    pub fn encoded_path(&self, id: images::Id, encoding: Encoding) -> PathBuf {
+        self.path(id).with_extension(encoding.extension())
+    }
+
+    /// Path and size of the `encoding` of image `id`, encoded and stored first if needed, `None`
+    /// when the image cannot be encoded or the encoding is not smaller than the original.
+    ///
+    /// An empty file is stored when the image cannot be encoded so that it is only tried once.
+    ///
+    /// Each encoding is only generated once at a time, and a few of them at most are generated at
+    /// the same time, the others wait for their turn.
+    pub async fn encoded(
+        &self,
+        id: images::Id,
+        encoding: Encoding,
+    ) -> io::Result<Option<(PathBuf, u64)>> {
+        let original_len = tokio::fs::metadata(self.path(id)).await?.len();
+        let path = self.encoded_path(id, encoding);
+        let len = match stored_len(&path).await? {
+            Some(len) => len,
+            None => self.encode(id, encoding).await?,
+        };
+
+        Ok((len > 0 && len < original_len).then_some((path, len)))
+    }
+
+    /// Same as [`Storage::encoded`] without generating the encoding, `None` as well when it was
+    /// not generated yet.
+    pub async fn stored_encoding(
+        &self,
+        id: images::Id,
+        encoding: Encoding,
+    ) -> io::Result<Option<(PathBuf, u64)>> {
+        let original_len = tokio::fs::metadata(self.path(id)).await?.len();
+        let path = self.encoded_path(id, encoding);
+
+        Ok(stored_len(&path)
+            .await?
+            .filter(|len| *len > 0 && *len < original_len)
+            .map(|len| (path, len)))
+    }
+
+    /// Generates and stores an encoding, returns its size.
+    async fn encode(&self, id: images::Id, encoding: Encoding) -> io::Result<u64> {
+        let path = self.encoded_path(id, encoding);
+        let lock = IN_FLIGHT
+            .lock()
+            .unwrap()
+            .entry(path.clone())
+            .or_default()
+            .clone();
+        let result = async {
+            let _generating = lock.lock().await;
+
+            // Generated by another request while this one waited.
+            if let Some(len) = stored_len(&path).await? {
+                return Ok(len);
+            }
+
+            let _permit = ENCODERS.acquire().await.map_err(io::Error::other)?;
+            let data = tokio::fs::read(self.path(id)).await?;
+            let encoded = tokio::task::spawn_blocking(move || encoding.encode(&data))
+                .await
+                .map_err(io::Error::other)?
+                .unwrap_or_default();
+            let partial = path.with_extension(format!("{}.part", encoding.extension()));
+
+            write(&path, &partial, &encoded).await?;
+            Ok(encoded.len() as u64)
+        }
+        .await;
+
+        let mut in_flight = IN_FLIGHT.lock().unwrap();
+
+        // Only the map and this request still hold the lock, no other one is waiting for it.
+        if Arc::strong_count(&lock) == 2 {
+            in_flight.remove(&path);
+        }
+        result
+    }
+
+    pub async fn remove(&self, id: images::Id) -> io::Result<()> {
+        tokio::fs::remove_file(self.path(id)).await
+    }
+}
+
+/// Size of the file at `path`, `None` when there is none.
+async fn stored_len(path: &Path) -> io::Result<Option<u64>> {
+    match tokio::fs::metadata(path).await {
+        Ok(metadata) => Ok(Some(metadata.len())),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+async fn write(path: &Path, partial: &Path, data: &[u8]) -> io::Result<()> {
+    let result = async {
+        let mut file = tokio::fs::File::create(partial).await?;
+
+        file.write_all(data).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(partial, path).await
+    }
+    .await;
+
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(partial).await;
+    }
+
+    result
+}
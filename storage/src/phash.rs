@@ -0,0 +1,35 @@
+//! Perceptual hashes, close for images that look alike even when resized or re-encoded.
+//!
+//! The hash is a dHash: the image is reduced to 9×8 gray pixels and each bit tells whether a pixel
+//! is brighter than its right neighbour. Near-duplicates are images whose hashes differ by few
+//! bits, see `db::images::near_duplicates`.
+
+use image::{imageops::FilterType, DynamicImage};
+
+/// Hash of the image, `None` when it cannot be decoded.
+pub fn dhash(data: &[u8]) -> Option<u64> {
+    image::load_from_memory(data)
+        .ok()
+        .map(|image| dhash_image(&image))
+}
+
+/// Hash of an image already decoded.
+pub fn dhash_image(image: &DynamicImage) -> u64 {
+    let pixels = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0;
+
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = pixels.get_pixel(x, y)[0] > pixels.get_pixel(x + 1, y)[0];
+
+            hash = hash << 1 | u64::from(brighter);
+        }
+    }
+
+    hash
+}
+
+/// Number of bits that differ between two hashes, from 0 for identical images to 64.
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
@@ -0,0 +1,209 @@
+use std::io::Cursor;
+
+use image::{DynamicImage, ImageOutputFormat, RgbImage, RgbaImage};
+
+/// A gradient, which PNG compresses poorly and lossless WebP well.
+fn png(alpha: bool) -> Vec<u8> {
+    let image = if alpha {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 48, |x, y| {
+            image::Rgba([(x * 4) as u8, (y * 5) as u8, 128, (x + y) as u8])
+        }))
+    } else {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(64, 48, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 5) as u8, 128])
+        }))
+    };
+    let mut data = Cursor::new(Vec::new());
+
+    image.write_to(&mut data, ImageOutputFormat::Png).unwrap();
+    data.into_inner()
+}
+
+mod negotiate {
+    use storage::encoding::Encoding::{self, *};
+
+    #[test]
+    fn browsers() {
+        assert_eq!(
+            Encoding::negotiate("image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8"),
+            [Avif, Webp]
+        );
+        assert_eq!(Encoding::negotiate("image/webp,*/*"), [Webp]);
+        assert_eq!(
+            Encoding::negotiate("image/png,image/svg+xml,image/*;q=0.8,*/*;q=0.5"),
+            []
+        );
+    }
+
+    #[test]
+    fn quality() {
+        assert_eq!(
+            Encoding::negotiate("image/avif;q=0.5, IMAGE/WEBP ; q=0.9"),
+            [Webp, Avif]
+        );
+        assert_eq!(Encoding::negotiate("image/webp, image/avif"), [Avif, Webp]);
+        assert_eq!(Encoding::negotiate("image/avif;q=0,image/webp"), [Webp]);
+        assert_eq!(Encoding::negotiate("image/avif;q=high"), []);
+        assert_eq!(Encoding::negotiate("image/webp;q=0.2,image/webp"), [Webp]);
+    }
+}
+
+mod encode {
+    use std::io::Cursor;
+
+    use image::{DynamicImage, ImageFormat, ImageOutputFormat, RgbImage};
+
+    use storage::encoding::Encoding;
+
+    use super::png;
+
+    #[test]
+    fn webp() {
+        for alpha in [false, true] {
+            let original = png(alpha);
+            let encoded = Encoding::Webp.encode(&original).unwrap();
+            let decoded = image::load_from_memory_with_format(&encoded, ImageFormat::WebP)
+                .unwrap()
+                .to_rgba8();
+
+            assert_eq!(Encoding::sniff(&encoded), Some(Encoding::Webp));
+            assert_eq!(
+                decoded,
+                image::load_from_memory(&original).unwrap().to_rgba8()
+            );
+        }
+    }
+
+    #[test]
+    fn avif() {
+        for alpha in [false, true] {
+            let encoded = Encoding::Avif.encode(&png(alpha)).unwrap();
+
+            assert_eq!(Encoding::sniff(&encoded), Some(Encoding::Avif));
+        }
+    }
+
+    #[test]
+    fn jpeg() {
+        let mut jpeg = Cursor::new(Vec::new());
+
+        image::load_from_memory(&png(false))
+            .unwrap()
+            .write_to(&mut jpeg, ImageOutputFormat::Jpeg(90))
+            .unwrap();
+
+        assert!(Encoding::Avif.encode(jpeg.get_ref()).is_some());
+        assert_eq!(Encoding::Webp.encode(jpeg.get_ref()), None);
+    }
+
+    #[test]
+    fn unsupported() {
+        let mut gif = Cursor::new(Vec::new());
+
+        DynamicImage::ImageRgb8(RgbImage::new(8, 8))
+            .write_to(&mut gif, ImageOutputFormat::Gif)
+            .unwrap();
+
+        assert_eq!(Encoding::Avif.encode(gif.get_ref()), None);
+        assert_eq!(Encoding::Webp.encode(b"\x89PNG\r\n\x1a\ntruncated"), None);
+        assert_eq!(Encoding::sniff(&png(false)), None);
+    }
+}
+
+mod encoded {
+    use std::io;
+
+    use db::images;
+    use storage::{encoding::Encoding, Storage};
+
+    use super::png;
+
+    #[tokio::test]
+    async fn stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path());
+        let id = images::Id(1);
+
+        storage.write(id, &png(false)).await.unwrap();
+
+        let (path, len) = storage.encoded(id, Encoding::Webp).await.unwrap().unwrap();
+        let encoded = std::fs::read(&path).unwrap();
+
+        assert_eq!(path, storage.encoded_path(id, Encoding::Webp));
+        assert_eq!(Encoding::sniff(&encoded), Some(Encoding::Webp));
+        assert_eq!(len, encoded.len() as u64);
+
+        // The stored encoding is used from then on.
+        std::fs::write(&path, b"cached").unwrap();
+        assert_eq!(
+            storage.encoded(id, Encoding::Webp).await.unwrap(),
+            Some((path.clone(), 6))
+        );
+        assert_eq!(std::fs::read(&path).unwrap(), b"cached");
+    }
+
+    #[tokio::test]
+    async fn concurrent() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path());
+        let id = images::Id(1);
+
+        storage.write(id, &png(false)).await.unwrap();
+        assert_eq!(
+            storage.stored_encoding(id, Encoding::Webp).await.unwrap(),
+            None
+        );
+
+        let (first, second) = tokio::join!(
+            storage.encoded(id, Encoding::Webp),
+            storage.encoded(id, Encoding::Webp)
+        );
+        let first = first.unwrap().unwrap();
+
+        assert_eq!(second.unwrap().unwrap(), first);
+        assert_eq!(
+            storage.stored_encoding(id, Encoding::Webp).await.unwrap(),
+            Some(first)
+        );
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn not_smaller() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path());
+        let id = images::Id(1);
+
+        storage.write(id, &png(false)).await.unwrap();
+        std::fs::write(
+            storage.encoded_path(id, Encoding::Avif),
+            vec![0; png(false).len()],
+        )
+        .unwrap();
+
+        assert_eq!(storage.encoded(id, Encoding::Avif).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::new(dir.path());
+        let id = images::Id(1);
+
+        storage.write(id, b"not an image").await.unwrap();
+
+        assert_eq!(storage.encoded(id, Encoding::Avif).await.unwrap(), None);
+        assert_eq!(
+            std::fs::read(storage.encoded_path(id, Encoding::Avif)).unwrap(),
+            b""
+        );
+        assert_eq!(
+            storage
+                .encoded(images::Id(2), Encoding::Avif)
+                .await
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use std::io::Write;
+
+use std::path::Path;
+
+use storage::import::{Entry, Limits, Reader, MAX_IMAGE_SIZE};
+
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+const JPEG: &[u8] = b"\xff\xd8\xff\xe0\0\x10JFIF";
+
+fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+
+    for (name, data) in files {
+        if name.ends_with('/') {
+            writer.add_directory(*name, Default::default()).unwrap();
+        } else {
+            writer.start_file(*name, Default::default()).unwrap();
+            writer.write_all(data).unwrap();
+        }
+    }
+
+    writer.finish().unwrap().into_inner()
+}
+
+fn expand(name: &str, data: Vec<u8>) -> Vec<Entry> {
+    let mut reader = Reader::new(Limits::default());
+
+    reader.add(name, data);
+    reader.finish().unwrap()
+}
+
+fn read_path(path: &Path) -> std::io::Result<Vec<Entry>> {
+    let mut reader = Reader::new(Limits::default());
+
+    reader.add_path(path)?;
+    Ok(reader.finish().unwrap())
+}
+
+fn names(entries: &[Entry]) -> Vec<&str> {
+    entries.iter().map(|entry| entry.name.as_str()).collect()
+}
+
+mod sniff {
+    use storage::format::Format;
+
+    use super::{JPEG, PNG};
+
+    #[test]
+    fn supported() {
+        assert_eq!(Format::sniff(PNG), Some(Format::Png));
+        assert_eq!(Format::sniff(JPEG), Some(Format::Jpeg));
+        assert_eq!(Format::sniff(b"GIF89a\x01\0"), Some(Format::Gif));
+        assert_eq!(Format::sniff(b"RIFF\0\0\0\0WEBPVP8 "), Some(Format::Webp));
+    }
+
+    #[test]
+    fn unsupported() {
+        assert_eq!(Format::sniff(b"%PDF-1.7"), None);
+        assert_eq!(Format::sniff(b""), None);
+    }
+}
+
+mod validate {
+    use std::io::Cursor;
+
+    use image::{DynamicImage, ImageOutputFormat, Rgb, RgbImage};
+    use storage::import::validate;
+
+    use super::PNG;
+
+    fn encode(format: ImageOutputFormat) -> Vec<u8> {
+        let image = RgbImage::from_fn(32, 24, |x, y| Rgb([x as u8 * 8, y as u8 * 10, 128]));
+        let mut data = Vec::new();
+
+        DynamicImage::ImageRgb8(image)
+            .write_to(&mut Cursor::new(&mut data), format)
+            .unwrap();
+        data
+    }
+
+    fn formats() -> [ImageOutputFormat; 3] {
+        [
+            ImageOutputFormat::Png,
+            ImageOutputFormat::Jpeg(90),
+            ImageOutputFormat::Gif,
+        ]
+    }
+
+    #[test]
+    fn complete() {
+        for format in formats() {
+            assert!(validate(&encode(format.clone())).is_ok(), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        for format in formats() {
+            let data = encode(format.clone());
+
+            assert!(validate(&data[..data.len() / 2]).is_err(), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn header_only() {
+        assert!(validate(PNG).is_err());
+        assert!(validate(b"%PDF-1.7").is_err());
+    }
+}
+
+mod expand {
+    use super::{expand, names, zip, PNG};
+
+    #[test]
+    fn plain_file() {
+        let entries = expand("cat.png", PNG.to_vec());
+
+        assert_eq!(names(&entries), ["cat.png"]);
+        assert_eq!(entries[0].data.as_deref(), Ok(PNG));
+    }
+
+    #[test]
+    fn archive() {
+        let archive = zip(&[
+            ("cats/", b""),
+            ("cats/cat.png", PNG),
+            ("cats/.DS_Store", b"junk"),
+            ("__MACOSX/cats/._cat.png", b"junk"),
+            ("notes.txt", b"notes"),
+        ]);
+
+        let entries = expand("upload.zip", archive);
+
+        assert_eq!(
+            names(&entries),
+            ["upload.zip/cats/cat.png", "upload.zip/notes.txt"]
+        );
+        assert_eq!(entries[0].data.as_deref(), Ok(PNG));
+    }
+
+    #[test]
+    fn invalid_archive() {
+        let entries = expand("broken.zip", b"PK\x03\x04broken".to_vec());
+
+        assert_eq!(names(&entries), ["broken.zip"]);
+        assert!(entries[0].data.is_err());
+    }
+}
+
+mod read_path {
+    use super::{names, read_path, zip, JPEG, MAX_IMAGE_SIZE, PNG};
+
+    #[test]
+    fn directory() {
+        let dir = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir(dir.path().join("nested")).unwrap();
+        std::fs::create_dir(dir.path().join(".git")).unwrap();
+        std::fs::write(dir.path().join("a.png"), PNG).unwrap();
+        std::fs::write(dir.path().join("nested/b.jpg"), JPEG).unwrap();
+        std::fs::write(dir.path().join(".git/HEAD"), b"ref").unwrap();
+        std::fs::write(dir.path().join("c.zip"), zip(&[("d.png", PNG)])).unwrap();
+
+        let entries = read_path(dir.path()).unwrap();
+
+        assert_eq!(names(&entries), ["a.png", "c.zip/d.png", "nested/b.jpg"]);
+        assert!(entries.iter().all(|entry| entry.data.is_ok()));
+    }
+
+    #[test]
+    fn archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("images.zip");
+
+        std::fs::write(&path, zip(&[("a.png", PNG), ("b.jpg", JPEG)])).unwrap();
+
+        let entries = read_path(&path).unwrap();
+
+        assert_eq!(names(&entries), ["images.zip/a.png", "images.zip/b.jpg"]);
+    }
+
+    #[test]
+    fn too_large() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("huge.png");
+
+        std::fs::File::create(&path)
+            .unwrap()
+            .set_len(MAX_IMAGE_SIZE + 1)
+            .unwrap();
+
+        let entries = read_path(&path).unwrap();
+
+        assert_eq!(names(&entries), ["huge.png"]);
+        assert!(entries[0].data.is_err());
+    }
+
+    #[test]
+    fn missing() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert!(read_path(&dir.path().join("missing")).is_err());
+    }
+}
+
+mod limits {
+    use storage::import::{Exceeded, Limits, Reader};
+
+    use super::{names, zip, PNG};
+
+    #[test]
+    fn files() {
+        let limits = Limits {
+            max_files: 2,
+            ..Limits::default()
+        };
+        let mut reader = Reader::new(limits);
+
+        reader.add("a.png", PNG.to_vec());
+        reader.add("b.zip", zip(&[("c.png", PNG)]));
+
+        assert_eq!(names(&reader.finish().unwrap()), ["a.png", "b.zip/c.png"]);
+
+        let mut reader = Reader::new(limits);
+
+        reader.add("a.png", PNG.to_vec());
+        reader.add("b.zip", zip(&[("c.png", PNG), ("d.png", PNG)]));
+
+        assert_eq!(reader.finish().err(), Some(Exceeded::Files(2)));
+    }
+
+    #[test]
+    fn total_size() {
+        let limits = Limits {
+            max_total_size: 3 * PNG.len() as u64,
+            ..Limits::default()
+        };
+        let mut reader = Reader::new(limits);
+
+        reader.add("a.png", PNG.to_vec());
+        reader.add("b.zip", zip(&[("c.png", PNG), ("d.png", PNG)]));
+        assert!(reader.finish().is_ok());
+
+        let mut reader = Reader::new(limits);
+
+        reader.add("a.png", PNG.to_vec());
+        reader.add(
+            "b.zip",
+            zip(&[("c.png", PNG), ("d.png", PNG), ("e.png", PNG)]),
+        );
+
+        assert_eq!(
+            reader.finish().err(),
+            Some(Exceeded::TotalSize(3 * PNG.len() as u64))
+        );
+    }
+
+    #[test]
+    fn archive_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("upload");
+
+        std::fs::write(&path, zip(&[("a.png", PNG), ("b.png", PNG)])).unwrap();
+
+        let mut reader = Reader::new(Limits {
+            max_files: 1,
+            ..Limits::default()
+        });
+
+        reader.add_file("upload.zip", &path);
+
+        assert_eq!(reader.finish().err(), Some(Exceeded::Files(1)));
+
+        let mut reader = Reader::new(Limits::default());
+
+        reader.add_file("upload.zip", &path);
+
+        assert_eq!(
+            names(&reader.finish().unwrap()),
+            ["upload.zip/a.png", "upload.zip/b.png"]
+        );
+    }
+}
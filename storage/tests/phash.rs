@@ -0,0 +1,48 @@
+use std::io::Cursor;
+
+use image::{imageops::FilterType, DynamicImage, ImageOutputFormat, RgbImage};
+
+use storage::phash::{dhash, distance};
+
+/// Diagonal stripes of varying brightness.
+fn photo(width: u32, height: u32, flipped: bool) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+        let x = if flipped { width - 1 - x } else { x };
+        let value = ((x * 255 / width) + (y * 128 / height)) as u8;
+
+        image::Rgb([value, value / 2, 255 - value])
+    }))
+}
+
+fn encode(image: &DynamicImage, format: ImageOutputFormat) -> Vec<u8> {
+    let mut data = Cursor::new(Vec::new());
+
+    image.write_to(&mut data, format).unwrap();
+    data.into_inner()
+}
+
+#[test]
+fn resized_and_reencoded() {
+    let original = photo(256, 192, false);
+    let png = dhash(&encode(&original, ImageOutputFormat::Png)).unwrap();
+    let jpeg = dhash(&encode(
+        &original.resize_exact(128, 96, FilterType::Lanczos3),
+        ImageOutputFormat::Jpeg(60),
+    ))
+    .unwrap();
+
+    assert!(distance(png, jpeg) <= 4);
+}
+
+#[test]
+fn different() {
+    let a = dhash(&encode(&photo(256, 192, false), ImageOutputFormat::Png)).unwrap();
+    let b = dhash(&encode(&photo(256, 192, true), ImageOutputFormat::Png)).unwrap();
+
+    assert!(distance(a, b) > 32);
+}
+
+#[test]
+fn undecodable() {
+    assert_eq!(dhash(b"\x89PNG\r\n\x1a\ntruncated"), None);
+}